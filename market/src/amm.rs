@@ -0,0 +1,110 @@
+//! 各市场报价的统一接口。每个DEX在自己的模块里实现Amm并通过dex::DEXES注册，路由和pool_info不区分市场
+
+use anyhow::Result;
+use solana_program::{program_pack::Pack, pubkey::Pubkey};
+use solana_sdk::account::Account;
+use spl_token::state::Account as TokenAccount;
+use std::collections::HashMap;
+use std::fmt;
+
+/// 报价用到的链上账户，key是地址的base58字符串
+pub type AccountMap = HashMap<String, Account>;
+
+/// quote和base对应池子文件里的顺序
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SwapDirection {
+    QuoteToBase,
+    BaseToQuote,
+}
+
+/// 手续费从哪一边扣
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FeeSide {
+    Source,
+    Destination,
+}
+
+/// 一次报价的结果，数量都是链上最小单位
+#[derive(Clone, Debug, PartialEq)]
+pub struct AmmQuote {
+    pub amount_in: u64,
+    pub amount_out: u64,
+    /// 按方向排列的储备，订单簿是对应一侧的挂单深度
+    pub source_reserve: u64,
+    pub destination_reserve: u64,
+    /// 名义费率
+    pub fee_rate: f64,
+    pub fee_side: FeeSide,
+    /// 链上实际收取的手续费，包含protocol_fee
+    pub fee: u64,
+    /// 归协议的部分(owner fee、admin fee、pnl)
+    pub protocol_fee: u64,
+    pub amp: Option<u64>,
+    /// 组装这一跳交易需要、池子文件里没有的账户
+    pub data: HashMap<String, String>,
+}
+
+pub trait Amm: Send + Sync {
+    /// 池子或订单簿市场的账户
+    fn key(&self) -> Pubkey;
+
+    /// 报价需要的账户。有些账户要从池子账户里读出来，update之后可能变多
+    fn accounts_needed(&self) -> Vec<Pubkey>;
+
+    /// 用最新的账户数据更新状态，缺少账户或解析失败时报错
+    fn update(&mut self, accounts: &AccountMap) -> Result<()>;
+
+    /// 链上状态不允许swap时返回原因
+    fn unavailable_reason(&self) -> Option<String> {
+        None
+    }
+
+    /// (quote, base)储备
+    fn reserves(&self) -> (u64, u64);
+
+    /// 深度不够时产出为0，不报错
+    fn quote(&self, amount_in: u64, direction: SwapDirection) -> Result<AmmQuote>;
+
+    /// pool_info里展示的池子信息
+    fn pool_data(&self) -> HashMap<String, String> {
+        HashMap::new()
+    }
+}
+
+/// 账户相关的错误，调用方可以downcast出来区分缺少账户和数据错误
+#[derive(Clone, Debug, PartialEq)]
+pub enum AmmError {
+    AccountNotFound(Pubkey),
+    AccountDecode(Pubkey),
+}
+
+impl fmt::Display for AmmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AmmError::AccountNotFound(key) => write!(f, "account {} not found", key),
+            AmmError::AccountDecode(key) => write!(f, "decode account {} failed", key),
+        }
+    }
+}
+
+impl std::error::Error for AmmError {}
+
+pub fn get_account<'a>(accounts: &'a AccountMap, key: &Pubkey) -> Result<&'a Account> {
+    accounts.get(&key.to_string()).ok_or_else(|| AmmError::AccountNotFound(*key).into())
+}
+
+/// token账户余额
+pub fn load_token_amount(accounts: &AccountMap, key: &Pubkey) -> Result<u64> {
+    let account = get_account(accounts, key)?;
+    let token_account = TokenAccount::unpack(&account.data).map_err(|_| AmmError::AccountDecode(*key))?;
+    Ok(token_account.amount)
+}
+
+/// 分数形式的费率，分母为0时按0处理
+pub fn fee_rate(numerator: u64, denominator: u64) -> f64 {
+    if denominator == 0 {
+        0.0
+    } else {
+        numerator as f64 / denominator as f64
+    }
+}
@@ -0,0 +1,102 @@
+//! 各DEX的入口。新增一个DEX只需要在自己的模块里实现Amm、加载池子和组装swap指令，再加到DEXES里
+
+use crate::amm::Amm;
+use crate::market::MarketPool;
+use crate::pool::{PoolInfo, RawPool};
+use crate::{orca, orca_whirlpool, raydium, saber, serum};
+use anyhow::{anyhow, Result};
+use solana_program::{instruction::Instruction, pubkey::Pubkey};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// 创建报价adapter需要的池子信息，来自池子文件
+pub struct AmmParams<'a> {
+    pub pool_key: Pubkey,
+    /// MarketType里的program_id
+    pub program_id: &'a str,
+    pub quote_mint: Pubkey,
+    pub quote_value_key: Pubkey,
+    pub base_value_key: Pubkey,
+    pub data: &'a HashMap<String, String>,
+}
+
+impl AmmParams<'_> {
+    pub fn data_key(&self, key: &str) -> Option<Pubkey> {
+        self.data.get(key).and_then(|x| Pubkey::from_str(x).ok())
+    }
+}
+
+/// 组装一跳swap指令需要的账户，data是报价时这一跳的池子数据
+pub struct SwapAccounts<'a> {
+    pub program_id: Pubkey,
+    pub pool_key: Pubkey,
+    pub wallet: Pubkey,
+    /// 用户卖出和买入token的账户
+    pub source: Pubkey,
+    pub destination: Pubkey,
+    pub data: &'a HashMap<String, String>,
+}
+
+impl SwapAccounts<'_> {
+    pub fn data_key(&self, key: &str) -> Result<Pubkey> {
+        let value = self.data.get(key).ok_or_else(|| anyhow!("{} missing in route {}", key, self.pool_key))?;
+        Ok(Pubkey::from_str(value)?)
+    }
+}
+
+/// 一跳swap指令的数量参数
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RouteAmount {
+    ExactIn { amount_in: u64, minimum_amount_out: u64 },
+    ExactOut { max_amount_in: u64, amount_out: u64 },
+}
+
+impl RouteAmount {
+    /// 指定的数量和另一边的限制，ExactIn是(输入, 最少产出)，ExactOut是(产出, 最多输入)
+    pub fn values(&self) -> (u64, u64) {
+        match *self {
+            RouteAmount::ExactIn { amount_in, minimum_amount_out } => (amount_in, minimum_amount_out),
+            RouteAmount::ExactOut { max_amount_in, amount_out } => (amount_out, max_amount_in),
+        }
+    }
+
+    pub fn exact_in(&self, market: &str) -> Result<(u64, u64)> {
+        match *self {
+            RouteAmount::ExactIn { amount_in, minimum_amount_out } => Ok((amount_in, minimum_amount_out)),
+            RouteAmount::ExactOut { .. } => Err(anyhow!("market {} does not support exact out", market)),
+        }
+    }
+}
+
+pub struct Dex {
+    /// 接口参数和路由图里用的小写名称
+    pub id: &'static str,
+    /// MarketType和报价路径里的名称
+    pub name: &'static str,
+    /// 路由用的池子
+    pub load_market_pools: fn() -> Result<Vec<MarketPool>>,
+    /// pool_info用的池子，没有流动性池子的市场为None
+    pub load_pools: Option<fn() -> Result<Vec<PoolInfo>>>,
+    /// pool_list用的池子
+    pub load_raw_pools: Option<fn() -> Result<Vec<RawPool>>>,
+    /// 以上加载用到的池子文件，修改后重新加载
+    pub pool_files: &'static [&'static str],
+    pub new_amm: fn(&AmmParams) -> Option<Box<dyn Amm>>,
+    /// 没有对应swap指令的市场为None，这类路径不能组装成交易
+    pub swap_instruction: Option<fn(&SwapAccounts, RouteAmount) -> Result<Instruction>>,
+    /// 链上支持按指定输出swap
+    pub exact_out: bool,
+    /// 池子有储备，能按两个方向的边际价格算中间价
+    pub mid_price: bool,
+}
+
+pub const DEXES: [&Dex; 5] = [&raydium::DEX, &orca::DEX, &saber::DEX, &serum::DEX, &orca_whirlpool::DEX];
+
+/// 按id或name查找
+pub fn find(market: &str) -> Option<&'static Dex> {
+    DEXES.iter().copied().find(|x| x.id == market || x.name == market)
+}
+
+pub fn ids() -> Vec<&'static str> {
+    DEXES.iter().map(|x| x.id).collect()
+}
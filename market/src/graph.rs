@@ -0,0 +1,183 @@
+use std::collections::{HashMap, HashSet};
+use solana_program::pubkey::Pubkey;
+use crate::market::{MarketPool, MarketSwap};
+
+//跨市场的token图，节点是mint，边是某个池子的一个兑换方向。
+//全部市场的池子只建一次图，查询时按市场过滤边
+#[derive(Debug, Default)]
+pub struct PoolGraph {
+    edges: HashMap<Pubkey, Vec<GraphEdge>>,
+}
+
+#[derive(Debug)]
+struct GraphEdge {
+    //池子所属市场，小写
+    market: String,
+    pool: MarketPool,
+}
+
+impl GraphEdge {
+    fn in_markets(&self, markets: &[&str]) -> bool {
+        markets.contains(&self.market.as_str())
+    }
+}
+
+impl PoolGraph {
+    pub fn new() -> Self {
+        PoolGraph {
+            edges: HashMap::new(),
+        }
+    }
+
+    pub fn add_pools(&mut self, market: &str, pools: Vec<MarketPool>) {
+        for pool in pools {
+            self.edges.entry(*pool.source_mint()).or_default().push(GraphEdge {
+                market: market.to_string(),
+                pool,
+            });
+        }
+    }
+
+    fn edges(&self, mint: &Pubkey) -> &[GraphEdge] {
+        self.edges.get(mint).map_or(&[], |x| x.as_slice())
+    }
+
+    fn degree(&self, mint: &Pubkey, markets: &[&str]) -> usize {
+        self.edges(mint).iter().filter(|x| x.in_markets(markets)).count()
+    }
+
+    //查找from到to之间不超过max_hops跳的路径，每个跳数各自最多取limit条，直连池子多的交易对也会带上多跳路径。
+    //同一跳数内优先经过池子多的token，它们通常流动性更好。候选路径由调用方按报价结果排序。
+    //markets是参与路由的市场，小写
+    pub fn find_swaps(&self, from: &Pubkey, to: &Pubkey, max_hops: usize, limit: usize, markets: &[&str]) -> Vec<MarketSwap> {
+        let mut res = vec![];
+        for hops in 1..=max_hops {
+            let mut found = vec![];
+            let mut path = vec![];
+            let mut visited = HashSet::new();
+            visited.insert(*from);
+            self.search(from, to, hops, limit, markets, &mut visited, &mut path, &mut found);
+            res.extend(found);
+        }
+        res
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn search<'a>(&'a self,
+                  current: &Pubkey,
+                  to: &Pubkey,
+                  remaining: usize,
+                  limit: usize,
+                  markets: &[&str],
+                  visited: &mut HashSet<Pubkey>,
+                  path: &mut Vec<&'a MarketPool>,
+                  res: &mut Vec<MarketSwap>) {
+        if remaining == 1 {
+            let edges = self.edges(current).iter()
+                .filter(|x| x.in_markets(markets))
+                .map(|x| &x.pool)
+                .filter(|x| x.destination_mint().eq(to));
+            for edge in edges {
+                if res.len() >= limit {
+                    return;
+                }
+                let mut step: Vec<MarketPool> = path.iter().map(|x| (*x).clone()).collect();
+                step.push(edge.clone());
+                res.push(MarketSwap {
+                    step,
+                });
+            }
+            return;
+        }
+
+        let mut next: Vec<&MarketPool> = self.edges(current).iter()
+            .filter(|x| x.in_markets(markets))
+            .map(|x| &x.pool)
+            .filter(|x| {
+                let mint = x.destination_mint();
+                !mint.eq(to) && !visited.contains(mint)
+            })
+            .collect();
+        next.sort_by_key(|x| std::cmp::Reverse(self.degree(x.destination_mint(), markets)));
+
+        for edge in next {
+            if res.len() >= limit {
+                return;
+            }
+            let mint = *edge.destination_mint();
+            visited.insert(mint);
+            path.push(edge);
+            self.search(&mint, to, remaining - 1, limit, markets, visited, path, res);
+            path.pop();
+            visited.remove(&mint);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::market::MarketType;
+
+    //mint_a和mint_b之间的池子，两个方向各一条边
+    fn pool(mint_a: Pubkey, mint_b: Pubkey) -> Vec<MarketPool> {
+        let pool_key = Pubkey::new_unique();
+        [true, false].iter().map(|is_quote_to_base| MarketPool {
+            market_type: MarketType::Orca("Orca".to_string(), String::new()),
+            pool_key,
+            quote_mint_key: mint_a,
+            base_mint_key: mint_b,
+            quote_value_key: Pubkey::new_unique(),
+            base_value_key: Pubkey::new_unique(),
+            is_quote_to_base: *is_quote_to_base,
+            amp: None,
+            data: HashMap::new(),
+        }).collect()
+    }
+
+    #[test]
+    fn test_routes_through_wsol() {
+        let usdc = Pubkey::new_unique();
+        let wsol = Pubkey::new_unique();
+        let ray = Pubkey::new_unique();
+        let mut graph = PoolGraph::new();
+        graph.add_pools("orca", pool(usdc, wsol));
+        graph.add_pools("orca", pool(wsol, ray));
+
+        let swaps = graph.find_swaps(&usdc, &ray, 3, 10, &["orca"]);
+        assert_eq!(swaps.len(), 1);
+        assert_eq!(swaps[0].step.len(), 2);
+        assert_eq!(*swaps[0].step[0].destination_mint(), wsol);
+        assert_eq!(*swaps[0].step[1].source_mint(), wsol);
+    }
+
+    #[test]
+    fn test_direct_pools_do_not_crowd_out_multi_hop() {
+        let from = Pubkey::new_unique();
+        let to = Pubkey::new_unique();
+        let middle = Pubkey::new_unique();
+        let mut graph = PoolGraph::new();
+        for _ in 0..5 {
+            graph.add_pools("orca", pool(from, to));
+        }
+        graph.add_pools("orca", pool(from, middle));
+        graph.add_pools("orca", pool(middle, to));
+
+        let swaps = graph.find_swaps(&from, &to, 2, 3, &["orca"]);
+        assert_eq!(swaps.iter().filter(|x| x.step.len() == 1).count(), 3);
+        assert_eq!(swaps.iter().filter(|x| x.step.len() == 2).count(), 1);
+    }
+
+    #[test]
+    fn test_filter_by_market() {
+        let from = Pubkey::new_unique();
+        let to = Pubkey::new_unique();
+        let mut graph = PoolGraph::new();
+        graph.add_pools("orca", pool(from, to));
+        graph.add_pools("saber", pool(from, to));
+
+        assert_eq!(graph.find_swaps(&from, &to, 1, 10, &["orca", "saber"]).len(), 2);
+        assert_eq!(graph.find_swaps(&from, &to, 1, 10, &["saber"]).len(), 1);
+        assert!(graph.find_swaps(&from, &to, 1, 10, &["raydium"]).is_empty());
+    }
+}
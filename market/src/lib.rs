@@ -8,9 +8,15 @@ extern crate anyhow;
 extern crate spl_token;
 extern crate solana_sdk;
 extern crate uint;
+extern crate spl_token_swap;
 
 pub mod raydium;
 pub mod market;
 pub mod orca;
+pub mod orca_whirlpool;
 pub mod saber;
-pub mod pool;
\ No newline at end of file
+pub mod serum;
+pub mod pool;
+pub mod graph;
+pub mod amm;
+pub mod dex;
\ No newline at end of file
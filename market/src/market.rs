@@ -2,7 +2,6 @@
 use serde::{Serialize, Deserialize};
 use std::collections::HashMap;
 use solana_program::pubkey::Pubkey;
-use anyhow::Result;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum MarketType {
@@ -11,6 +10,7 @@ pub enum MarketType {
     Saber(String, String),
     Swap(String, String),
     Serum(String, String),
+    Whirlpool(String, String),
 }
 
 impl MarketType {
@@ -31,21 +31,16 @@ impl MarketType {
             MarketType::Serum(x, y) => {
                 (x.to_string(), y.to_string())
             }
+            MarketType::Whirlpool(x, y) => {
+                (x.to_string(), y.to_string())
+            }
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct MarketOptMap {
-    pub market_type: MarketType,
-    pub quote_mint: String,
-    pub base_mint: String,
-    pub quote_map: HashMap<String, MarketPool>,
-    pub base_map: HashMap<String, MarketPool>,
-}
-
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct MarketPool {
+    pub market_type: MarketType,
     pub pool_key: Pubkey,
     pub quote_mint_key: Pubkey,
     pub base_mint_key: Pubkey,
@@ -56,45 +51,44 @@ pub struct MarketPool {
     pub data: HashMap<String, String>,
 }
 
+impl MarketPool {
+    //这一跳卖出的token
+    pub fn source_mint(&self) -> &Pubkey {
+        if self.is_quote_to_base {
+            &self.quote_mint_key
+        } else {
+            &self.base_mint_key
+        }
+    }
+
+    //这一跳买入的token
+    pub fn destination_mint(&self) -> &Pubkey {
+        if self.is_quote_to_base {
+            &self.base_mint_key
+        } else {
+            &self.quote_mint_key
+        }
+    }
+}
+
+//一条路径，每一跳的池子各自带着所属市场
 #[derive(Debug, Serialize, Deserialize)]
 pub struct MarketSwap {
-    pub market_type: MarketType,
     pub step: Vec<MarketPool>,
 }
 
-impl MarketOptMap {
-    pub fn filer_swap(&self) -> Result<Vec<MarketSwap>> {
-        let mut res = vec![];
-
-        //先判断有没有直接swap的交易对
-        let step = &self.base_map.get(&self.quote_mint);
-        match step {
-            Some(a) => {
-                return Ok(vec![MarketSwap {
-                    market_type: self.market_type.clone(),
-                    step: vec![(*a).clone()],
-                }]);
+impl MarketSwap {
+    //路径经过的市场名称和program_id，跨市场时用"/"连接各跳
+    pub fn market_name(&self) -> (String, String) {
+        let mut names: Vec<String> = vec![];
+        let mut program_ids: Vec<String> = vec![];
+        for step in &self.step {
+            let (name, program_id) = step.market_type.get_name();
+            if !names.contains(&name) {
+                names.push(name);
+                program_ids.push(program_id);
             }
-            None => {}
         }
-
-        for (key, value) in &self.quote_map {
-            if key.eq("So11111111111111111111111111111111111111112"){
-                continue
-            }
-            let source = &self.base_map.get(key);
-            match source {
-                Some(a) => {
-                    res.push(MarketSwap {
-                        market_type: self.market_type.clone(),
-                        step: vec![value.clone(), (*a).clone()],
-                    })
-                }
-                None => {}
-            }
-        }
-
-        Ok(res)
+        (names.join("/"), program_ids.join("/"))
     }
 }
-
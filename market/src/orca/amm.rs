@@ -0,0 +1,113 @@
+//! Orca使用spl-token-swap，直接用池子账户里的曲线报价
+
+use crate::amm::{Amm, AmmError, AmmQuote, AccountMap, FeeSide, SwapDirection, fee_rate, get_account, load_token_amount};
+use anyhow::Result;
+use solana_program::{program_pack::Pack, pubkey::Pubkey};
+use spl_token_swap::curve::calculator::TradeDirection;
+use spl_token_swap::state::SwapV1;
+use std::collections::HashMap;
+
+pub struct OrcaAmm {
+    swap_key: Pubkey,
+    quote_vault: Pubkey,
+    base_vault: Pubkey,
+    /// SwapCurve里的calculator不一定是Send，保存原始数据，报价时再解析
+    swap_data: Vec<u8>,
+    initialized: bool,
+    quote_amount: u64,
+    base_amount: u64,
+}
+
+impl OrcaAmm {
+    pub fn new(swap_key: Pubkey, quote_vault: Pubkey, base_vault: Pubkey) -> Self {
+        OrcaAmm {
+            swap_key,
+            quote_vault,
+            base_vault,
+            swap_data: vec![],
+            initialized: false,
+            quote_amount: 0,
+            base_amount: 0,
+        }
+    }
+}
+
+/// unpack_from_slice按固定长度取数据，长度不够时会panic
+pub fn unpack_swap_v1(key: &Pubkey, data: &[u8]) -> Result<SwapV1> {
+    if data.len() < SwapV1::LEN {
+        return Err(AmmError::AccountDecode(*key).into());
+    }
+    Ok(SwapV1::unpack_from_slice(data).map_err(|_| AmmError::AccountDecode(*key))?)
+}
+
+impl Amm for OrcaAmm {
+    fn key(&self) -> Pubkey {
+        self.swap_key
+    }
+
+    fn accounts_needed(&self) -> Vec<Pubkey> {
+        vec![self.swap_key, self.quote_vault, self.base_vault]
+    }
+
+    fn update(&mut self, accounts: &AccountMap) -> Result<()> {
+        let swap_data = &get_account(accounts, &self.swap_key)?.data;
+        let swap_info = unpack_swap_v1(&self.swap_key, swap_data)?;
+        self.quote_amount = load_token_amount(accounts, &self.quote_vault)?;
+        self.base_amount = load_token_amount(accounts, &self.base_vault)?;
+        self.initialized = swap_info.is_initialized;
+        self.swap_data = swap_data.clone();
+        Ok(())
+    }
+
+    fn unavailable_reason(&self) -> Option<String> {
+        if self.swap_data.is_empty() {
+            return Some("swap not loaded".to_string());
+        }
+        if !self.initialized {
+            return Some("swap not initialized".to_string());
+        }
+        None
+    }
+
+    fn reserves(&self) -> (u64, u64) {
+        (self.quote_amount, self.base_amount)
+    }
+
+    /// 恒定乘积和stable池子都与链上计算一致。
+    /// trade_fee和owner_fee由Fees::trading_fee/owner_trading_fee从输入里计算，取整和链上一致
+    fn quote(&self, amount_in: u64, direction: SwapDirection) -> Result<AmmQuote> {
+        let swap_info = unpack_swap_v1(&self.swap_key, &self.swap_data)?;
+        let (source_vault, source_reserve, destination_reserve) = match direction {
+            SwapDirection::QuoteToBase => (self.quote_vault, self.quote_amount, self.base_amount),
+            SwapDirection::BaseToQuote => (self.base_vault, self.base_amount, self.quote_amount),
+        };
+        let trade_direction = if source_vault.eq(&swap_info.token_a) {
+            TradeDirection::AtoB
+        } else {
+            TradeDirection::BtoA
+        };
+
+        //池子深度不够时曲线返回None，按产出0处理
+        let (amount_out, trade_fee, owner_fee) = swap_info.swap_curve.swap(amount_in as u128,
+                                                                          source_reserve as u128,
+                                                                          destination_reserve as u128,
+                                                                          trade_direction,
+                                                                          &swap_info.fees)
+            .map_or((0, 0, 0), |x| (x.destination_amount_swapped as u64, x.trade_fee as u64, x.owner_fee as u64));
+
+        let fees = &swap_info.fees;
+        Ok(AmmQuote {
+            amount_in,
+            amount_out,
+            source_reserve,
+            destination_reserve,
+            fee_rate: fee_rate(fees.trade_fee_numerator, fees.trade_fee_denominator) +
+                fee_rate(fees.owner_trade_fee_numerator, fees.owner_trade_fee_denominator),
+            fee_side: FeeSide::Source,
+            fee: trade_fee + owner_fee,
+            protocol_fee: owner_fee,
+            amp: None,
+            data: HashMap::new(),
+        })
+    }
+}
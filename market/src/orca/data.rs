@@ -5,11 +5,15 @@ use std::fs;
 use std::collections::HashMap;
 use solana_program::pubkey::Pubkey;
 use rust_decimal::prelude::FromStr;
-use market::{MarketPool, MarketOptMap, MarketType};
-use crate::pool::PoolInfo;
+use market::{MarketPool, MarketType};
+use crate::pool::{PoolInfo, RawPool};
 
-const ORCA_MARKET: &str = "Orca";
+pub const ORCA_MARKET: &str = "Orca";
 const ORCA_PROGRAM_ID: &str = "9W959DqEETiGZocYWCQPaJ6sBmUzgfxXfqGeTEdp3aQP";
+//pool_info和pool_list用的池子
+pub const POOL_PATH: &str = "./resource/pool/orca.json";
+//路由用的池子
+pub const MARKET_POOL_PATH: &str = "./orca_pool.json";
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RawMarketPool {
@@ -23,176 +27,55 @@ pub struct RawMarketPool {
     pub amp: Option<u64>,
 }
 
+impl RawMarketPool {
+    pub fn pool_info(&self) -> Option<PoolInfo> {
+        let mut data = HashMap::new();
+        if let Some(amp) = self.amp {
+            data.insert("amp".to_string(), amp.to_string());
+        }
+        data.insert("authority".to_string(), self.authority.clone());
+        data.insert("feeAccount".to_string(), self.fee_account.clone());
+        Some(PoolInfo {
+            market_type: MarketType::Orca(ORCA_MARKET.to_string(), ORCA_PROGRAM_ID.to_string()),
+            pool_key: Pubkey::from_str(&self.account).ok()?,
+            quote_mint_key: Pubkey::from_str(&self.quote.mint).ok()?,
+            base_mint_key: Pubkey::from_str(&self.base.mint).ok()?,
+            lp_mint_key: Pubkey::from_str(&self.pool_mint).ok()?,
+            quote_value_key: Pubkey::from_str(&self.quote.reserves).ok()?,
+            base_value_key: Pubkey::from_str(&self.base.reserves).ok()?,
+            data,
+        })
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RawMarketToken {
     pub mint: String,
     pub reserves: String,
 }
 
-pub fn load_data_from_file(quote_mint: &String, base_mint: &String) -> Result<MarketOptMap> {
-    let market_main_path = "./orca_pool.json".to_string();
-
-    let raw_info = fs::read_to_string(market_main_path).expect("Error read file");
-    let vec: Vec<RawMarketPool> = serde_json::from_str(&raw_info)?;
-
-    let mut quote_map = HashMap::new();
-    let mut base_map = HashMap::new();
-
-    for pool in &vec {
-        let mut data = HashMap::new();
-        data.insert("authority".to_string(), pool.authority.clone());
-        data.insert("poolMint".to_string(), pool.pool_mint.clone());
-        data.insert("feeAccount".to_string(), pool.fee_account.clone());
-        if pool.quote.mint.eq(quote_mint) {
-            data.insert("poolQuoteValue".to_string(), pool.quote.reserves.clone());
-            data.insert("poolBaseValue".to_string(), pool.base.reserves.clone());
-            let market_pool = MarketPool {
-                pool_key: Pubkey::from_str(&pool.account).unwrap(),
-                quote_mint_key: Pubkey::from_str(&pool.quote.mint).unwrap(),
-                base_mint_key: Pubkey::from_str(&pool.base.mint).unwrap(),
-                quote_value_key: Pubkey::from_str(&pool.quote.reserves).unwrap(),
-                base_value_key: Pubkey::from_str(&pool.base.reserves).unwrap(),
-                is_quote_to_base: true,
-                amp: pool.amp,
-                data: data.clone(),
-            };
-            quote_map.insert(pool.base.mint.clone(), market_pool);
-        }
-        if pool.base.mint.eq(quote_mint) {
-            data.insert("poolQuoteValue".to_string(), pool.base.reserves.clone());
-            data.insert("poolBaseValue".to_string(), pool.quote.reserves.clone());
-            let market_pool = MarketPool {
-                pool_key: Pubkey::from_str(&pool.account).unwrap(),
-                quote_mint_key: Pubkey::from_str(&pool.quote.mint).unwrap(),
-                base_mint_key: Pubkey::from_str(&pool.base.mint).unwrap(),
-                quote_value_key: Pubkey::from_str(&pool.quote.reserves).unwrap(),
-                base_value_key: Pubkey::from_str(&pool.base.reserves).unwrap(),
-                is_quote_to_base: false,
-                amp: pool.amp,
-                data: data.clone(),
-            };
-            quote_map.insert(pool.quote.mint.clone(), market_pool);
-        }
-        if pool.quote.mint.eq(base_mint) {
-            data.insert("poolQuoteValue".to_string(), pool.base.reserves.clone());
-            data.insert("poolBaseValue".to_string(), pool.quote.reserves.clone());
-            let market_pool = MarketPool {
-                pool_key: Pubkey::from_str(&pool.account).unwrap(),
-                quote_mint_key: Pubkey::from_str(&pool.quote.mint).unwrap(),
-                base_mint_key: Pubkey::from_str(&pool.base.mint).unwrap(),
-                quote_value_key: Pubkey::from_str(&pool.quote.reserves).unwrap(),
-                base_value_key: Pubkey::from_str(&pool.base.reserves).unwrap(),
-                is_quote_to_base: false,
-                amp: pool.amp,
-                data: data.clone(),
-            };
-            base_map.insert(pool.base.mint.clone(), market_pool);
-        }
-        if pool.base.mint.eq(base_mint) {
-            data.insert("poolQuoteValue".to_string(), pool.quote.reserves.clone());
-            data.insert("poolBaseValue".to_string(), pool.base.reserves.clone());
-            let market_pool = MarketPool {
-                pool_key: Pubkey::from_str(&pool.account).unwrap(),
-                quote_mint_key: Pubkey::from_str(&pool.quote.mint).unwrap(),
-                base_mint_key: Pubkey::from_str(&pool.base.mint).unwrap(),
-                quote_value_key: Pubkey::from_str(&pool.quote.reserves).unwrap(),
-                base_value_key: Pubkey::from_str(&pool.base.reserves).unwrap(),
-                is_quote_to_base: true,
-                amp: pool.amp,
-                data: data.clone(),
-            };
-            base_map.insert(pool.quote.mint.clone(), market_pool);
-        }
-    }
-
-    Ok(MarketOptMap {
-        market_type: MarketType::Orca(ORCA_MARKET.to_string().clone(), ORCA_PROGRAM_ID.to_string().clone()),
-        quote_mint: quote_mint.clone(),
-        base_mint: base_mint.clone(),
-        quote_map,
-        base_map,
-    })
+//加载resource/pool/orca.json里的全部池子
+pub fn load_pools() -> Result<Vec<PoolInfo>> {
+    Ok(read_pools(POOL_PATH)?.iter().filter_map(|x| x.pool_info()).collect())
 }
 
-pub fn load_pool_from_file(lp_mint: Option<String>,
-                           quote_mint: Option<String>,
-                           base_mint: Option<String>) -> Option<PoolInfo> {
-    let pool_main_path = "./resource/pool/orca.json".to_string();
-
-    let raw_info = fs::read_to_string(pool_main_path).expect("Error read file");
-    let vec: Vec<RawMarketPool> = serde_json::from_str(&raw_info).ok()?;
-
-    let pool_info = match lp_mint {
-        Some(lp) => {
-            //通过pool地址筛选
-            for raw_pool in vec {
-                if raw_pool.pool_mint.eq(&lp) {
-                    let mut data = HashMap::new();
-                    if raw_pool.amp.is_some() {
-                        data.insert("amp".to_string(), raw_pool.amp.unwrap().to_string());
-                    }
-                    data.insert("authority".to_string(), raw_pool.authority.clone());
-                    data.insert("feeAccount".to_string(), raw_pool.fee_account.clone());
-                    return Some(PoolInfo {
-                        market_type: MarketType::Orca(ORCA_MARKET.to_string(), ORCA_PROGRAM_ID.to_string()),
-                        pool_key: Pubkey::from_str(&raw_pool.account).unwrap(),
-                        quote_mint_key: Pubkey::from_str(&raw_pool.quote.mint).unwrap(),
-                        base_mint_key: Pubkey::from_str(&raw_pool.base.mint).unwrap(),
-                        lp_mint_key: Pubkey::from_str(&raw_pool.pool_mint).unwrap(),
-                        quote_value_key: Pubkey::from_str(&raw_pool.quote.reserves).unwrap(),
-                        base_value_key: Pubkey::from_str(&raw_pool.base.reserves).unwrap(),
-                        data,
-                    });
-                }
-            }
-            None
-        }
-        None => {
-            //通过 quote/base token对mint地址筛选
-            let quote_mint_address = quote_mint.unwrap().clone();
-            let base_mint_address = base_mint.unwrap().clone();
+//pool_list用的池子
+pub fn load_raw_pools() -> Result<Vec<RawPool>> {
+    Ok(read_pools(POOL_PATH)?.iter().map(|x| RawPool {
+        market: ORCA_MARKET.to_string(),
+        pool_key: x.account.clone(),
+        quote_mint: x.quote.mint.clone(),
+        base_mint: x.base.mint.clone(),
+        lp_mint: x.pool_mint.clone(),
+        quote_token: None,
+        base_token: None,
+    }).collect())
+}
 
-            for raw_pool in vec {
-                if quote_mint_address.eq(&raw_pool.quote.mint) && base_mint_address.eq(&raw_pool.base.mint) {
-                    let mut data = HashMap::new();
-                    if raw_pool.amp.is_some() {
-                        data.insert("amp".to_string(), raw_pool.amp.unwrap().to_string());
-                    }
-                    data.insert("authority".to_string(), raw_pool.authority.clone());
-                    data.insert("feeAccount".to_string(), raw_pool.fee_account.clone());
-                    return Some(PoolInfo {
-                        market_type: MarketType::Orca(ORCA_MARKET.to_string(), ORCA_PROGRAM_ID.to_string()),
-                        pool_key: Pubkey::from_str(&raw_pool.account).unwrap(),
-                        quote_mint_key: Pubkey::from_str(&raw_pool.quote.mint).unwrap(),
-                        base_mint_key: Pubkey::from_str(&raw_pool.base.mint).unwrap(),
-                        lp_mint_key: Pubkey::from_str(&raw_pool.pool_mint).unwrap(),
-                        quote_value_key: Pubkey::from_str(&raw_pool.quote.reserves).unwrap(),
-                        base_value_key: Pubkey::from_str(&raw_pool.base.reserves).unwrap(),
-                        data,
-                    });
-                } else if quote_mint_address.eq(&raw_pool.base.mint) && base_mint_address.eq(&raw_pool.quote.mint) {
-                    let mut data = HashMap::new();
-                    if raw_pool.amp.is_some() {
-                        data.insert("amp".to_string(), raw_pool.amp.unwrap().to_string());
-                    }
-                    data.insert("authority".to_string(), raw_pool.authority.clone());
-                    data.insert("feeAccount".to_string(), raw_pool.fee_account.clone());
-                    return Some(PoolInfo {
-                        market_type: MarketType::Orca(ORCA_MARKET.to_string(), ORCA_PROGRAM_ID.to_string()),
-                        pool_key: Pubkey::from_str(&raw_pool.account).unwrap(),
-                        quote_mint_key: Pubkey::from_str(&raw_pool.quote.mint).unwrap(),
-                        base_mint_key: Pubkey::from_str(&raw_pool.base.mint).unwrap(),
-                        lp_mint_key: Pubkey::from_str(&raw_pool.pool_mint).unwrap(),
-                        quote_value_key: Pubkey::from_str(&raw_pool.quote.reserves).unwrap(),
-                        base_value_key: Pubkey::from_str(&raw_pool.base.reserves).unwrap(),
-                        data,
-                    });
-                }
-            }
-            None
-        }
-    };
-    pool_info
+//两个池子文件的格式相同
+fn read_pools(path: &str) -> Result<Vec<RawMarketPool>> {
+    let raw_info = fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&raw_info)?)
 }
 
 pub fn calculate_pool_deposit_amount(quote_reserves: u64, _base_reserves: u64, _pool_supply: u64) -> u64 {
@@ -202,3 +85,37 @@ pub fn calculate_pool_deposit_amount(quote_reserves: u64, _base_reserves: u64, _
     0
 }
 
+
+//加载全部池子，每个池子按两个方向各生成一条边，供路由图使用
+pub fn load_market_pools() -> Result<Vec<MarketPool>> {
+    let vec = read_pools(MARKET_POOL_PATH)?;
+
+    let mut res = vec![];
+    for pool in &vec {
+        for is_quote_to_base in [true, false] {
+            let (source, destination) = if is_quote_to_base {
+                (&pool.quote, &pool.base)
+            } else {
+                (&pool.base, &pool.quote)
+            };
+            let mut data = HashMap::new();
+            data.insert("authority".to_string(), pool.authority.clone());
+            data.insert("poolMint".to_string(), pool.pool_mint.clone());
+            data.insert("feeAccount".to_string(), pool.fee_account.clone());
+            data.insert("poolQuoteValue".to_string(), source.reserves.clone());
+            data.insert("poolBaseValue".to_string(), destination.reserves.clone());
+            res.push(MarketPool {
+                market_type: MarketType::Orca(ORCA_MARKET.to_string(), ORCA_PROGRAM_ID.to_string()),
+                pool_key: Pubkey::from_str(&pool.account)?,
+                quote_mint_key: Pubkey::from_str(&pool.quote.mint)?,
+                base_mint_key: Pubkey::from_str(&pool.base.mint)?,
+                quote_value_key: Pubkey::from_str(&pool.quote.reserves)?,
+                base_value_key: Pubkey::from_str(&pool.base.reserves)?,
+                is_quote_to_base,
+                amp: pool.amp,
+                data,
+            });
+        }
+    }
+    Ok(res)
+}
@@ -1,2 +1,46 @@
 pub mod data;
+pub mod amm;
 
+use crate::dex::{AmmParams, Dex, RouteAmount, SwapAccounts};
+use crate::amm::Amm;
+use anyhow::Result;
+use solana_program::instruction::Instruction;
+use amm::OrcaAmm;
+
+pub const DEX: Dex = Dex {
+    id: "orca",
+    name: data::ORCA_MARKET,
+    load_market_pools: data::load_market_pools,
+    load_pools: Some(data::load_pools),
+    load_raw_pools: Some(data::load_raw_pools),
+    pool_files: &[data::MARKET_POOL_PATH, data::POOL_PATH],
+    new_amm,
+    swap_instruction: Some(swap_instruction),
+    exact_out: false,
+    mid_price: true,
+};
+
+fn new_amm(params: &AmmParams) -> Option<Box<dyn Amm>> {
+    Some(Box::new(OrcaAmm::new(params.pool_key, params.quote_value_key, params.base_value_key)))
+}
+
+//orca路径的data里poolQuoteValue是这一跳的源储备，poolBaseValue是目标储备
+fn swap_instruction(accounts: &SwapAccounts, amount: RouteAmount) -> Result<Instruction> {
+    let (amount_in, minimum_amount_out) = amount.exact_in(data::ORCA_MARKET)?;
+    Ok(spl_token_swap::instruction::swap(&accounts.program_id,
+                                         &spl_token::id(),
+                                         &accounts.pool_key,
+                                         &accounts.data_key("authority")?,
+                                         &accounts.wallet,
+                                         &accounts.source,
+                                         &accounts.data_key("poolQuoteValue")?,
+                                         &accounts.data_key("poolBaseValue")?,
+                                         &accounts.destination,
+                                         &accounts.data_key("poolMint")?,
+                                         &accounts.data_key("feeAccount")?,
+                                         None,
+                                         spl_token_swap::instruction::Swap {
+                                             amount_in,
+                                             minimum_amount_out,
+                                         })?)
+}
@@ -0,0 +1,336 @@
+//! Orca Whirlpool，quote和base分别是池子的token A和token B或反过来，由vault判断
+
+use crate::amm::{Amm, AmmError, AmmQuote, AccountMap, FeeSide, SwapDirection, fee_rate, get_account, load_token_amount};
+use crate::orca_whirlpool::math::FEE_RATE_MUL_VALUE;
+use crate::orca_whirlpool::state::{Oracle, TickArray, Whirlpool, oracle_address, tick_array_address, tick_array_start_index};
+use crate::orca_whirlpool::swap::{TickSequence, simulate_swap};
+use anyhow::Result;
+use solana_program::pubkey::Pubkey;
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// 和swap指令一样，每个方向最多经过3个tick array
+const SWAP_TICK_ARRAYS: i32 = 3;
+
+pub struct WhirlpoolAmm {
+    whirlpool_key: Pubkey,
+    program_id: Pubkey,
+    quote_vault: Pubkey,
+    base_vault: Pubkey,
+    whirlpool: Option<Whirlpool>,
+    /// 按当前tick推出的两个方向swap经过的tick array地址，池子账户更新时重新计算
+    a_to_b_tick_arrays: Vec<Pubkey>,
+    b_to_a_tick_arrays: Vec<Pubkey>,
+    /// 只和池子地址有关，创建时算一次
+    oracle_key: Pubkey,
+    /// 按地址索引，链上不存在的tick array不在里面
+    tick_arrays: HashMap<Pubkey, TickArray>,
+    oracle: Option<Oracle>,
+    quote_amount: u64,
+    base_amount: u64,
+}
+
+impl WhirlpoolAmm {
+    pub fn new(whirlpool_key: Pubkey, program_id: Pubkey, quote_vault: Pubkey, base_vault: Pubkey) -> Self {
+        WhirlpoolAmm {
+            whirlpool_key,
+            program_id,
+            quote_vault,
+            base_vault,
+            whirlpool: None,
+            a_to_b_tick_arrays: vec![],
+            b_to_a_tick_arrays: vec![],
+            oracle_key: oracle_address(&program_id, &whirlpool_key),
+            tick_arrays: HashMap::new(),
+            oracle: None,
+            quote_amount: 0,
+            base_amount: 0,
+        }
+    }
+
+    fn now() -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH).map(|x| x.as_secs()).unwrap_or_default()
+    }
+
+    fn is_a_to_b(&self, whirlpool: &Whirlpool, direction: SwapDirection) -> bool {
+        whirlpool.token_vault_a.eq(&self.quote_vault) == (direction == SwapDirection::QuoteToBase)
+    }
+
+    /// swap指令传入的3个tick array。b到a时从当前tick右移一个tick_spacing开始，价格正好在数组边界上时也能覆盖
+    fn derive_tick_arrays(&self, whirlpool: &Whirlpool, a_to_b: bool) -> Vec<Pubkey> {
+        let (shift, step) = if a_to_b { (0, -1) } else { (whirlpool.tick_spacing as i32, 1) };
+        (0..SWAP_TICK_ARRAYS)
+            .map(|i| tick_array_start_index(whirlpool.tick_current_index + shift, whirlpool.tick_spacing, i * step))
+            .map(|start| tick_array_address(&self.program_id, &self.whirlpool_key, start))
+            .collect()
+    }
+
+    /// 换了池子数据时一起算好tick array地址，报价时不再推导PDA
+    fn set_whirlpool(&mut self, whirlpool: Whirlpool) {
+        self.a_to_b_tick_arrays = self.derive_tick_arrays(&whirlpool, true);
+        self.b_to_a_tick_arrays = self.derive_tick_arrays(&whirlpool, false);
+        self.whirlpool = Some(whirlpool);
+    }
+
+    fn swap_tick_arrays(&self, a_to_b: bool) -> &[Pubkey] {
+        if a_to_b {
+            &self.a_to_b_tick_arrays
+        } else {
+            &self.b_to_a_tick_arrays
+        }
+    }
+}
+
+impl Amm for WhirlpoolAmm {
+    fn key(&self) -> Pubkey {
+        self.whirlpool_key
+    }
+
+    /// tick array和oracle要读到池子账户之后才知道
+    fn accounts_needed(&self) -> Vec<Pubkey> {
+        let mut keys = vec![self.whirlpool_key, self.quote_vault, self.base_vault];
+        if let Some(whirlpool) = &self.whirlpool {
+            for key in self.a_to_b_tick_arrays.iter().chain(self.b_to_a_tick_arrays.iter()) {
+                if !keys.contains(key) {
+                    keys.push(*key);
+                }
+            }
+            if whirlpool.is_adaptive_fee() {
+                keys.push(self.oracle_key);
+            }
+        }
+        keys
+    }
+
+    /// 池子和vault必须存在。tick array不存在时报价经过它就按深度不够处理
+    fn update(&mut self, accounts: &AccountMap) -> Result<()> {
+        let whirlpool = Whirlpool::unpack(&get_account(accounts, &self.whirlpool_key)?.data)
+            .map_err(|_| AmmError::AccountDecode(self.whirlpool_key))?;
+        self.set_whirlpool(whirlpool.clone());
+        self.quote_amount = load_token_amount(accounts, &self.quote_vault)?;
+        self.base_amount = load_token_amount(accounts, &self.base_vault)?;
+
+        let mut tick_arrays = HashMap::new();
+        for a_to_b in [true, false] {
+            for key in self.swap_tick_arrays(a_to_b) {
+                if let Some(account) = accounts.get(&key.to_string()) {
+                    let tick_array = TickArray::unpack(&account.data).map_err(|_| AmmError::AccountDecode(*key))?;
+                    tick_arrays.insert(*key, tick_array);
+                }
+            }
+        }
+        self.tick_arrays = tick_arrays;
+
+        self.oracle = if whirlpool.is_adaptive_fee() {
+            let oracle = Oracle::unpack(&get_account(accounts, &self.oracle_key)?.data)
+                .map_err(|_| AmmError::AccountDecode(self.oracle_key))?;
+            Some(oracle)
+        } else {
+            None
+        };
+        Ok(())
+    }
+
+    fn unavailable_reason(&self) -> Option<String> {
+        if self.whirlpool.is_none() {
+            return Some("whirlpool not loaded".to_string());
+        }
+        match &self.oracle {
+            Some(oracle) if oracle.trade_enable_timestamp > WhirlpoolAmm::now() => Some("trade not enabled".to_string()),
+            _ => None,
+        }
+    }
+
+    fn reserves(&self) -> (u64, u64) {
+        (self.quote_amount, self.base_amount)
+    }
+
+    /// 手续费从输入里扣，其中protocol_fee_rate的部分归协议。经过的tick array不够时产出为0
+    fn quote(&self, amount_in: u64, direction: SwapDirection) -> Result<AmmQuote> {
+        let whirlpool = self.whirlpool.as_ref().ok_or(AmmError::AccountDecode(self.whirlpool_key))?;
+        let a_to_b = self.is_a_to_b(whirlpool, direction);
+        let (source_reserve, destination_reserve) = match direction {
+            SwapDirection::QuoteToBase => (self.quote_amount, self.base_amount),
+            SwapDirection::BaseToQuote => (self.base_amount, self.quote_amount),
+        };
+
+        let tick_array_keys = self.swap_tick_arrays(a_to_b);
+        let sequence = TickSequence::new(tick_array_keys.iter().map_while(|x| self.tick_arrays.get(x)).collect(),
+                                         whirlpool.tick_spacing,
+                                         a_to_b);
+        let result = simulate_swap(whirlpool, &sequence, self.oracle.as_ref(), amount_in, a_to_b, WhirlpoolAmm::now());
+        let (amount_out, fee, protocol_fee, rate) = result
+            .map_or((0, 0, 0, whirlpool.fee_rate as u32), |x| (x.amount_out, x.fee_amount, x.protocol_fee, x.fee_rate));
+
+        //组装交易需要的tick array和oracle，池子文件里没有
+        let mut data = HashMap::new();
+        data.insert("aToB".to_string(), a_to_b.to_string());
+        data.insert("tokenVaultA".to_string(), whirlpool.token_vault_a.to_string());
+        data.insert("tokenVaultB".to_string(), whirlpool.token_vault_b.to_string());
+        for (i, key) in tick_array_keys.iter().enumerate() {
+            data.insert(format!("tickArray{}", i), key.to_string());
+        }
+        data.insert("oracle".to_string(), self.oracle_key.to_string());
+
+        Ok(AmmQuote {
+            amount_in,
+            amount_out,
+            source_reserve,
+            destination_reserve,
+            fee_rate: fee_rate(rate as u64, FEE_RATE_MUL_VALUE as u64),
+            fee_side: FeeSide::Source,
+            fee,
+            protocol_fee,
+            amp: None,
+            data,
+        })
+    }
+
+    fn pool_data(&self) -> HashMap<String, String> {
+        let mut data = HashMap::new();
+        let whirlpool = match &self.whirlpool {
+            Some(whirlpool) => whirlpool,
+            None => return data,
+        };
+        data.insert("tickSpacing".to_string(), whirlpool.tick_spacing.to_string());
+        data.insert("feeRate".to_string(), whirlpool.fee_rate.to_string());
+        data.insert("liquidity".to_string(), whirlpool.liquidity.to_string());
+        data.insert("sqrtPrice".to_string(), whirlpool.sqrt_price.to_string());
+        data.insert("tickCurrentIndex".to_string(), whirlpool.tick_current_index.to_string());
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orca_whirlpool::state::tests::{oracle, pack_oracle, pack_tick_array, pack_whirlpool, tick_array, whirlpool};
+    use solana_program::program_pack::Pack;
+    use solana_sdk::account::Account;
+    use spl_token::state::{Account as TokenAccount, AccountState};
+
+    const QUOTE_AMOUNT: u64 = 5_000_000_000;
+    const BASE_AMOUNT: u64 = 6_000_000_000;
+
+    fn account(data: Vec<u8>) -> Account {
+        Account {
+            lamports: 1,
+            data,
+            owner: Pubkey::new_unique(),
+            executable: false,
+            rent_epoch: 0,
+        }
+    }
+
+    fn token_account(mint: Pubkey, amount: u64) -> Account {
+        let mut data = vec![0; TokenAccount::LEN];
+        TokenAccount {
+            mint,
+            owner: Pubkey::new_unique(),
+            amount,
+            state: AccountState::Initialized,
+            ..TokenAccount::default()
+        }.pack_into_slice(&mut data);
+        account(data)
+    }
+
+    /// 价格为1的池子，quote是token A。with_tick_arrays时按accounts_needed补上空的tick array
+    fn load(pool: &Whirlpool, with_tick_arrays: bool) -> (WhirlpoolAmm, AccountMap) {
+        let whirlpool_key = Pubkey::new_unique();
+        let mut amm = WhirlpoolAmm::new(whirlpool_key, Pubkey::new_unique(), pool.token_vault_a, pool.token_vault_b);
+        let mut accounts = AccountMap::new();
+        accounts.insert(whirlpool_key.to_string(), account(pack_whirlpool(pool)));
+        accounts.insert(pool.token_vault_a.to_string(), token_account(pool.token_mint_a, QUOTE_AMOUNT));
+        accounts.insert(pool.token_vault_b.to_string(), token_account(pool.token_mint_b, BASE_AMOUNT));
+        if with_tick_arrays {
+            amm.set_whirlpool(pool.clone());
+            for key in amm.accounts_needed().iter().skip(3) {
+                if key.eq(&amm.oracle_key) {
+                    continue;
+                }
+                let start = [-11264, -5632, 0, 5632, 11264].into_iter()
+                    .find(|x| tick_array_address(&amm.program_id, &whirlpool_key, *x).eq(key))
+                    .unwrap();
+                accounts.insert(key.to_string(), account(pack_tick_array(&tick_array(whirlpool_key, start, &[], 0))));
+            }
+        }
+        (amm, accounts)
+    }
+
+    #[test]
+    fn test_quote() {
+        let pool = whirlpool(64, 1_000_000_000_000, 1u128 << 64, 0);
+        let (mut amm, accounts) = load(&pool, true);
+        amm.update(&accounts).unwrap();
+        assert_eq!(amm.accounts_needed().len(), 3 + 5);
+        assert_eq!(amm.tick_arrays.len(), 5);
+        assert_eq!(amm.unavailable_reason(), None);
+        assert_eq!(amm.reserves(), (QUOTE_AMOUNT, BASE_AMOUNT));
+
+        let quote = amm.quote(1_000_000, SwapDirection::QuoteToBase).unwrap();
+        assert_eq!(quote.amount_out, 996_999);
+        assert_eq!(quote.fee, 3000);
+        assert_eq!(quote.protocol_fee, 390);
+        assert_eq!(quote.fee_rate, 0.003);
+        assert_eq!((quote.source_reserve, quote.destination_reserve), (QUOTE_AMOUNT, BASE_AMOUNT));
+        assert_eq!(quote.data["aToB"], "true");
+        for (i, start) in [0, -5632, -11264].iter().enumerate() {
+            assert_eq!(quote.data[&format!("tickArray{}", i)], tick_array_address(&amm.program_id, &amm.whirlpool_key, *start).to_string());
+        }
+
+        let quote = amm.quote(1_000_000, SwapDirection::BaseToQuote).unwrap();
+        assert_eq!(quote.amount_out, 996_999);
+        assert_eq!((quote.source_reserve, quote.destination_reserve), (BASE_AMOUNT, QUOTE_AMOUNT));
+        assert_eq!(quote.data["aToB"], "false");
+        assert_eq!(quote.data["tickArray0"], tick_array_address(&amm.program_id, &amm.whirlpool_key, 0).to_string());
+    }
+
+    #[test]
+    fn test_tick_arrays_follow_current_tick() {
+        let mut pool = whirlpool(64, 1_000_000_000_000, 1u128 << 64, 0);
+        let (mut amm, mut accounts) = load(&pool, false);
+        amm.update(&accounts).unwrap();
+        let (program_id, whirlpool_key) = (amm.program_id, amm.whirlpool_key);
+        let address = |start| tick_array_address(&program_id, &whirlpool_key, start);
+        assert_eq!(amm.swap_tick_arrays(true), [address(0), address(-5632), address(-11264)]);
+        assert_eq!(amm.swap_tick_arrays(false), [address(0), address(5632), address(11264)]);
+
+        //池子账户更新后按新的tick重新计算
+        pool.tick_current_index = 6000;
+        accounts.insert(whirlpool_key.to_string(), account(pack_whirlpool(&pool)));
+        amm.update(&accounts).unwrap();
+        assert_eq!(amm.swap_tick_arrays(true), [address(5632), address(0), address(-5632)]);
+        assert_eq!(amm.swap_tick_arrays(false), [address(5632), address(11264), address(16896)]);
+    }
+
+    #[test]
+    fn test_quote_without_tick_arrays() {
+        let pool = whirlpool(64, 1_000_000_000_000, 1u128 << 64, 0);
+        let (mut amm, accounts) = load(&pool, false);
+        amm.update(&accounts).unwrap();
+        assert!(amm.tick_arrays.is_empty());
+        let quote = amm.quote(1_000_000, SwapDirection::QuoteToBase).unwrap();
+        assert_eq!(quote.amount_out, 0);
+        assert_eq!(quote.fee, 0);
+    }
+
+    #[test]
+    fn test_adaptive_fee_oracle() {
+        let mut pool = whirlpool(64, 1_000_000_000_000, 1u128 << 64, 0);
+        pool.fee_tier_index = 1024;
+        let (mut amm, mut accounts) = load(&pool, true);
+        assert!(amm.accounts_needed().contains(&amm.oracle_key));
+        //启用adaptive fee的池子必须有oracle
+        assert!(amm.update(&accounts).is_err());
+
+        let mut oracle = oracle(amm.whirlpool_key, WhirlpoolAmm::now());
+        oracle.trade_enable_timestamp = WhirlpoolAmm::now() + 3600;
+        accounts.insert(amm.oracle_key.to_string(), account(pack_oracle(&oracle)));
+        amm.update(&accounts).unwrap();
+        assert_eq!(amm.unavailable_reason(), Some("trade not enabled".to_string()));
+
+        let quote = amm.quote(1_000_000, SwapDirection::QuoteToBase).unwrap();
+        assert!(quote.fee > 3000);
+        assert!(quote.amount_out < 996_999);
+    }
+}
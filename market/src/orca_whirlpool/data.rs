@@ -0,0 +1,109 @@
+use crate::market;
+use serde::{Serialize, Deserialize};
+use anyhow::Result;
+use std::fs;
+use std::collections::HashMap;
+use solana_program::pubkey::Pubkey;
+use rust_decimal::prelude::FromStr;
+use market::{MarketPool, MarketType};
+use crate::pool::{PoolInfo, RawPool};
+
+pub const WHIRLPOOL_MARKET: &str = "Whirlpool";
+pub const WHIRLPOOL_PROGRAM_ID: &str = "whirLbMiicVdio4qvUfM5KAg6Ct8VwpYzGff3uctyCc";
+//路由、pool_info和pool_list共用一个池子文件
+pub const POOL_PATH: &str = "./whirlpool_pool.json";
+
+//quote是池子的token A，base是token B
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RawMarketPool {
+    pub account: String,
+    pub quote: RawMarketToken,
+    pub base: RawMarketToken,
+
+    #[serde(default)]
+    pub tick_spacing: Option<u16>,
+}
+
+impl RawMarketPool {
+    fn market_type() -> MarketType {
+        MarketType::Whirlpool(WHIRLPOOL_MARKET.to_string(), WHIRLPOOL_PROGRAM_ID.to_string())
+    }
+
+    fn pool_info(&self) -> Option<PoolInfo> {
+        let mut data = HashMap::new();
+        if let Some(tick_spacing) = self.tick_spacing {
+            data.insert("tickSpacing".to_string(), tick_spacing.to_string());
+        }
+        Some(PoolInfo {
+            market_type: RawMarketPool::market_type(),
+            pool_key: Pubkey::from_str(&self.account).ok()?,
+            quote_mint_key: Pubkey::from_str(&self.quote.mint).ok()?,
+            base_mint_key: Pubkey::from_str(&self.base.mint).ok()?,
+            //集中流动性的头寸是NFT，没有lp mint
+            lp_mint_key: Pubkey::default(),
+            quote_value_key: Pubkey::from_str(&self.quote.reserves).ok()?,
+            base_value_key: Pubkey::from_str(&self.base.reserves).ok()?,
+            data,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RawMarketToken {
+    pub mint: String,
+    pub reserves: String,
+}
+
+fn read_pools() -> Result<Vec<RawMarketPool>> {
+    let raw_info = fs::read_to_string(POOL_PATH)?;
+    Ok(serde_json::from_str(&raw_info)?)
+}
+
+//加载全部池子，每个池子按两个方向各生成一条边，供路由图使用
+pub fn load_market_pools() -> Result<Vec<MarketPool>> {
+    let vec = read_pools()?;
+
+    let mut res = vec![];
+    for pool in &vec {
+        for is_quote_to_base in [true, false] {
+            let (source, destination) = if is_quote_to_base {
+                (&pool.quote, &pool.base)
+            } else {
+                (&pool.base, &pool.quote)
+            };
+            let mut data = HashMap::new();
+            data.insert("poolSource".to_string(), source.reserves.clone());
+            data.insert("poolDestination".to_string(), destination.reserves.clone());
+            res.push(MarketPool {
+                market_type: RawMarketPool::market_type(),
+                pool_key: Pubkey::from_str(&pool.account)?,
+                quote_mint_key: Pubkey::from_str(&pool.quote.mint)?,
+                base_mint_key: Pubkey::from_str(&pool.base.mint)?,
+                quote_value_key: Pubkey::from_str(&pool.quote.reserves)?,
+                base_value_key: Pubkey::from_str(&pool.base.reserves)?,
+                is_quote_to_base,
+                amp: None,
+                data,
+            });
+        }
+    }
+    Ok(res)
+}
+
+//加载whirlpool_pool.json里的全部池子
+pub fn load_pools() -> Result<Vec<PoolInfo>> {
+    Ok(read_pools()?.iter().filter_map(|x| x.pool_info()).collect())
+}
+
+//pool_list用的池子，集中流动性没有lp mint
+pub fn load_raw_pools() -> Result<Vec<RawPool>> {
+    Ok(read_pools()?.iter().map(|x| RawPool {
+        market: WHIRLPOOL_MARKET.to_string(),
+        pool_key: x.account.clone(),
+        quote_mint: x.quote.mint.clone(),
+        base_mint: x.base.mint.clone(),
+        lp_mint: String::new(),
+        quote_token: None,
+        base_token: None,
+    }).collect())
+}
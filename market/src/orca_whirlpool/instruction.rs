@@ -0,0 +1,62 @@
+//! Whirlpool swap指令
+
+#![allow(clippy::too_many_arguments)]
+
+use crate::orca_whirlpool::math::{MAX_SQRT_PRICE_X64, MIN_SQRT_PRICE_X64};
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
+
+/// Anchor指令discriminator，sha256("global:swap")的前8字节
+const SWAP_DISCRIMINATOR: [u8; 8] = [248, 198, 158, 145, 225, 117, 135, 200];
+
+/// swap，不限制价格，只用other_amount_threshold保护。
+/// amount_specified_is_input时amount是输入，threshold是最少产出；否则amount是产出，threshold是最多输入。
+/// tick array按价格移动方向传入，和报价时经过的3个tick array一致
+pub fn swap(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    user_authority_key: &Pubkey,
+    whirlpool_pubkey: &Pubkey,
+    token_owner_account_a: &Pubkey,
+    token_vault_a: &Pubkey,
+    token_owner_account_b: &Pubkey,
+    token_vault_b: &Pubkey,
+    tick_arrays: [&Pubkey; 3],
+    oracle_pubkey: &Pubkey,
+    amount: u64,
+    other_amount_threshold: u64,
+    amount_specified_is_input: bool,
+    a_to_b: bool,
+) -> Result<Instruction, ProgramError> {
+    let sqrt_price_limit = if a_to_b { MIN_SQRT_PRICE_X64 } else { MAX_SQRT_PRICE_X64 };
+    let mut data = Vec::with_capacity(42);
+    data.extend_from_slice(&SWAP_DISCRIMINATOR);
+    data.extend_from_slice(&amount.to_le_bytes());
+    data.extend_from_slice(&other_amount_threshold.to_le_bytes());
+    data.extend_from_slice(&sqrt_price_limit.to_le_bytes());
+    data.push(amount_specified_is_input as u8);
+    data.push(a_to_b as u8);
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*token_program_id, false),
+        AccountMeta::new_readonly(*user_authority_key, true),
+        AccountMeta::new(*whirlpool_pubkey, false),
+        AccountMeta::new(*token_owner_account_a, false),
+        AccountMeta::new(*token_vault_a, false),
+        AccountMeta::new(*token_owner_account_b, false),
+        AccountMeta::new(*token_vault_b, false),
+        AccountMeta::new(*tick_arrays[0], false),
+        AccountMeta::new(*tick_arrays[1], false),
+        AccountMeta::new(*tick_arrays[2], false),
+        AccountMeta::new(*oracle_pubkey, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
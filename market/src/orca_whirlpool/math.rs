@@ -0,0 +1,291 @@
+//! 集中流动性的sqrt price计算，和链上一样使用Q64.64定点数，取整方向一致
+
+#![allow(clippy::assign_op_pattern)]
+#![allow(clippy::ptr_offset_with_cast)]
+#![allow(clippy::manual_range_contains)]
+#![allow(clippy::manual_div_ceil)]
+
+use uint::construct_uint;
+
+construct_uint! {
+    /// 256-bit unsigned integer.
+    pub struct U256(4);
+}
+
+pub const MIN_TICK_INDEX: i32 = -443636;
+pub const MAX_TICK_INDEX: i32 = 443636;
+pub const MIN_SQRT_PRICE_X64: u128 = 4295048016;
+pub const MAX_SQRT_PRICE_X64: u128 = 79226673515401279992447579055;
+
+/// fee_rate的分母
+pub const FEE_RATE_MUL_VALUE: u128 = 1_000_000;
+/// protocol_fee_rate的分母
+pub const PROTOCOL_FEE_RATE_MUL_VALUE: u128 = 10_000;
+
+/// sqrt(1.0001)^(2^i)，Q96，i从1开始
+const POSITIVE_TICK_FACTORS: [u128; 18] = [
+    79236085330515764027303304731,
+    79244008939048815603706035061,
+    79259858533276714757314932305,
+    79291567232598584799939703904,
+    79355022692464371645785046466,
+    79482085999252804386437311141,
+    79736823300114093921829183326,
+    80248749790819932309965073892,
+    81282483887344747381513967011,
+    83390072131320151908154831281,
+    87770609709833776024991924138,
+    97234110755111693312479820773,
+    119332217159966728226237229890,
+    179736315981702064433883588727,
+    407748233172238350107850275304,
+    2098478828474011932436660412517,
+    55581415166113811149459800483533,
+    38992368544603139932233054999993551,
+];
+
+/// 1 / sqrt(1.0001)^(2^i)，Q64，i从1开始
+const NEGATIVE_TICK_FACTORS: [u128; 18] = [
+    18444899583751176498,
+    18443055278223354162,
+    18439367220385604838,
+    18431993317065449817,
+    18417254355718160513,
+    18387811781193591352,
+    18329067761203520168,
+    18212142134806087854,
+    17980523815641551639,
+    17526086738831147013,
+    16651378430235024244,
+    15030750278693429944,
+    12247334978882834399,
+    8131365268884726200,
+    3584323654723342297,
+    696457651847595233,
+    26294789957452057,
+    37481735321082,
+];
+
+/// sqrt(1.0001^tick)，Q64.64
+pub fn sqrt_price_from_tick_index(tick: i32) -> u128 {
+    let abs_tick = tick.unsigned_abs();
+    if tick >= 0 {
+        let mut ratio = if abs_tick & 1 != 0 {
+            U256::from(79232123823359799118286999567u128)
+        } else {
+            U256::from(79228162514264337593543950336u128)
+        };
+        for (i, factor) in POSITIVE_TICK_FACTORS.iter().enumerate() {
+            if abs_tick & (2 << i) != 0 {
+                ratio = (ratio * U256::from(*factor)) >> 96;
+            }
+        }
+        (ratio >> 32).as_u128()
+    } else {
+        let mut ratio: u128 = if abs_tick & 1 != 0 {
+            18445821805675392311
+        } else {
+            18446744073709551616
+        };
+        for (i, factor) in NEGATIVE_TICK_FACTORS.iter().enumerate() {
+            if abs_tick & (2 << i) != 0 {
+                ratio = (ratio * factor) >> 64;
+            }
+        }
+        ratio
+    }
+}
+
+/// sqrt_price_from_tick_index(tick) <= sqrt_price的最大tick。先用浮点数估计，再按精确值修正
+pub fn tick_index_from_sqrt_price(sqrt_price: u128) -> i32 {
+    let price = sqrt_price as f64 / 18446744073709551616.0;
+    let estimate = (2.0 * price.ln() / 1.0001f64.ln()).floor();
+    let mut tick = (estimate as i32).clamp(MIN_TICK_INDEX, MAX_TICK_INDEX);
+    while tick < MAX_TICK_INDEX && sqrt_price_from_tick_index(tick + 1) <= sqrt_price {
+        tick += 1;
+    }
+    while tick > MIN_TICK_INDEX && sqrt_price_from_tick_index(tick) > sqrt_price {
+        tick -= 1;
+    }
+    tick
+}
+
+fn div_round_up(numerator: U256, denominator: U256) -> U256 {
+    let quotient = numerator / denominator;
+    if quotient * denominator == numerator {
+        quotient
+    } else {
+        quotient + 1
+    }
+}
+
+fn to_u64(value: U256) -> Option<u64> {
+    if value > U256::from(u64::MAX) {
+        None
+    } else {
+        Some(value.as_u64())
+    }
+}
+
+/// 两个价格之间token A的数量：L * (upper - lower) / (upper * lower)，超过u64时返回None
+pub fn get_amount_delta_a(sqrt_price_0: u128, sqrt_price_1: u128, liquidity: u128, round_up: bool) -> Option<u64> {
+    let (lower, upper) = if sqrt_price_0 < sqrt_price_1 { (sqrt_price_0, sqrt_price_1) } else { (sqrt_price_1, sqrt_price_0) };
+    if lower == 0 {
+        return None;
+    }
+    let numerator = (U256::from(liquidity) * U256::from(upper - lower)) << 64;
+    let denominator = U256::from(upper) * U256::from(lower);
+    let result = if round_up {
+        div_round_up(numerator, denominator)
+    } else {
+        numerator / denominator
+    };
+    to_u64(result)
+}
+
+/// 两个价格之间token B的数量：L * (upper - lower)，超过u64时返回None
+pub fn get_amount_delta_b(sqrt_price_0: u128, sqrt_price_1: u128, liquidity: u128, round_up: bool) -> Option<u64> {
+    let (lower, upper) = if sqrt_price_0 < sqrt_price_1 { (sqrt_price_0, sqrt_price_1) } else { (sqrt_price_1, sqrt_price_0) };
+    let product = U256::from(liquidity) * U256::from(upper - lower);
+    let mut result = product >> 64;
+    if round_up && product.low_u64() != 0 {
+        result = result + 1;
+    }
+    to_u64(result)
+}
+
+/// 输入amount个token A后的价格，向上取整
+pub fn get_next_sqrt_price_from_a_round_up(sqrt_price: u128, liquidity: u128, amount: u64) -> Option<u128> {
+    if amount == 0 {
+        return Some(sqrt_price);
+    }
+    let product = U256::from(sqrt_price) * U256::from(amount);
+    let numerator = (U256::from(liquidity) * U256::from(sqrt_price)) << 64;
+    let denominator = (U256::from(liquidity) << 64) + product;
+    let price = div_round_up(numerator, denominator);
+    if price < U256::from(MIN_SQRT_PRICE_X64) || price > U256::from(MAX_SQRT_PRICE_X64) {
+        return None;
+    }
+    Some(price.as_u128())
+}
+
+/// 输入amount个token B后的价格，向下取整
+pub fn get_next_sqrt_price_from_b_round_down(sqrt_price: u128, liquidity: u128, amount: u64) -> Option<u128> {
+    if liquidity == 0 {
+        return None;
+    }
+    let delta = ((amount as u128) << 64) / liquidity;
+    let price = sqrt_price.checked_add(delta)?;
+    if price > MAX_SQRT_PRICE_X64 {
+        return None;
+    }
+    Some(price)
+}
+
+/// 一段价格区间内的成交，amount_in不含手续费
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SwapStep {
+    pub amount_in: u64,
+    pub amount_out: u64,
+    pub next_sqrt_price: u128,
+    pub fee_amount: u64,
+}
+
+/// 指定输入时在[sqrt_price_current, sqrt_price_target]内成交，和链上swap_math::compute_swap一致。
+/// 输入足够把价格推到目标时停在目标价格，否则按扣费后的输入计算停下的价格
+pub fn compute_swap(amount_remaining: u64,
+                    fee_rate: u32,
+                    liquidity: u128,
+                    sqrt_price_current: u128,
+                    sqrt_price_target: u128,
+                    a_to_b: bool) -> Option<SwapStep> {
+    let fee_rate = fee_rate as u128;
+    let fixed_delta = |sqrt_price: u128| if a_to_b {
+        get_amount_delta_a(sqrt_price_current, sqrt_price, liquidity, true)
+    } else {
+        get_amount_delta_b(sqrt_price_current, sqrt_price, liquidity, true)
+    };
+
+    let initial_fixed_delta = fixed_delta(sqrt_price_target);
+    let amount_calc = (amount_remaining as u128 * (FEE_RATE_MUL_VALUE - fee_rate) / FEE_RATE_MUL_VALUE) as u64;
+    let next_sqrt_price = match initial_fixed_delta {
+        Some(delta) if amount_calc >= delta => sqrt_price_target,
+        _ if a_to_b => get_next_sqrt_price_from_a_round_up(sqrt_price_current, liquidity, amount_calc)?,
+        _ => get_next_sqrt_price_from_b_round_down(sqrt_price_current, liquidity, amount_calc)?,
+    };
+    let is_max_swap = next_sqrt_price == sqrt_price_target;
+
+    let amount_out = if a_to_b {
+        get_amount_delta_b(sqrt_price_current, next_sqrt_price, liquidity, false)?
+    } else {
+        get_amount_delta_a(sqrt_price_current, next_sqrt_price, liquidity, false)?
+    };
+    let amount_in = match initial_fixed_delta {
+        Some(delta) if is_max_swap => delta,
+        _ => fixed_delta(next_sqrt_price)?,
+    };
+
+    //没有到达目标价格时剩余的输入全部算作手续费
+    let fee_amount = if is_max_swap {
+        let fee = (amount_in as u128 * fee_rate + FEE_RATE_MUL_VALUE - fee_rate - 1) / (FEE_RATE_MUL_VALUE - fee_rate);
+        u64::try_from(fee).ok()?
+    } else {
+        amount_remaining.checked_sub(amount_in)?
+    };
+
+    Some(SwapStep {
+        amount_in,
+        amount_out,
+        next_sqrt_price,
+        fee_amount,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sqrt_price_bounds() {
+        assert_eq!(sqrt_price_from_tick_index(0), 1u128 << 64);
+        assert_eq!(sqrt_price_from_tick_index(MAX_TICK_INDEX), MAX_SQRT_PRICE_X64);
+        assert_eq!(sqrt_price_from_tick_index(MIN_TICK_INDEX), MIN_SQRT_PRICE_X64);
+    }
+
+    #[test]
+    fn test_tick_index_round_trip() {
+        for tick in [MIN_TICK_INDEX, -223027, -1, 0, 1, 64, 22_222, MAX_TICK_INDEX] {
+            let sqrt_price = sqrt_price_from_tick_index(tick);
+            assert_eq!(tick_index_from_sqrt_price(sqrt_price), tick);
+            if tick < MAX_TICK_INDEX {
+                assert_eq!(tick_index_from_sqrt_price(sqrt_price + 1), tick);
+            }
+            if tick > MIN_TICK_INDEX {
+                assert_eq!(tick_index_from_sqrt_price(sqrt_price - 1), tick - 1);
+            }
+        }
+    }
+
+    #[test]
+    fn test_compute_swap_reaches_target() {
+        let liquidity = 1_000_000_000_000u128;
+        let current = sqrt_price_from_tick_index(0);
+        let target = sqrt_price_from_tick_index(-64);
+        let step = compute_swap(u64::MAX / 2, 3000, liquidity, current, target, true).unwrap();
+        assert_eq!(step.next_sqrt_price, target);
+        assert_eq!(step.amount_in, get_amount_delta_a(current, target, liquidity, true).unwrap());
+        assert_eq!(step.amount_out, get_amount_delta_b(current, target, liquidity, false).unwrap());
+    }
+
+    #[test]
+    fn test_compute_swap_partial() {
+        let liquidity = 1_000_000_000_000u128;
+        let current = sqrt_price_from_tick_index(0);
+        let target = sqrt_price_from_tick_index(6400);
+        let step = compute_swap(1_000_000, 3000, liquidity, current, target, false).unwrap();
+        assert!(step.next_sqrt_price > current && step.next_sqrt_price < target);
+        assert_eq!(step.amount_in + step.fee_amount, 1_000_000);
+        //价格接近1时产出略少于扣费后的输入
+        assert!(step.amount_out < 997_000 && step.amount_out > 996_000);
+    }
+}
@@ -0,0 +1,57 @@
+pub mod data;
+pub mod state;
+pub mod math;
+pub mod swap;
+pub mod instruction;
+pub mod amm;
+
+use crate::dex::{AmmParams, Dex, RouteAmount, SwapAccounts};
+use crate::amm::Amm;
+use anyhow::Result;
+use solana_program::{instruction::Instruction, pubkey::Pubkey};
+use std::str::FromStr;
+use amm::WhirlpoolAmm;
+
+pub const DEX: Dex = Dex {
+    id: "whirlpool",
+    name: data::WHIRLPOOL_MARKET,
+    load_market_pools: data::load_market_pools,
+    load_pools: Some(data::load_pools),
+    load_raw_pools: Some(data::load_raw_pools),
+    pool_files: &[data::POOL_PATH],
+    new_amm,
+    swap_instruction: Some(swap_instruction),
+    exact_out: true,
+    mid_price: true,
+};
+
+fn new_amm(params: &AmmParams) -> Option<Box<dyn Amm>> {
+    let program_id = Pubkey::from_str(params.program_id).ok()?;
+    Some(Box::new(WhirlpoolAmm::new(params.pool_key, program_id, params.quote_value_key, params.base_value_key)))
+}
+
+//用户账户按池子的token A/B传入，方向由aToB决定。tick array和oracle在报价时按当前价格算出
+fn swap_instruction(accounts: &SwapAccounts, amount: RouteAmount) -> Result<Instruction> {
+    let a_to_b = accounts.data.get("aToB").is_some_and(|x| x == "true");
+    let (owner_a, owner_b) = if a_to_b {
+        (accounts.source, accounts.destination)
+    } else {
+        (accounts.destination, accounts.source)
+    };
+    let amount_specified_is_input = matches!(amount, RouteAmount::ExactIn { .. });
+    let (amount, other_amount_threshold) = amount.values();
+    Ok(instruction::swap(&accounts.program_id,
+                         &spl_token::id(),
+                         &accounts.wallet,
+                         &accounts.pool_key,
+                         &owner_a,
+                         &accounts.data_key("tokenVaultA")?,
+                         &owner_b,
+                         &accounts.data_key("tokenVaultB")?,
+                         [&accounts.data_key("tickArray0")?, &accounts.data_key("tickArray1")?, &accounts.data_key("tickArray2")?],
+                         &accounts.data_key("oracle")?,
+                         amount,
+                         other_amount_threshold,
+                         amount_specified_is_input,
+                         a_to_b)?)
+}
@@ -0,0 +1,454 @@
+//! Whirlpool程序的Anchor账户，前8字节是discriminator，数值都是小端
+
+use arrayref::array_ref;
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+/// 每个tick array里的tick数
+pub const TICK_ARRAY_SIZE: i32 = 88;
+
+pub const WHIRLPOOL_LEN: usize = 653;
+pub const TICK_ARRAY_LEN: usize = 9988;
+pub const ORACLE_LEN: usize = 254;
+
+const WHIRLPOOL_DISCRIMINATOR: [u8; 8] = [63, 149, 209, 12, 225, 128, 99, 9];
+const TICK_ARRAY_DISCRIMINATOR: [u8; 8] = [69, 97, 189, 190, 110, 7, 66, 187];
+const DYNAMIC_TICK_ARRAY_DISCRIMINATOR: [u8; 8] = [17, 216, 246, 142, 225, 199, 218, 56];
+const ORACLE_DISCRIMINATOR: [u8; 8] = [139, 194, 131, 179, 140, 179, 229, 244];
+
+/// 定长tick array里每个tick占的字节数
+const TICK_LEN: usize = 113;
+/// 动态tick array里已初始化tick的数据长度，不含前面的标记字节
+const DYNAMIC_TICK_DATA_LEN: usize = 112;
+
+fn check_discriminator(data: &[u8], discriminator: &[u8; 8], len: usize) -> Result<(), ProgramError> {
+    if data.len() < len || data[..8] != discriminator[..] {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    Ok(())
+}
+
+fn read_pubkey(data: &[u8], offset: usize) -> Pubkey {
+    Pubkey::new_from_array(*array_ref![data, offset, 32])
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(*array_ref![data, offset, 2])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(*array_ref![data, offset, 4])
+}
+
+fn read_i32(data: &[u8], offset: usize) -> i32 {
+    i32::from_le_bytes(*array_ref![data, offset, 4])
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(*array_ref![data, offset, 8])
+}
+
+fn read_u128(data: &[u8], offset: usize) -> u128 {
+    u128::from_le_bytes(*array_ref![data, offset, 16])
+}
+
+fn read_i128(data: &[u8], offset: usize) -> i128 {
+    i128::from_le_bytes(*array_ref![data, offset, 16])
+}
+
+/// 池子状态，省略了手续费累计和奖励信息
+#[derive(Clone, Debug, PartialEq)]
+pub struct Whirlpool {
+    pub whirlpools_config: Pubkey,
+    pub tick_spacing: u16,
+    /// 创建池子用的fee tier序号，和tick_spacing不同时池子启用了adaptive fee
+    pub fee_tier_index: u16,
+    /// 百万分之一
+    pub fee_rate: u16,
+    /// 手续费里归协议的比例，万分之一
+    pub protocol_fee_rate: u16,
+    pub liquidity: u128,
+    /// Q64.64
+    pub sqrt_price: u128,
+    pub tick_current_index: i32,
+    pub token_mint_a: Pubkey,
+    pub token_vault_a: Pubkey,
+    pub token_mint_b: Pubkey,
+    pub token_vault_b: Pubkey,
+}
+
+impl Whirlpool {
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        check_discriminator(data, &WHIRLPOOL_DISCRIMINATOR, WHIRLPOOL_LEN)?;
+        Ok(Whirlpool {
+            whirlpools_config: read_pubkey(data, 8),
+            tick_spacing: read_u16(data, 41),
+            fee_tier_index: read_u16(data, 43),
+            fee_rate: read_u16(data, 45),
+            protocol_fee_rate: read_u16(data, 47),
+            liquidity: read_u128(data, 49),
+            sqrt_price: read_u128(data, 65),
+            tick_current_index: read_i32(data, 81),
+            token_mint_a: read_pubkey(data, 101),
+            token_vault_a: read_pubkey(data, 133),
+            token_mint_b: read_pubkey(data, 181),
+            token_vault_b: read_pubkey(data, 213),
+        })
+    }
+
+    pub fn is_adaptive_fee(&self) -> bool {
+        self.fee_tier_index != self.tick_spacing
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Tick {
+    pub initialized: bool,
+    /// 价格从左向右穿过这个tick时流动性的变化
+    pub liquidity_net: i128,
+    pub liquidity_gross: u128,
+}
+
+/// 从start_tick_index开始连续TICK_ARRAY_SIZE个tick，间隔是池子的tick_spacing
+#[derive(Clone, Debug, PartialEq)]
+pub struct TickArray {
+    pub start_tick_index: i32,
+    pub whirlpool: Pubkey,
+    pub ticks: Vec<Tick>,
+}
+
+impl TickArray {
+    /// 同时支持定长和动态两种tick array
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() >= 8 && data[..8] == DYNAMIC_TICK_ARRAY_DISCRIMINATOR[..] {
+            return TickArray::unpack_dynamic(data);
+        }
+        check_discriminator(data, &TICK_ARRAY_DISCRIMINATOR, TICK_ARRAY_LEN)?;
+        let ticks = (0..TICK_ARRAY_SIZE as usize)
+            .map(|i| {
+                let offset = 12 + i * TICK_LEN;
+                Tick {
+                    initialized: data[offset] != 0,
+                    liquidity_net: read_i128(data, offset + 1),
+                    liquidity_gross: read_u128(data, offset + 17),
+                }
+            })
+            .collect();
+        Ok(TickArray {
+            start_tick_index: read_i32(data, 8),
+            whirlpool: read_pubkey(data, 12 + TICK_ARRAY_SIZE as usize * TICK_LEN),
+            ticks,
+        })
+    }
+
+    /// 动态tick array里未初始化的tick只占1个标记字节
+    fn unpack_dynamic(data: &[u8]) -> Result<Self, ProgramError> {
+        if data.len() < 60 {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let mut ticks = Vec::with_capacity(TICK_ARRAY_SIZE as usize);
+        let mut offset = 60;
+        for _ in 0..TICK_ARRAY_SIZE {
+            match data.get(offset) {
+                Some(0) => {
+                    ticks.push(Tick::default());
+                    offset += 1;
+                }
+                Some(1) if data.len() >= offset + 1 + DYNAMIC_TICK_DATA_LEN => {
+                    ticks.push(Tick {
+                        initialized: true,
+                        liquidity_net: read_i128(data, offset + 1),
+                        liquidity_gross: read_u128(data, offset + 17),
+                    });
+                    offset += 1 + DYNAMIC_TICK_DATA_LEN;
+                }
+                _ => return Err(ProgramError::InvalidAccountData),
+            }
+        }
+        Ok(TickArray {
+            start_tick_index: read_i32(data, 8),
+            whirlpool: read_pubkey(data, 12),
+            ticks,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct AdaptiveFeeConstants {
+    pub filter_period: u16,
+    pub decay_period: u16,
+    /// 万分之一
+    pub reduction_factor: u16,
+    pub adaptive_fee_control_factor: u32,
+    pub max_volatility_accumulator: u32,
+    pub tick_group_size: u16,
+    pub major_swap_threshold_ticks: u16,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct AdaptiveFeeVariables {
+    pub last_reference_update_timestamp: u64,
+    pub last_major_swap_timestamp: u64,
+    pub volatility_reference: u32,
+    pub tick_group_index_reference: i32,
+    pub volatility_accumulator: u32,
+}
+
+/// 启用adaptive fee的池子才有oracle账户
+#[derive(Clone, Debug, PartialEq)]
+pub struct Oracle {
+    pub whirlpool: Pubkey,
+    /// 在这之前池子不允许swap
+    pub trade_enable_timestamp: u64,
+    pub adaptive_fee_constants: AdaptiveFeeConstants,
+    pub adaptive_fee_variables: AdaptiveFeeVariables,
+}
+
+impl Oracle {
+    pub fn unpack(data: &[u8]) -> Result<Self, ProgramError> {
+        check_discriminator(data, &ORACLE_DISCRIMINATOR, ORACLE_LEN)?;
+        Ok(Oracle {
+            whirlpool: read_pubkey(data, 8),
+            trade_enable_timestamp: read_u64(data, 40),
+            adaptive_fee_constants: AdaptiveFeeConstants {
+                filter_period: read_u16(data, 48),
+                decay_period: read_u16(data, 50),
+                reduction_factor: read_u16(data, 52),
+                adaptive_fee_control_factor: read_u32(data, 54),
+                max_volatility_accumulator: read_u32(data, 58),
+                tick_group_size: read_u16(data, 62),
+                major_swap_threshold_ticks: read_u16(data, 64),
+            },
+            adaptive_fee_variables: AdaptiveFeeVariables {
+                last_reference_update_timestamp: read_u64(data, 82),
+                last_major_swap_timestamp: read_u64(data, 90),
+                volatility_reference: read_u32(data, 98),
+                tick_group_index_reference: read_i32(data, 102),
+                volatility_accumulator: read_u32(data, 106),
+            },
+        })
+    }
+}
+
+/// 包含tick_index的tick array的起始tick，offset按数组个数前后移动
+pub fn tick_array_start_index(tick_index: i32, tick_spacing: u16, offset: i32) -> i32 {
+    let ticks_in_array = TICK_ARRAY_SIZE * tick_spacing as i32;
+    (tick_index.div_euclid(ticks_in_array) + offset) * ticks_in_array
+}
+
+pub fn tick_array_address(program_id: &Pubkey, whirlpool: &Pubkey, start_tick_index: i32) -> Pubkey {
+    Pubkey::find_program_address(&[b"tick_array", whirlpool.as_ref(), start_tick_index.to_string().as_bytes()],
+                                 program_id).0
+}
+
+pub fn oracle_address(program_id: &Pubkey, whirlpool: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"oracle", whirlpool.as_ref()], program_id).0
+}
+
+/// 按链上账户的字段顺序逐个写入，不依赖上面unpack用的偏移，amm和swap的测试也用这里构造账户
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn pack_whirlpool(whirlpool: &Whirlpool) -> Vec<u8> {
+        let mut data = WHIRLPOOL_DISCRIMINATOR.to_vec();
+        data.extend_from_slice(whirlpool.whirlpools_config.as_ref());
+        data.push(255);
+        data.extend_from_slice(&whirlpool.tick_spacing.to_le_bytes());
+        data.extend_from_slice(&whirlpool.fee_tier_index.to_le_bytes());
+        data.extend_from_slice(&whirlpool.fee_rate.to_le_bytes());
+        data.extend_from_slice(&whirlpool.protocol_fee_rate.to_le_bytes());
+        data.extend_from_slice(&whirlpool.liquidity.to_le_bytes());
+        data.extend_from_slice(&whirlpool.sqrt_price.to_le_bytes());
+        data.extend_from_slice(&whirlpool.tick_current_index.to_le_bytes());
+        //protocol_fee_owed_a/b
+        data.extend_from_slice(&[1; 16]);
+        data.extend_from_slice(whirlpool.token_mint_a.as_ref());
+        data.extend_from_slice(whirlpool.token_vault_a.as_ref());
+        //fee_growth_global_a
+        data.extend_from_slice(&[2; 16]);
+        data.extend_from_slice(whirlpool.token_mint_b.as_ref());
+        data.extend_from_slice(whirlpool.token_vault_b.as_ref());
+        //fee_growth_global_b、reward_last_updated_timestamp和3个reward info
+        data.extend_from_slice(&[3; 16 + 8 + 3 * 128]);
+        data
+    }
+
+    pub(crate) fn pack_tick_array(tick_array: &TickArray) -> Vec<u8> {
+        let mut data = TICK_ARRAY_DISCRIMINATOR.to_vec();
+        data.extend_from_slice(&tick_array.start_tick_index.to_le_bytes());
+        for tick in &tick_array.ticks {
+            data.push(tick.initialized as u8);
+            data.extend_from_slice(&tick.liquidity_net.to_le_bytes());
+            data.extend_from_slice(&tick.liquidity_gross.to_le_bytes());
+            //fee_growth_outside_a/b和3个reward_growth_outside
+            data.extend_from_slice(&[4; 5 * 16]);
+        }
+        data.extend_from_slice(tick_array.whirlpool.as_ref());
+        data
+    }
+
+    pub(crate) fn pack_dynamic_tick_array(tick_array: &TickArray) -> Vec<u8> {
+        let mut data = DYNAMIC_TICK_ARRAY_DISCRIMINATOR.to_vec();
+        data.extend_from_slice(&tick_array.start_tick_index.to_le_bytes());
+        data.extend_from_slice(tick_array.whirlpool.as_ref());
+        let bitmap = tick_array.ticks.iter().enumerate()
+            .filter(|(_, x)| x.initialized)
+            .fold(0u128, |bitmap, (i, _)| bitmap | (1 << i));
+        data.extend_from_slice(&bitmap.to_le_bytes());
+        for tick in &tick_array.ticks {
+            if !tick.initialized {
+                data.push(0);
+                continue;
+            }
+            data.push(1);
+            data.extend_from_slice(&tick.liquidity_net.to_le_bytes());
+            data.extend_from_slice(&tick.liquidity_gross.to_le_bytes());
+            data.extend_from_slice(&[4; 5 * 16]);
+        }
+        data
+    }
+
+    pub(crate) fn pack_oracle(oracle: &Oracle) -> Vec<u8> {
+        let constants = &oracle.adaptive_fee_constants;
+        let variables = &oracle.adaptive_fee_variables;
+        let mut data = ORACLE_DISCRIMINATOR.to_vec();
+        data.extend_from_slice(oracle.whirlpool.as_ref());
+        data.extend_from_slice(&oracle.trade_enable_timestamp.to_le_bytes());
+        data.extend_from_slice(&constants.filter_period.to_le_bytes());
+        data.extend_from_slice(&constants.decay_period.to_le_bytes());
+        data.extend_from_slice(&constants.reduction_factor.to_le_bytes());
+        data.extend_from_slice(&constants.adaptive_fee_control_factor.to_le_bytes());
+        data.extend_from_slice(&constants.max_volatility_accumulator.to_le_bytes());
+        data.extend_from_slice(&constants.tick_group_size.to_le_bytes());
+        data.extend_from_slice(&constants.major_swap_threshold_ticks.to_le_bytes());
+        data.extend_from_slice(&[5; 16]);
+        data.extend_from_slice(&variables.last_reference_update_timestamp.to_le_bytes());
+        data.extend_from_slice(&variables.last_major_swap_timestamp.to_le_bytes());
+        data.extend_from_slice(&variables.volatility_reference.to_le_bytes());
+        data.extend_from_slice(&variables.tick_group_index_reference.to_le_bytes());
+        data.extend_from_slice(&variables.volatility_accumulator.to_le_bytes());
+        data.extend_from_slice(&[6; 16 + 128]);
+        data
+    }
+
+    pub(crate) fn whirlpool(tick_spacing: u16, liquidity: u128, sqrt_price: u128, tick_current_index: i32) -> Whirlpool {
+        Whirlpool {
+            whirlpools_config: Pubkey::new_unique(),
+            tick_spacing,
+            fee_tier_index: tick_spacing,
+            fee_rate: 3000,
+            protocol_fee_rate: 1300,
+            liquidity,
+            sqrt_price,
+            tick_current_index,
+            token_mint_a: Pubkey::new_unique(),
+            token_vault_a: Pubkey::new_unique(),
+            token_mint_b: Pubkey::new_unique(),
+            token_vault_b: Pubkey::new_unique(),
+        }
+    }
+
+    /// offsets里的tick已初始化，liquidity_net都是net
+    pub(crate) fn tick_array(whirlpool: Pubkey, start_tick_index: i32, offsets: &[usize], net: i128) -> TickArray {
+        let mut ticks = vec![Tick::default(); TICK_ARRAY_SIZE as usize];
+        for offset in offsets {
+            ticks[*offset] = Tick {
+                initialized: true,
+                liquidity_net: net,
+                liquidity_gross: net.unsigned_abs(),
+            };
+        }
+        TickArray {
+            start_tick_index,
+            whirlpool,
+            ticks,
+        }
+    }
+
+    pub(crate) fn oracle(whirlpool: Pubkey, timestamp: u64) -> Oracle {
+        Oracle {
+            whirlpool,
+            trade_enable_timestamp: 0,
+            adaptive_fee_constants: AdaptiveFeeConstants {
+                filter_period: 30,
+                decay_period: 600,
+                reduction_factor: 5000,
+                adaptive_fee_control_factor: 4000,
+                max_volatility_accumulator: 350_000,
+                tick_group_size: 64,
+                major_swap_threshold_ticks: 64,
+            },
+            adaptive_fee_variables: AdaptiveFeeVariables {
+                last_reference_update_timestamp: timestamp,
+                last_major_swap_timestamp: timestamp,
+                volatility_reference: 10_000,
+                tick_group_index_reference: 0,
+                volatility_accumulator: 10_000,
+            },
+        }
+    }
+
+    #[test]
+    fn test_unpack_whirlpool() {
+        let mut expected = whirlpool(64, 1_000_000_000_000, 1u128 << 64, -1);
+        expected.fee_tier_index = 1024;
+        let data = pack_whirlpool(&expected);
+        assert_eq!(data.len(), WHIRLPOOL_LEN);
+        let whirlpool = Whirlpool::unpack(&data).unwrap();
+        assert_eq!(whirlpool, expected);
+        assert!(whirlpool.is_adaptive_fee());
+
+        assert!(Whirlpool::unpack(&data[..WHIRLPOOL_LEN - 1]).is_err());
+        let mut wrong = data.clone();
+        wrong[0] ^= 1;
+        assert!(Whirlpool::unpack(&wrong).is_err());
+    }
+
+    #[test]
+    fn test_unpack_tick_array() {
+        let mut expected = tick_array(Pubkey::new_unique(), -5632, &[0, 42, 87], 1_000);
+        expected.ticks[42].liquidity_net = -2_000;
+        let data = pack_tick_array(&expected);
+        assert_eq!(data.len(), TICK_ARRAY_LEN);
+        assert_eq!(TickArray::unpack(&data).unwrap(), expected);
+        assert!(TickArray::unpack(&data[..TICK_ARRAY_LEN - 1]).is_err());
+    }
+
+    #[test]
+    fn test_unpack_dynamic_tick_array() {
+        let mut expected = tick_array(Pubkey::new_unique(), 5632, &[1, 2, 87], -3_000);
+        expected.ticks[2].liquidity_net = i128::MAX;
+        expected.ticks[2].liquidity_gross = u128::MAX;
+        let data = pack_dynamic_tick_array(&expected);
+        assert_eq!(data.len(), 60 + TICK_ARRAY_SIZE as usize + 3 * DYNAMIC_TICK_DATA_LEN);
+        assert_eq!(TickArray::unpack(&data).unwrap(), expected);
+
+        //最后一个tick的数据不完整
+        assert!(TickArray::unpack(&data[..data.len() - 1]).is_err());
+        //标记字节只能是0或1
+        let mut wrong = data.clone();
+        wrong[60] = 2;
+        assert!(TickArray::unpack(&wrong).is_err());
+    }
+
+    #[test]
+    fn test_unpack_oracle() {
+        let mut expected = oracle(Pubkey::new_unique(), 1_700_000_000);
+        expected.trade_enable_timestamp = 1_800_000_000;
+        expected.adaptive_fee_variables.tick_group_index_reference = -7;
+        let data = pack_oracle(&expected);
+        assert_eq!(data.len(), ORACLE_LEN);
+        assert_eq!(Oracle::unpack(&data).unwrap(), expected);
+        assert!(Oracle::unpack(&data[..ORACLE_LEN - 1]).is_err());
+    }
+
+    #[test]
+    fn test_tick_array_start_index() {
+        assert_eq!(tick_array_start_index(0, 64, 0), 0);
+        assert_eq!(tick_array_start_index(5631, 64, 0), 0);
+        assert_eq!(tick_array_start_index(5632, 64, 0), 5632);
+        assert_eq!(tick_array_start_index(-1, 64, 0), -5632);
+        assert_eq!(tick_array_start_index(-1, 64, -1), -11264);
+        assert_eq!(tick_array_start_index(100, 1, 2), 264);
+    }
+}
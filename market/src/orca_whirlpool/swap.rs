@@ -0,0 +1,368 @@
+//! 按链上swap_manager模拟一次指定输入的swap：逐段推进sqrt price，跨过已初始化的tick时更新流动性
+
+use crate::orca_whirlpool::math::{MAX_SQRT_PRICE_X64, MAX_TICK_INDEX, MIN_SQRT_PRICE_X64, MIN_TICK_INDEX,
+                                  PROTOCOL_FEE_RATE_MUL_VALUE, compute_swap, sqrt_price_from_tick_index,
+                                  tick_index_from_sqrt_price};
+use crate::orca_whirlpool::state::{AdaptiveFeeConstants, AdaptiveFeeVariables, Oracle, Tick, TickArray, TICK_ARRAY_SIZE,
+                                   Whirlpool};
+
+/// 静态费率加上adaptive fee的上限，百万分之一
+const FEE_RATE_HARD_LIMIT: u32 = 100_000;
+const MAX_REFERENCE_AGE: u64 = 3_600;
+const VOLATILITY_ACCUMULATOR_SCALE_FACTOR: u64 = 10_000;
+const REDUCTION_FACTOR_DENOMINATOR: u64 = 10_000;
+const ADAPTIVE_FEE_CONTROL_FACTOR_DENOMINATOR: u128 = 100_000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SwapResult {
+    pub amount_in: u64,
+    pub amount_out: u64,
+    /// 包含protocol_fee
+    pub fee_amount: u64,
+    pub protocol_fee: u64,
+    /// 第一段成交的费率，百万分之一
+    pub fee_rate: u32,
+    pub next_sqrt_price: u128,
+    pub next_tick_index: i32,
+}
+
+/// swap经过的tick array，按价格移动方向排列，和swap指令传入的3个tick array对应
+pub struct TickSequence<'a> {
+    arrays: Vec<&'a TickArray>,
+    tick_spacing: i32,
+    a_to_b: bool,
+}
+
+impl<'a> TickSequence<'a> {
+    pub fn new(arrays: Vec<&'a TickArray>, tick_spacing: u16, a_to_b: bool) -> Self {
+        TickSequence {
+            arrays,
+            tick_spacing: tick_spacing as i32,
+            a_to_b,
+        }
+    }
+
+    /// 价格移动方向上的下一个已初始化tick。当前tick array里没有时返回数组边界上的tick(不改变流动性)，
+    /// 下一轮从下一个tick array继续。tick array用完时返回None，链上会报错，报价按深度不够处理
+    pub fn next_initialized_tick(&self, tick_index: i32) -> Option<(i32, Option<Tick>)> {
+        let span = TICK_ARRAY_SIZE * self.tick_spacing;
+        for array in self.arrays.iter() {
+            let start = array.start_tick_index;
+            if self.a_to_b {
+                //小于等于当前tick
+                if tick_index < start {
+                    continue;
+                }
+                let from = (tick_index - start).div_euclid(self.tick_spacing).min(TICK_ARRAY_SIZE - 1);
+                for offset in (0..=from).rev() {
+                    let tick = array.ticks[offset as usize];
+                    if tick.initialized {
+                        return Some((start + offset * self.tick_spacing, Some(tick)));
+                    }
+                }
+                return Some((start, None));
+            } else {
+                //大于当前tick
+                if tick_index >= start + span - self.tick_spacing {
+                    continue;
+                }
+                let from = if tick_index < start { 0 } else { (tick_index - start).div_euclid(self.tick_spacing) + 1 };
+                for offset in from..TICK_ARRAY_SIZE {
+                    let tick = array.ticks[offset as usize];
+                    if tick.initialized {
+                        return Some((start + offset * self.tick_spacing, Some(tick)));
+                    }
+                }
+                return Some((start + span - self.tick_spacing, None));
+            }
+        }
+        None
+    }
+}
+
+/// 启用adaptive fee时按tick group累积波动率，和链上FeeRateManager一致
+struct AdaptiveFee {
+    constants: AdaptiveFeeConstants,
+    variables: AdaptiveFeeVariables,
+    tick_group_index: i32,
+    a_to_b: bool,
+}
+
+impl AdaptiveFee {
+    fn new(oracle: &Oracle, tick_current_index: i32, timestamp: u64, a_to_b: bool) -> Self {
+        let constants = oracle.adaptive_fee_constants;
+        let mut fee = AdaptiveFee {
+            constants,
+            variables: oracle.adaptive_fee_variables,
+            tick_group_index: tick_current_index.div_euclid(constants.tick_group_size.max(1) as i32),
+            a_to_b,
+        };
+        fee.update_reference(timestamp);
+        fee
+    }
+
+    /// 距离上次交易的时间决定波动率参考值是保留、衰减还是清零
+    fn update_reference(&mut self, timestamp: u64) {
+        let variables = &mut self.variables;
+        let reference_age = timestamp.saturating_sub(variables.last_reference_update_timestamp);
+        if reference_age > MAX_REFERENCE_AGE {
+            variables.volatility_reference = 0;
+            variables.tick_group_index_reference = self.tick_group_index;
+            variables.last_reference_update_timestamp = timestamp;
+            return;
+        }
+
+        let last_timestamp = variables.last_reference_update_timestamp.max(variables.last_major_swap_timestamp);
+        let elapsed = timestamp.saturating_sub(last_timestamp);
+        if elapsed < self.constants.filter_period as u64 {
+            return;
+        }
+        variables.tick_group_index_reference = self.tick_group_index;
+        variables.last_reference_update_timestamp = timestamp;
+        variables.volatility_reference = if elapsed < self.constants.decay_period as u64 {
+            (variables.volatility_accumulator as u64 * self.constants.reduction_factor as u64 / REDUCTION_FACTOR_DENOMINATOR) as u32
+        } else {
+            0
+        };
+    }
+
+    fn update_volatility_accumulator(&mut self) {
+        let index_delta = (self.variables.tick_group_index_reference as i64 - self.tick_group_index as i64).unsigned_abs();
+        let accumulator = self.variables.volatility_reference as u64 + index_delta * VOLATILITY_ACCUMULATOR_SCALE_FACTOR;
+        self.variables.volatility_accumulator = accumulator.min(self.constants.max_volatility_accumulator as u64) as u32;
+    }
+
+    fn fee_rate(&self) -> u32 {
+        let crossed = self.variables.volatility_accumulator as u128 * self.constants.tick_group_size as u128;
+        let denominator = ADAPTIVE_FEE_CONTROL_FACTOR_DENOMINATOR *
+            (VOLATILITY_ACCUMULATOR_SCALE_FACTOR as u128 * VOLATILITY_ACCUMULATOR_SCALE_FACTOR as u128);
+        let fee_rate = (self.constants.adaptive_fee_control_factor as u128 * crossed * crossed).div_ceil(denominator);
+        fee_rate.min(FEE_RATE_HARD_LIMIT as u128) as u32
+    }
+
+    /// 每段成交不超过当前tick group的边界。没有流动性时价格直接跳过，不按tick group推进
+    fn bounded_sqrt_price_target(&self, sqrt_price_target: u128, liquidity: u128) -> (u128, bool) {
+        if liquidity == 0 {
+            return (sqrt_price_target, true);
+        }
+        let tick_group_size = self.constants.tick_group_size as i32;
+        let boundary_tick = if self.a_to_b {
+            self.tick_group_index * tick_group_size
+        } else {
+            self.tick_group_index * tick_group_size + tick_group_size
+        };
+        let boundary = sqrt_price_from_tick_index(boundary_tick.clamp(MIN_TICK_INDEX, MAX_TICK_INDEX));
+        if self.a_to_b {
+            (sqrt_price_target.max(boundary), false)
+        } else {
+            (sqrt_price_target.min(boundary), false)
+        }
+    }
+
+    /// 停在tick group边界上时进入下一个group，否则按停下的tick重新计算
+    fn advance_tick_group(&mut self, reached_boundary: bool, tick_index: i32) {
+        if !reached_boundary {
+            self.tick_group_index = tick_index.div_euclid(self.constants.tick_group_size.max(1) as i32);
+        } else if self.a_to_b {
+            self.tick_group_index -= 1;
+        } else {
+            self.tick_group_index += 1;
+        }
+    }
+}
+
+fn cross_tick(liquidity: u128, liquidity_net: i128, a_to_b: bool) -> Option<u128> {
+    //从右向左穿过tick时流动性变化取反
+    let delta = if a_to_b { liquidity_net.checked_neg()? } else { liquidity_net };
+    if delta >= 0 {
+        liquidity.checked_add(delta as u128)
+    } else {
+        liquidity.checked_sub(delta.unsigned_abs())
+    }
+}
+
+/// 不限制价格，输入全部成交才返回结果。oracle只在池子启用adaptive fee时传入，timestamp用于衰减波动率
+pub fn simulate_swap(whirlpool: &Whirlpool,
+                     sequence: &TickSequence,
+                     oracle: Option<&Oracle>,
+                     amount: u64,
+                     a_to_b: bool,
+                     timestamp: u64) -> Option<SwapResult> {
+    let sqrt_price_limit = if a_to_b { MIN_SQRT_PRICE_X64 } else { MAX_SQRT_PRICE_X64 };
+    let mut adaptive_fee = oracle
+        .filter(|x| x.adaptive_fee_constants.tick_group_size > 0)
+        .map(|x| AdaptiveFee::new(x, whirlpool.tick_current_index, timestamp, a_to_b));
+
+    let mut amount_remaining = amount;
+    let mut amount_out: u64 = 0;
+    let mut fee_amount: u64 = 0;
+    let mut protocol_fee: u64 = 0;
+    let mut first_fee_rate = None;
+    let mut liquidity = whirlpool.liquidity;
+    let mut sqrt_price = whirlpool.sqrt_price;
+    let mut tick_index = whirlpool.tick_current_index;
+
+    while amount_remaining > 0 && sqrt_price != sqrt_price_limit {
+        let (next_tick_index, next_tick) = sequence.next_initialized_tick(tick_index)?;
+        let next_tick_sqrt_price = sqrt_price_from_tick_index(next_tick_index.clamp(MIN_TICK_INDEX, MAX_TICK_INDEX));
+        let sqrt_price_target = if a_to_b {
+            next_tick_sqrt_price.max(sqrt_price_limit)
+        } else {
+            next_tick_sqrt_price.min(sqrt_price_limit)
+        };
+
+        loop {
+            let (fee_rate, bounded_target, skipped) = match adaptive_fee.as_mut() {
+                Some(fee) => {
+                    fee.update_volatility_accumulator();
+                    let fee_rate = (whirlpool.fee_rate as u32 + fee.fee_rate()).min(FEE_RATE_HARD_LIMIT);
+                    let (bounded_target, skipped) = fee.bounded_sqrt_price_target(sqrt_price_target, liquidity);
+                    (fee_rate, bounded_target, skipped)
+                }
+                None => (whirlpool.fee_rate as u32, sqrt_price_target, true),
+            };
+            first_fee_rate.get_or_insert(fee_rate);
+
+            let step = compute_swap(amount_remaining, fee_rate, liquidity, sqrt_price, bounded_target, a_to_b)?;
+            amount_remaining = amount_remaining.checked_sub(step.amount_in)?.checked_sub(step.fee_amount)?;
+            amount_out = amount_out.checked_add(step.amount_out)?;
+            fee_amount = fee_amount.checked_add(step.fee_amount)?;
+            protocol_fee += (step.fee_amount as u128 * whirlpool.protocol_fee_rate as u128 / PROTOCOL_FEE_RATE_MUL_VALUE) as u64;
+
+            if step.next_sqrt_price == next_tick_sqrt_price {
+                if let Some(tick) = next_tick {
+                    liquidity = cross_tick(liquidity, tick.liquidity_net, a_to_b)?;
+                }
+                tick_index = if a_to_b { next_tick_index - 1 } else { next_tick_index };
+            } else if step.next_sqrt_price != sqrt_price {
+                tick_index = tick_index_from_sqrt_price(step.next_sqrt_price);
+            }
+            sqrt_price = step.next_sqrt_price;
+
+            if let Some(fee) = adaptive_fee.as_mut() {
+                fee.advance_tick_group(!skipped && sqrt_price == bounded_target, tick_index);
+            }
+            if amount_remaining == 0 || sqrt_price == sqrt_price_target {
+                break;
+            }
+        }
+    }
+
+    if amount_remaining > 0 {
+        return None;
+    }
+    Some(SwapResult {
+        amount_in: amount,
+        amount_out,
+        fee_amount,
+        protocol_fee,
+        fee_rate: first_fee_rate.unwrap_or(whirlpool.fee_rate as u32),
+        next_sqrt_price: sqrt_price,
+        next_tick_index: tick_index,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orca_whirlpool::math::{compute_swap, sqrt_price_from_tick_index};
+    use crate::orca_whirlpool::state::tests::{oracle, tick_array, whirlpool};
+    use solana_program::pubkey::Pubkey;
+
+    const LIQUIDITY: u128 = 1_000_000_000_000;
+
+    #[test]
+    fn test_next_initialized_tick_a_to_b() {
+        let key = Pubkey::new_unique();
+        let array = tick_array(key, 0, &[1], 1);
+        let sequence = TickSequence::new(vec![&array], 64, true);
+        assert_eq!(sequence.next_initialized_tick(100), Some((64, Some(array.ticks[1]))));
+        //当前tick正好是已初始化的tick时包含它
+        assert_eq!(sequence.next_initialized_tick(64), Some((64, Some(array.ticks[1]))));
+        assert_eq!(sequence.next_initialized_tick(63), Some((0, None)));
+        assert_eq!(sequence.next_initialized_tick(-1), None);
+
+        let empty = tick_array(key, 0, &[], 0);
+        let previous = tick_array(key, -5632, &[87], 1);
+        let sequence = TickSequence::new(vec![&empty, &previous], 64, true);
+        assert_eq!(sequence.next_initialized_tick(100), Some((0, None)));
+        assert_eq!(sequence.next_initialized_tick(-1), Some((-64, Some(previous.ticks[87]))));
+    }
+
+    #[test]
+    fn test_next_initialized_tick_b_to_a() {
+        let key = Pubkey::new_unique();
+        let array = tick_array(key, 0, &[1], 1);
+        let sequence = TickSequence::new(vec![&array], 64, false);
+        assert_eq!(sequence.next_initialized_tick(0), Some((64, Some(array.ticks[1]))));
+        assert_eq!(sequence.next_initialized_tick(-100), Some((64, Some(array.ticks[1]))));
+        //不包含当前tick
+        assert_eq!(sequence.next_initialized_tick(64), Some((5568, None)));
+        assert_eq!(sequence.next_initialized_tick(5568), None);
+    }
+
+    #[test]
+    fn test_swap_within_tick() {
+        let pool = whirlpool(64, LIQUIDITY, 1u128 << 64, 0);
+        let array = tick_array(Pubkey::new_unique(), 0, &[], 0);
+        let sequence = TickSequence::new(vec![&array], 64, false);
+        let result = simulate_swap(&pool, &sequence, None, 1_000_000, false, 0).unwrap();
+        //扣掉0.3%手续费后997000个B，价格接近1
+        assert_eq!(result.amount_out, 996_999);
+        assert_eq!(result.fee_amount, 3000);
+        assert_eq!(result.protocol_fee, 390);
+        assert_eq!(result.fee_rate, 3000);
+        assert_eq!(result.next_sqrt_price, 18_446_762_465_113_393_104);
+        assert_eq!(result.next_tick_index, 0);
+    }
+
+    #[test]
+    fn test_swap_crosses_tick() {
+        let pool = whirlpool(64, LIQUIDITY, sqrt_price_from_tick_index(100), 100);
+        let key = Pubkey::new_unique();
+        //从右向左穿过tick 64时流动性减少一半
+        let array = tick_array(key, 0, &[1], LIQUIDITY as i128 / 2);
+        let previous = tick_array(key, -5632, &[], 0);
+        let sequence = TickSequence::new(vec![&array, &previous], 64, true);
+        let amount = 2_500_000_000;
+        let result = simulate_swap(&pool, &sequence, None, amount, true, 0).unwrap();
+
+        let first = compute_swap(amount, 3000, LIQUIDITY, pool.sqrt_price, sqrt_price_from_tick_index(64), true).unwrap();
+        assert_eq!(first.next_sqrt_price, sqrt_price_from_tick_index(64));
+        let remaining = amount - first.amount_in - first.fee_amount;
+        let second = compute_swap(remaining, 3000, LIQUIDITY / 2, first.next_sqrt_price, sqrt_price_from_tick_index(0), true)
+            .unwrap();
+        assert!(second.next_sqrt_price > sqrt_price_from_tick_index(0));
+        assert_eq!(result.amount_out, first.amount_out + second.amount_out);
+        assert_eq!(result.fee_amount, first.fee_amount + second.fee_amount);
+        assert_eq!(result.next_sqrt_price, second.next_sqrt_price);
+        assert!(result.next_tick_index > 0 && result.next_tick_index < 64);
+
+        //推过tick 0之后tick array用完，按深度不够处理
+        let sequence = TickSequence::new(vec![&array], 64, true);
+        assert_eq!(simulate_swap(&pool, &sequence, None, amount * 4, true, 0), None);
+    }
+
+    #[test]
+    fn test_adaptive_fee() {
+        let timestamp = 1_700_000_000;
+        let mut pool = whirlpool(64, LIQUIDITY, 1u128 << 64, 0);
+        pool.fee_tier_index = 1024;
+        let array = tick_array(Pubkey::new_unique(), 0, &[], 0);
+        let sequence = TickSequence::new(vec![&array], 64, false);
+        let static_fee = simulate_swap(&pool, &sequence, None, 1_000_000, false, timestamp).unwrap();
+
+        //波动率参考值10000，还在filter_period内不衰减：4000 * (10000 * 64)^2 / 10^13向上取整是164
+        let oracle = oracle(Pubkey::new_unique(), timestamp);
+        let result = simulate_swap(&pool, &sequence, Some(&oracle), 1_000_000, false, timestamp).unwrap();
+        assert_eq!(result.fee_rate, 3164);
+        assert_eq!(result.fee_amount, 3164);
+        assert!(result.amount_out < static_fee.amount_out);
+
+        //参考值超过MAX_REFERENCE_AGE时清零，第一段只收静态费率
+        let result = simulate_swap(&pool, &sequence, Some(&oracle), 1_000_000, false, timestamp + MAX_REFERENCE_AGE + 1)
+            .unwrap();
+        assert_eq!(result.fee_rate, 3000);
+        assert_eq!(result.amount_out, static_fee.amount_out);
+    }
+}
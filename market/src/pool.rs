@@ -2,8 +2,8 @@ use std::collections::HashMap;
 use crate::market::MarketType;
 use serde::{Serialize, Deserialize};
 use solana_program::pubkey::Pubkey;
-use std::fs;
-use crate::{orca, raydium};
+use anyhow::Result;
+use crate::dex::DEXES;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct PoolInfo {
@@ -29,6 +29,9 @@ pub struct PoolResponse {
     pub base_value: String,
     pub rate: Option<f32>,
     pub data: HashMap<String, String>,
+    //计算rate使用的账户数据中最早的slot
+    #[serde(default)]
+    pub slot: Option<u64>,
 }
 
 
@@ -54,62 +57,17 @@ pub struct TokenInfo {
 }
 
 impl RawPool {
-    pub fn load_all_pool_data(market: Option<String>) -> Vec<RawPool> {
+    //market为None时加载全部市场，否则只加载id匹配的市场
+    pub fn load_all_pool_data(market: Option<String>) -> Result<Vec<RawPool>> {
         let mut vec = vec![];
-
-        let mut need_raydium = false;
-        let mut need_orca = false;
-        match market {
-            Some(name) => {
-                if name.eq("raydium".trim()) {
-                    need_raydium = true;
-                } else if name.eq("orca".trim()) {
-                    need_orca = true;
-                } else {
-                    return vec;
-                }
+        for dex in DEXES {
+            if market.as_ref().is_some_and(|x| x != dex.id) {
+                continue;
             }
-            None => {
-                need_orca = true;
-                need_raydium = true;
+            if let Some(load_raw_pools) = dex.load_raw_pools {
+                vec.extend(load_raw_pools()?);
             }
         }
-
-        if need_raydium {
-            let raydium_pool_path = "./resource/pool/raydium.json".to_string();
-            let raydium_raw = fs::read_to_string(raydium_pool_path).expect("Error read file");
-            let raydium_vec: Vec<raydium::data::RawPoolInfo> = serde_json::from_str(&raydium_raw).unwrap();
-            for raydium in &raydium_vec {
-                vec.push(RawPool {
-                    market: "Raydium".to_string(),
-                    pool_key: raydium.id.clone(),
-                    quote_mint: raydium.quote_mint.clone(),
-                    base_mint: raydium.base_mint.clone(),
-                    lp_mint: raydium.lp_mint.clone(),
-                    quote_token: None,
-                    base_token: None
-                })
-            };
-        }
-
-        if need_orca {
-            let orca_pool_path = "./resource/pool/orca.json".to_string();
-            let orca_raw = fs::read_to_string(orca_pool_path).expect("Error read file");
-            let orca_vec: Vec<orca::data::RawMarketPool> = serde_json::from_str(&orca_raw).unwrap();
-            for orca in &orca_vec {
-                vec.push(RawPool {
-                    market: "Orca".to_string(),
-                    pool_key: orca.account.clone(),
-                    quote_mint: orca.quote.mint.clone(),
-                    base_mint: orca.base.mint.clone(),
-                    lp_mint: orca.pool_mint.clone(),
-                    quote_token: None,
-                    base_token: None
-                })
-            };
-        }
-
-        vec
+        Ok(vec)
     }
 }
-
@@ -0,0 +1,115 @@
+//! Raydium AMM v4，quote是pc，base是coin
+
+use crate::amm::{Amm, AmmError, AmmQuote, AccountMap, FeeSide, SwapDirection, fee_rate, get_account, load_token_amount};
+use crate::raydium::stats::AmmInfo;
+use crate::serum::state::OpenOrders;
+use anyhow::Result;
+use solana_program::pubkey::Pubkey;
+use std::mem::size_of;
+
+pub struct RaydiumAmm {
+    amm_key: Pubkey,
+    pc_vault: Pubkey,
+    coin_vault: Pubkey,
+    /// 池子文件里没有时从AmmInfo里读
+    open_orders: Option<Pubkey>,
+    amm_info: Option<AmmInfo>,
+    /// 包含open orders里的资金并扣除待提取的pnl
+    total_pc: u64,
+    total_coin: u64,
+}
+
+impl RaydiumAmm {
+    pub fn new(amm_key: Pubkey, pc_vault: Pubkey, coin_vault: Pubkey, open_orders: Option<Pubkey>) -> Self {
+        RaydiumAmm {
+            amm_key,
+            pc_vault,
+            coin_vault,
+            open_orders,
+            amm_info: None,
+            total_pc: 0,
+            total_coin: 0,
+        }
+    }
+}
+
+/// 账户长度不对时直接按Pod读取会panic，需要先检查
+pub fn load_amm_info(key: &Pubkey, data: &[u8]) -> Result<AmmInfo> {
+    if data.len() != size_of::<AmmInfo>() {
+        return Err(AmmError::AccountDecode(*key).into());
+    }
+    Ok(bytemuck::pod_read_unaligned::<AmmInfo>(data))
+}
+
+impl Amm for RaydiumAmm {
+    fn key(&self) -> Pubkey {
+        self.amm_key
+    }
+
+    fn accounts_needed(&self) -> Vec<Pubkey> {
+        let mut keys = vec![self.amm_key, self.pc_vault, self.coin_vault];
+        keys.extend(self.open_orders);
+        keys
+    }
+
+    fn update(&mut self, accounts: &AccountMap) -> Result<()> {
+        let amm_info = load_amm_info(&self.amm_key, &get_account(accounts, &self.amm_key)?.data)?;
+        let open_orders_key = self.open_orders.unwrap_or(amm_info.open_orders);
+        let open_orders = OpenOrders::unpack(&get_account(accounts, &open_orders_key)?.data)
+            .map_err(|_| AmmError::AccountDecode(open_orders_key))?;
+
+        let pc_amount = load_token_amount(accounts, &self.pc_vault)?;
+        let coin_amount = load_token_amount(accounts, &self.coin_vault)?;
+        let (total_pc, total_coin) = amm_info.total_without_take_pnl(pc_amount, coin_amount, &open_orders);
+
+        self.open_orders = Some(open_orders_key);
+        self.amm_info = Some(amm_info);
+        self.total_pc = total_pc;
+        self.total_coin = total_coin;
+        Ok(())
+    }
+
+    fn unavailable_reason(&self) -> Option<String> {
+        match &self.amm_info {
+            Some(amm_info) => amm_info.swap_disabled_reason(),
+            None => Some("amm not loaded".to_string()),
+        }
+    }
+
+    fn reserves(&self) -> (u64, u64) {
+        (self.total_pc, self.total_coin)
+    }
+
+    /// 与链上swap_base_in一致：手续费向上取整，恒定乘积向下取整
+    fn quote(&self, amount_in: u64, direction: SwapDirection) -> Result<AmmQuote> {
+        let amm_info = self.amm_info.as_ref().ok_or(AmmError::AccountDecode(self.amm_key))?;
+        let (source_reserve, destination_reserve) = match direction {
+            SwapDirection::QuoteToBase => (self.total_pc, self.total_coin),
+            SwapDirection::BaseToQuote => (self.total_coin, self.total_pc),
+        };
+
+        let fees = &amm_info.fees;
+        let denominator = (fees.swap_fee_denominator as u128).max(1);
+        let swap_fee = (amount_in as u128 * fees.swap_fee_numerator as u128).div_ceil(denominator);
+        let amount_in_after_fee = (amount_in as u128).saturating_sub(swap_fee);
+        let amount_out = match source_reserve as u128 + amount_in_after_fee {
+            0 => 0,
+            x => destination_reserve as u128 * amount_in_after_fee / x,
+        };
+        //pnl部分之后由take_pnl提取给协议
+        let protocol_fee = swap_fee * fees.pnl_numerator as u128 / (fees.pnl_denominator as u128).max(1);
+
+        Ok(AmmQuote {
+            amount_in,
+            amount_out: amount_out as u64,
+            source_reserve,
+            destination_reserve,
+            fee_rate: fee_rate(fees.swap_fee_numerator, fees.swap_fee_denominator),
+            fee_side: FeeSide::Source,
+            fee: swap_fee as u64,
+            protocol_fee: protocol_fee as u64,
+            amp: None,
+            data: Default::default(),
+        })
+    }
+}
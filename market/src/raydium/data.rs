@@ -6,11 +6,15 @@ use std::fs;
 use std::collections::HashMap;
 use solana_program::pubkey::Pubkey;
 use rust_decimal::prelude::FromStr;
-use market::{MarketPool, MarketOptMap, MarketType};
-use pool::PoolInfo;
+use market::{MarketPool, MarketType};
+use pool::{PoolInfo, RawPool};
 
-const RAYDIUM_MARKET: &str = "Raydium";
+pub const RAYDIUM_MARKET: &str = "Raydium";
 const RAYDIUM_PROGRAM_ID: &str = "675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8";
+//pool_info和pool_list用的池子
+pub const POOL_PATH: &str = "./resource/pool/raydium.json";
+//路由用的池子，也是serum订单簿的来源
+pub const MARKET_POOL_PATH: &str = "./raydium_pool.json";
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RawMarketPool {
@@ -84,15 +88,67 @@ pub struct RawPoolInfo {
 
 }
 
-pub fn load_data_from_file(quote_mint: &String, base_mint: &String) -> Result<MarketOptMap> {
-    let market_main_path = "./raydium_pool.json".to_string();
+impl RawPoolInfo {
+    pub fn pool_info(&self) -> Option<PoolInfo> {
+        let mut data = HashMap::new();
+        data.insert("ammAuthority".to_string(), self.authority.clone());
+        data.insert("ammOpenOrders".to_string(), self.open_orders.clone());
+        data.insert("ammTargetOrders".to_string(), self.target_orders.clone());
+        data.insert("poolCoinTokenAccount".to_string(), self.base_vault.clone());
+        data.insert("poolPcTokenAccount".to_string(), self.quote_vault.clone());
+        data.insert("poolWithdrawQueue".to_string(), self.withdraw_queue.clone());
+        data.insert("poolTempLpTokenAccount".to_string(), self.temp_lp_token_account.clone());
+        data.insert("marketProgramId".to_string(), self.market_program_id.clone());
+        data.insert("marketId".to_string(), self.market_id.clone());
+        data.insert("marketBids".to_string(), self.market_bids.clone());
+        data.insert("marketAsks".to_string(), self.market_asks.clone());
+        data.insert("marketBaseVault".to_string(), self.market_base_vault.clone());
+        data.insert("marketQuoteVault".to_string(), self.market_quote_vault.clone());
+        data.insert("marketEventQueue".to_string(), self.market_event_queue.clone());
+        data.insert("marketVaultSigner".to_string(), self.market_vault_signer.clone());
+
+        Some(PoolInfo {
+            market_type: MarketType::Raydium(RAYDIUM_MARKET.to_string(), RAYDIUM_PROGRAM_ID.to_string()),
+            pool_key: Pubkey::from_str(&self.id).ok()?,
+            quote_mint_key: Pubkey::from_str(&self.quote_mint).ok()?,
+            base_mint_key: Pubkey::from_str(&self.base_mint).ok()?,
+            lp_mint_key: Pubkey::from_str(&self.lp_mint).ok()?,
+            quote_value_key: Pubkey::from_str(&self.quote_vault).ok()?,
+            base_value_key: Pubkey::from_str(&self.base_vault).ok()?,
+            data,
+        })
+    }
+}
 
-    let raw_info = fs::read_to_string(market_main_path).expect("Error read file");
-    let vec: Vec<RawMarketPool> = serde_json::from_str(&raw_info)?;
+//加载resource/pool/raydium.json里的全部池子
+pub fn load_pools() -> Result<Vec<PoolInfo>> {
+    Ok(read_pools()?.iter().filter_map(|x| x.pool_info()).collect())
+}
+
+//pool_list用的池子
+pub fn load_raw_pools() -> Result<Vec<RawPool>> {
+    Ok(read_pools()?.iter().map(|x| RawPool {
+        market: RAYDIUM_MARKET.to_string(),
+        pool_key: x.id.clone(),
+        quote_mint: x.quote_mint.clone(),
+        base_mint: x.base_mint.clone(),
+        lp_mint: x.lp_mint.clone(),
+        quote_token: None,
+        base_token: None,
+    }).collect())
+}
+
+fn read_pools() -> Result<Vec<RawPoolInfo>> {
+    let raw_info = fs::read_to_string(POOL_PATH)?;
+    Ok(serde_json::from_str(&raw_info)?)
+}
 
-    let mut quote_map = HashMap::new();
-    let mut base_map = HashMap::new();
+//加载全部池子，每个池子按两个方向各生成一条边，供路由图使用
+pub fn load_market_pools() -> Result<Vec<MarketPool>> {
+    let raw_info = fs::read_to_string(MARKET_POOL_PATH)?;
+    let vec: Vec<RawMarketPool> = serde_json::from_str(&raw_info)?;
 
+    let mut res = vec![];
     for pool in &vec {
         let mut data = HashMap::new();
         data.insert("poolMint".to_string(), pool.authority.clone());
@@ -109,181 +165,19 @@ pub fn load_data_from_file(quote_mint: &String, base_mint: &String) -> Result<Ma
         data.insert("marketEventQueue".to_string(), pool.market_event_queue.clone());
         data.insert("marketVaultSigner".to_string(), pool.market_vault_signer.clone());
 
-        if pool.quote_mint.eq(quote_mint) {
-            let market_pool = MarketPool {
-                pool_key: Pubkey::from_str(&pool.id).unwrap(),
-                quote_mint_key: Pubkey::from_str(&pool.quote_mint).unwrap(),
-                base_mint_key: Pubkey::from_str(&pool.base_mint).unwrap(),
-                quote_value_key: Pubkey::from_str(&pool.quote_vault).unwrap(),
-                base_value_key: Pubkey::from_str(&pool.base_vault).unwrap(),
-                is_quote_to_base: true,
-                amp: None,
-                data: data.clone(),
-            };
-            quote_map.insert(pool.base_mint.clone(), market_pool);
-        }
-        if pool.base_mint.eq(quote_mint) {
-            let market_pool = MarketPool {
-                pool_key: Pubkey::from_str(&pool.id).unwrap(),
-                quote_mint_key: Pubkey::from_str(&pool.quote_mint).unwrap(),
-                base_mint_key: Pubkey::from_str(&pool.base_mint).unwrap(),
-                quote_value_key: Pubkey::from_str(&pool.quote_vault).unwrap(),
-                base_value_key: Pubkey::from_str(&pool.base_vault).unwrap(),
-                is_quote_to_base: false,
-                amp: None,
-                data: data.clone(),
-            };
-            quote_map.insert(pool.quote_mint.clone(), market_pool);
-        }
-        if pool.quote_mint.eq(base_mint) {
-            let market_pool = MarketPool {
-                pool_key: Pubkey::from_str(&pool.id).unwrap(),
-                quote_mint_key: Pubkey::from_str(&pool.quote_mint).unwrap(),
-                base_mint_key: Pubkey::from_str(&pool.base_mint).unwrap(),
-                quote_value_key: Pubkey::from_str(&pool.quote_vault).unwrap(),
-                base_value_key: Pubkey::from_str(&pool.base_vault).unwrap(),
-                is_quote_to_base: false,
+        for is_quote_to_base in [true, false] {
+            res.push(MarketPool {
+                market_type: MarketType::Raydium(RAYDIUM_MARKET.to_string(), RAYDIUM_PROGRAM_ID.to_string()),
+                pool_key: Pubkey::from_str(&pool.id)?,
+                quote_mint_key: Pubkey::from_str(&pool.quote_mint)?,
+                base_mint_key: Pubkey::from_str(&pool.base_mint)?,
+                quote_value_key: Pubkey::from_str(&pool.quote_vault)?,
+                base_value_key: Pubkey::from_str(&pool.base_vault)?,
+                is_quote_to_base,
                 amp: None,
                 data: data.clone(),
-            };
-            base_map.insert(pool.base_mint.clone(), market_pool);
-        }
-        if pool.base_mint.eq(base_mint) {
-            let market_pool = MarketPool {
-                pool_key: Pubkey::from_str(&pool.id).unwrap(),
-                quote_mint_key: Pubkey::from_str(&pool.quote_mint).unwrap(),
-                base_mint_key: Pubkey::from_str(&pool.base_mint).unwrap(),
-                quote_value_key: Pubkey::from_str(&pool.quote_vault).unwrap(),
-                base_value_key: Pubkey::from_str(&pool.base_vault).unwrap(),
-                is_quote_to_base: true,
-                amp: None,
-                data: data.clone(),
-            };
-            base_map.insert(pool.quote_mint.clone(), market_pool);
+            });
         }
     }
-
-    Ok(MarketOptMap {
-        market_type: MarketType::Raydium(RAYDIUM_MARKET.to_string().clone(), RAYDIUM_PROGRAM_ID.to_string().clone()),
-        quote_mint: quote_mint.clone(),
-        base_mint: base_mint.clone(),
-        quote_map,
-        base_map,
-    })
-}
-
-pub fn load_pool_from_file(lp_mint: Option<String>,
-                           quote_mint: Option<String>,
-                           base_mint: Option<String>) -> Option<PoolInfo> {
-    let pool_main_path = "./resource/pool/raydium.json".to_string();
-
-    let raw_info = fs::read_to_string(pool_main_path).expect("Error read file");
-    let vec: Vec<RawPoolInfo> = serde_json::from_str(&raw_info).ok()?;
-
-    let pool_info = match lp_mint {
-        Some(lp) => {
-            //通过pool地址筛选
-            for raw_pool in vec {
-                if raw_pool.lp_mint.eq(&lp) {
-
-                    let mut data = HashMap::new();
-                    data.insert("ammAuthority".to_string(), raw_pool.authority.clone());
-                    data.insert("ammOpenOrders".to_string(), raw_pool.open_orders.clone());
-                    data.insert("ammTargetOrders".to_string(), raw_pool.target_orders.clone());
-                    data.insert("poolCoinTokenAccount".to_string(), raw_pool.base_vault.clone());
-                    data.insert("poolPcTokenAccount".to_string(), raw_pool.quote_vault.clone());
-                    data.insert("poolWithdrawQueue".to_string(), raw_pool.withdraw_queue.clone());
-                    data.insert("poolTempLpTokenAccount".to_string(), raw_pool.temp_lp_token_account.clone());
-                    data.insert("marketProgramId".to_string(), raw_pool.market_program_id.clone());
-                    data.insert("marketId".to_string(), raw_pool.market_id.clone());
-                    data.insert("marketBids".to_string(), raw_pool.market_bids.clone());
-                    data.insert("marketAsks".to_string(), raw_pool.market_asks.clone());
-                    data.insert("marketBaseVault".to_string(), raw_pool.market_base_vault.clone());
-                    data.insert("marketQuoteVault".to_string(), raw_pool.market_quote_vault.clone());
-                    data.insert("marketEventQueue".to_string(), raw_pool.market_event_queue.clone());
-                    data.insert("marketVaultSigner".to_string(), raw_pool.market_vault_signer.clone());
-
-                    return Some(PoolInfo {
-                        market_type: MarketType::Raydium(RAYDIUM_MARKET.to_string(), RAYDIUM_PROGRAM_ID.to_string()),
-                        pool_key: Pubkey::from_str(&raw_pool.id).unwrap(),
-                        quote_mint_key: Pubkey::from_str(&raw_pool.quote_mint).unwrap(),
-                        base_mint_key: Pubkey::from_str(&raw_pool.base_mint).unwrap(),
-                        lp_mint_key: Pubkey::from_str(&raw_pool.lp_mint).unwrap(),
-                        quote_value_key: Pubkey::from_str(&raw_pool.quote_vault).unwrap(),
-                        base_value_key: Pubkey::from_str(&raw_pool.base_vault).unwrap(),
-                        data
-                    });
-                }
-            }
-            None
-        }
-        None => {
-            //通过 quote/base token对mint地址筛选
-            let quote_mint_address = quote_mint.unwrap().clone();
-            let base_mint_address = base_mint.unwrap().clone();
-
-            for raw_pool in vec {
-                if quote_mint_address.eq(&raw_pool.quote_mint) && base_mint_address.eq(&raw_pool.base_mint) {
-
-                    let mut data = HashMap::new();
-                    data.insert("ammAuthority".to_string(), raw_pool.authority.clone());
-                    data.insert("ammOpenOrders".to_string(), raw_pool.open_orders.clone());
-                    data.insert("ammTargetOrders".to_string(), raw_pool.target_orders.clone());
-                    data.insert("poolCoinTokenAccount".to_string(), raw_pool.base_vault.clone());
-                    data.insert("poolPcTokenAccount".to_string(), raw_pool.quote_vault.clone());
-                    data.insert("poolWithdrawQueue".to_string(), raw_pool.withdraw_queue.clone());
-                    data.insert("poolTempLpTokenAccount".to_string(), raw_pool.temp_lp_token_account.clone());
-                    data.insert("marketProgramId".to_string(), raw_pool.market_program_id.clone());
-                    data.insert("marketId".to_string(), raw_pool.market_id.clone());
-                    data.insert("marketBids".to_string(), raw_pool.market_bids.clone());
-                    data.insert("marketAsks".to_string(), raw_pool.market_asks.clone());
-                    data.insert("marketBaseVault".to_string(), raw_pool.market_base_vault.clone());
-                    data.insert("marketQuoteVault".to_string(), raw_pool.market_quote_vault.clone());
-                    data.insert("marketEventQueue".to_string(), raw_pool.market_event_queue.clone());
-                    data.insert("marketVaultSigner".to_string(), raw_pool.market_vault_signer.clone());
-
-                    return Some(PoolInfo {
-                        market_type: MarketType::Raydium(RAYDIUM_MARKET.to_string(), RAYDIUM_PROGRAM_ID.to_string()),
-                        pool_key: Pubkey::from_str(&raw_pool.id).unwrap(),
-                        quote_mint_key: Pubkey::from_str(&raw_pool.quote_mint).unwrap(),
-                        base_mint_key: Pubkey::from_str(&raw_pool.base_mint).unwrap(),
-                        lp_mint_key: Pubkey::from_str(&raw_pool.lp_mint).unwrap(),
-                        quote_value_key: Pubkey::from_str(&raw_pool.quote_vault).unwrap(),
-                        base_value_key: Pubkey::from_str(&raw_pool.base_vault).unwrap(),
-                        data
-                    });
-                } else if quote_mint_address.eq(&raw_pool.base_mint) && base_mint_address.eq(&raw_pool.quote_mint) {
-                    let mut data = HashMap::new();
-                    data.insert("ammAuthority".to_string(), raw_pool.authority.clone());
-                    data.insert("ammOpenOrders".to_string(), raw_pool.open_orders.clone());
-                    data.insert("ammTargetOrders".to_string(), raw_pool.target_orders.clone());
-                    data.insert("poolCoinTokenAccount".to_string(), raw_pool.base_vault.clone());
-                    data.insert("poolPcTokenAccount".to_string(), raw_pool.quote_vault.clone());
-                    data.insert("poolWithdrawQueue".to_string(), raw_pool.withdraw_queue.clone());
-                    data.insert("poolTempLpTokenAccount".to_string(), raw_pool.temp_lp_token_account.clone());
-                    data.insert("marketProgramId".to_string(), raw_pool.market_program_id.clone());
-                    data.insert("marketId".to_string(), raw_pool.market_id.clone());
-                    data.insert("marketBids".to_string(), raw_pool.market_bids.clone());
-                    data.insert("marketAsks".to_string(), raw_pool.market_asks.clone());
-                    data.insert("marketBaseVault".to_string(), raw_pool.market_base_vault.clone());
-                    data.insert("marketQuoteVault".to_string(), raw_pool.market_quote_vault.clone());
-                    data.insert("marketEventQueue".to_string(), raw_pool.market_event_queue.clone());
-                    data.insert("marketVaultSigner".to_string(), raw_pool.market_vault_signer.clone());
-                    return Some(PoolInfo {
-                        market_type: MarketType::Raydium(RAYDIUM_MARKET.to_string(), RAYDIUM_PROGRAM_ID.to_string()),
-                        pool_key: Pubkey::from_str(&raw_pool.id).unwrap(),
-                        quote_mint_key: Pubkey::from_str(&raw_pool.quote_mint).unwrap(),
-                        base_mint_key: Pubkey::from_str(&raw_pool.base_mint).unwrap(),
-                        lp_mint_key: Pubkey::from_str(&raw_pool.lp_mint).unwrap(),
-                        quote_value_key: Pubkey::from_str(&raw_pool.quote_vault).unwrap(),
-                        base_value_key: Pubkey::from_str(&raw_pool.base_vault).unwrap(),
-                        data
-                    });
-                }
-            }
-            None
-        }
-    };
-    pool_info
+    Ok(res)
 }
-
@@ -1,4 +1,59 @@
+pub mod amm;
 pub mod instruction;
 pub mod stats;
 pub mod data;
 
+use crate::dex::{AmmParams, Dex, RouteAmount, SwapAccounts};
+use crate::amm::Amm;
+use anyhow::Result;
+use solana_program::instruction::Instruction;
+use amm::RaydiumAmm;
+
+pub const DEX: Dex = Dex {
+    id: "raydium",
+    name: data::RAYDIUM_MARKET,
+    load_market_pools: data::load_market_pools,
+    load_pools: Some(data::load_pools),
+    load_raw_pools: Some(data::load_raw_pools),
+    pool_files: &[data::MARKET_POOL_PATH, data::POOL_PATH],
+    new_amm,
+    swap_instruction: Some(swap_instruction),
+    exact_out: true,
+    mid_price: true,
+};
+
+//路由池子和pool_info的文件里open orders的字段名不同
+fn new_amm(params: &AmmParams) -> Option<Box<dyn Amm>> {
+    let open_orders = params.data_key("openOrders").or_else(|| params.data_key("ammOpenOrders"));
+    Some(Box::new(RaydiumAmm::new(params.pool_key, params.quote_value_key, params.base_value_key, open_orders)))
+}
+
+//raydium的池子里coin是base，pc是quote，方向由池子根据用户账户的mint判断。
+//data里的poolMint存的是池子的authority
+fn swap_instruction(accounts: &SwapAccounts, amount: RouteAmount) -> Result<Instruction> {
+    let swap = match amount {
+        RouteAmount::ExactIn { .. } => instruction::swap_base_in,
+        RouteAmount::ExactOut { .. } => instruction::swap_base_out,
+    };
+    let (amount, other_amount) = amount.values();
+    Ok(swap(&accounts.program_id,
+            &accounts.pool_key,
+            &accounts.data_key("poolMint")?,
+            &accounts.data_key("openOrders")?,
+            &accounts.data_key("targetOrders")?,
+            &accounts.data_key("baseVault")?,
+            &accounts.data_key("quoteVault")?,
+            &accounts.data_key("marketProgramId")?,
+            &accounts.data_key("marketId")?,
+            &accounts.data_key("marketBids")?,
+            &accounts.data_key("marketAsks")?,
+            &accounts.data_key("marketEventQueue")?,
+            &accounts.data_key("marketBaseVault")?,
+            &accounts.data_key("marketQuoteVault")?,
+            &accounts.data_key("marketVaultSigner")?,
+            &accounts.source,
+            &accounts.destination,
+            &accounts.wallet,
+            amount,
+            other_amount)?)
+}
@@ -13,6 +13,7 @@ use bytemuck::{
 };
 use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
 use std::{cell::RefMut};
+use crate::serum::state::OpenOrders;
 
 #[repr(u64)]
 pub enum AmmStatus {
@@ -350,6 +351,38 @@ impl AmmInfo {
         Ok(amm_data)
     }
 
+    /// 和链上Calculator::calc_total_without_take_pnl一致：vault余额加上挂在serum上的资金，
+    /// 再扣掉还没提取的pnl。返回(pc, coin)
+    pub fn total_without_take_pnl(&self, pc_vault_amount: u64, coin_vault_amount: u64,
+                                  open_orders: &OpenOrders) -> (u64, u64) {
+        let total_pc = pc_vault_amount
+            .saturating_add(open_orders.native_pc_total)
+            .saturating_sub(self.out_put.need_take_pnl_pc);
+        let total_coin = coin_vault_amount
+            .saturating_add(open_orders.native_coin_total)
+            .saturating_sub(self.out_put.need_take_pnl_coin);
+        (total_pc, total_coin)
+    }
+
+    /// 链上swap_base_in/swap_base_out只接受Initialized状态的池子，state为InvlidState时也无法成交。
+    /// 可以swap时返回None，否则返回原因
+    pub fn swap_disabled_reason(&self) -> Option<String> {
+        let status = match self.status {
+            1u64 => None,
+            0u64 => Some("uninitialized"),
+            2u64 => Some("disabled"),
+            3u64 => Some("withdraw only"),
+            _ => Some("unknown"),
+        };
+        if let Some(status) = status {
+            return Some(format!("amm status {} ({})", self.status, status));
+        }
+        if !AmmState::valid_state(self.state) || self.state == AmmState::InvlidState as u64 {
+            return Some(format!("amm state {} invalid", self.state));
+        }
+        None
+    }
+
     #[inline]
     pub fn check_status(&self) -> Result<bool, ProgramError> {
         if self.status == AmmStatus::Uninitialized as u64 {
@@ -0,0 +1,141 @@
+//! Saber StableSwap
+
+use crate::amm::{Amm, AmmError, AmmQuote, AccountMap, FeeSide, SwapDirection, fee_rate, get_account, load_token_amount};
+use crate::saber::curve::StableSwap;
+use crate::saber::instruction::swap_authority;
+use crate::saber::state::SwapInfo;
+use anyhow::Result;
+use solana_program::{program_pack::Pack, pubkey::Pubkey};
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub struct SaberAmm {
+    swap_key: Pubkey,
+    program_id: Pubkey,
+    quote_vault: Pubkey,
+    base_vault: Pubkey,
+    swap_info: Option<SwapInfo>,
+    quote_amount: u64,
+    base_amount: u64,
+}
+
+impl SaberAmm {
+    pub fn new(swap_key: Pubkey, program_id: Pubkey, quote_vault: Pubkey, base_vault: Pubkey) -> Self {
+        SaberAmm {
+            swap_key,
+            program_id,
+            quote_vault,
+            base_vault,
+            swap_info: None,
+            quote_amount: 0,
+            base_amount: 0,
+        }
+    }
+
+    /// amp在ramp期间随时间变化，需要用当前时间计算
+    fn stable_swap(swap_info: &SwapInfo) -> StableSwap {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|x| x.as_secs()).unwrap_or_default() as i64;
+        StableSwap::new(swap_info.initial_amp_factor, swap_info.target_amp_factor, now,
+                        swap_info.start_ramp_ts, swap_info.stop_ramp_ts)
+    }
+
+    fn authority(&self, swap_info: &SwapInfo) -> Option<Pubkey> {
+        swap_authority(&self.program_id, &self.swap_key, swap_info.nonce).ok()
+    }
+}
+
+/// unpack_from_slice按固定长度取数据，长度不够时会panic
+pub fn unpack_swap_info(key: &Pubkey, data: &[u8]) -> Result<SwapInfo> {
+    if data.len() < SwapInfo::LEN {
+        return Err(AmmError::AccountDecode(*key).into());
+    }
+    Ok(SwapInfo::unpack_from_slice(data).map_err(|_| AmmError::AccountDecode(*key))?)
+}
+
+impl Amm for SaberAmm {
+    fn key(&self) -> Pubkey {
+        self.swap_key
+    }
+
+    fn accounts_needed(&self) -> Vec<Pubkey> {
+        vec![self.swap_key, self.quote_vault, self.base_vault]
+    }
+
+    fn update(&mut self, accounts: &AccountMap) -> Result<()> {
+        let swap_info = unpack_swap_info(&self.swap_key, &get_account(accounts, &self.swap_key)?.data)?;
+        self.quote_amount = load_token_amount(accounts, &self.quote_vault)?;
+        self.base_amount = load_token_amount(accounts, &self.base_vault)?;
+        self.swap_info = Some(swap_info);
+        Ok(())
+    }
+
+    fn unavailable_reason(&self) -> Option<String> {
+        match &self.swap_info {
+            Some(swap_info) if !swap_info.is_initialized => Some("swap not initialized".to_string()),
+            Some(swap_info) if swap_info.is_paused => Some("swap paused".to_string()),
+            Some(_) => None,
+            None => Some("swap not loaded".to_string()),
+        }
+    }
+
+    fn reserves(&self) -> (u64, u64) {
+        (self.quote_amount, self.base_amount)
+    }
+
+    /// Fees::trade_fee从曲线产出里扣，其中admin_trade_fee部分转入目标token的admin fee账户
+    fn quote(&self, amount_in: u64, direction: SwapDirection) -> Result<AmmQuote> {
+        let swap_info = self.swap_info.as_ref().ok_or(AmmError::AccountDecode(self.swap_key))?;
+        let (source_reserve, destination_reserve, destination_vault) = match direction {
+            SwapDirection::QuoteToBase => (self.quote_amount, self.base_amount, self.base_vault),
+            SwapDirection::BaseToQuote => (self.base_amount, self.quote_amount, self.quote_vault),
+        };
+
+        let stable_swap = SaberAmm::stable_swap(swap_info);
+        //池子深度不够时曲线返回None，按产出0处理
+        let (amount_out, fee, admin_fee) = stable_swap.swap_to(amount_in, source_reserve, destination_reserve, &swap_info.fees)
+            .map_or((0, 0, 0), |x| (x.amount_swapped, x.fee, x.admin_fee));
+
+        //组装交易需要的authority和目标token的admin fee账户，池子文件里没有
+        let admin_fee_destination = if swap_info.token_a.reserves.eq(&destination_vault) {
+            swap_info.token_a.admin_fees
+        } else {
+            swap_info.token_b.admin_fees
+        };
+        let authority = self.authority(swap_info).ok_or(AmmError::AccountDecode(self.swap_key))?;
+        let mut data = HashMap::new();
+        data.insert("authority".to_string(), authority.to_string());
+        data.insert("poolMint".to_string(), swap_info.pool_mint.to_string());
+        data.insert("adminFeeDestination".to_string(), admin_fee_destination.to_string());
+
+        Ok(AmmQuote {
+            amount_in,
+            amount_out,
+            source_reserve,
+            destination_reserve,
+            fee_rate: fee_rate(swap_info.fees.trade_fee_numerator, swap_info.fees.trade_fee_denominator),
+            fee_side: FeeSide::Destination,
+            fee,
+            protocol_fee: admin_fee,
+            amp: stable_swap.compute_amp_factor(),
+            data,
+        })
+    }
+
+    fn pool_data(&self) -> HashMap<String, String> {
+        let mut data = HashMap::new();
+        let swap_info = match &self.swap_info {
+            Some(swap_info) => swap_info,
+            None => return data,
+        };
+        if let Some(authority) = self.authority(swap_info) {
+            data.insert("authority".to_string(), authority.to_string());
+        }
+        if let Some(amp) = SaberAmm::stable_swap(swap_info).compute_amp_factor() {
+            data.insert("amp".to_string(), amp.to_string());
+        }
+        data.insert("poolMint".to_string(), swap_info.pool_mint.to_string());
+        data.insert("adminFeeA".to_string(), swap_info.token_a.admin_fees.to_string());
+        data.insert("adminFeeB".to_string(), swap_info.token_b.admin_fees.to_string());
+        data
+    }
+}
@@ -5,112 +5,120 @@ use std::fs;
 use std::collections::HashMap;
 use solana_program::pubkey::Pubkey;
 use rust_decimal::prelude::FromStr;
-use market::{MarketPool, MarketOptMap, MarketType};
+use market::{MarketPool, MarketType};
+use crate::pool::{PoolInfo, RawPool};
 
-const SABER_MARKET: &str = "Saber";
+pub const SABER_MARKET: &str = "Saber";
 const SABER_PROGRAM_ID: &str = "SSwpkEEcbUqx4vtoEByFjSkhKdCT862DNVb52nZg1UZ";
+//路由、pool_info和pool_list共用一个池子文件
+pub const POOL_PATH: &str = "./saber_pool.json";
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RawMarketPool {
     pub account: String,
     pub quote: RawMarketToken,
     pub base: RawMarketToken,
-}
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct RawMarketToken {
-    pub mint: String,
-    pub reserves: String,
+    //以下字段老的池子文件里没有，报价时从链上SwapInfo补齐
+    #[serde(default)]
+    pub authority: Option<String>,
+    #[serde(default)]
+    pub pool_mint: Option<String>,
+    #[serde(default)]
+    pub amp: Option<u64>,
 }
 
-pub fn load_data_from_file(quote_mint: &String, base_mint: &String) -> Result<MarketOptMap> {
-    let market_main_path = "./saber_pool.json".to_string();
-
-    let raw_info = fs::read_to_string(market_main_path).expect("Error read file");
-    let vec: Vec<RawMarketPool> = serde_json::from_str(&raw_info)?;
-
-    let mut quote_map = HashMap::new();
-    let mut base_map = HashMap::new();
-
-    for pool in &vec {
+impl RawMarketPool {
+    //按兑换方向生成data，poolSource是卖出token的储备
+    fn market_data(&self, source: &RawMarketToken, destination: &RawMarketToken) -> HashMap<String, String> {
         let mut data = HashMap::new();
-
-        if pool.quote.mint.eq(quote_mint) {
-
-            data.insert("poolSource".to_string(), pool.quote.reserves.clone());
-            data.insert("poolDestination".to_string(), pool.base.reserves.clone());
-
-            let market_pool = MarketPool {
-                pool_key: Pubkey::from_str(&pool.account).unwrap(),
-                quote_mint_key: Pubkey::from_str(&pool.quote.mint).unwrap(),
-                base_mint_key: Pubkey::from_str(&pool.base.mint).unwrap(),
-                quote_value_key: Pubkey::from_str(&pool.quote.reserves).unwrap(),
-                base_value_key: Pubkey::from_str(&pool.base.reserves).unwrap(),
-                is_quote_to_base: true,
-                amp: None,
-                data: data.clone(),
-            };
-            quote_map.insert(pool.base.mint.clone(), market_pool);
+        data.insert("poolSource".to_string(), source.reserves.clone());
+        data.insert("poolDestination".to_string(), destination.reserves.clone());
+        if let Some(authority) = &self.authority {
+            data.insert("authority".to_string(), authority.clone());
         }
-        if pool.base.mint.eq(quote_mint) {
-
-            data.insert("poolSource".to_string(), pool.base.reserves.clone());
-            data.insert("poolDestination".to_string(), pool.quote.reserves.clone());
-
-            let market_pool = MarketPool {
-                pool_key: Pubkey::from_str(&pool.account).unwrap(),
-                quote_mint_key: Pubkey::from_str(&pool.quote.mint).unwrap(),
-                base_mint_key: Pubkey::from_str(&pool.base.mint).unwrap(),
-                quote_value_key: Pubkey::from_str(&pool.quote.reserves).unwrap(),
-                base_value_key: Pubkey::from_str(&pool.base.reserves).unwrap(),
-                is_quote_to_base: false,
-                amp: None,
-                data: data.clone(),
-            };
-            quote_map.insert(pool.quote.mint.clone(), market_pool);
+        if let Some(pool_mint) = &self.pool_mint {
+            data.insert("poolMint".to_string(), pool_mint.clone());
         }
-        if pool.quote.mint.eq(base_mint) {
-
-            data.insert("poolSource".to_string(), pool.base.reserves.clone());
-            data.insert("poolDestination".to_string(), pool.quote.reserves.clone());
+        data
+    }
 
-            let market_pool = MarketPool {
-                pool_key: Pubkey::from_str(&pool.account).unwrap(),
-                quote_mint_key: Pubkey::from_str(&pool.quote.mint).unwrap(),
-                base_mint_key: Pubkey::from_str(&pool.base.mint).unwrap(),
-                quote_value_key: Pubkey::from_str(&pool.quote.reserves).unwrap(),
-                base_value_key: Pubkey::from_str(&pool.base.reserves).unwrap(),
-                is_quote_to_base: false,
-                amp: None,
-                data: data.clone(),
-            };
-            base_map.insert(pool.base.mint.clone(), market_pool);
+    fn pool_info(&self) -> Option<PoolInfo> {
+        let mut data = HashMap::new();
+        if let Some(amp) = self.amp {
+            data.insert("amp".to_string(), amp.to_string());
         }
-        if pool.base.mint.eq(base_mint) {
+        if let Some(authority) = &self.authority {
+            data.insert("authority".to_string(), authority.clone());
+        }
+        Some(PoolInfo {
+            market_type: MarketType::Saber(SABER_MARKET.to_string(), SABER_PROGRAM_ID.to_string()),
+            pool_key: Pubkey::from_str(&self.account).ok()?,
+            quote_mint_key: Pubkey::from_str(&self.quote.mint).ok()?,
+            base_mint_key: Pubkey::from_str(&self.base.mint).ok()?,
+            //没有配置lp mint时先留空，计算rate时用链上的pool_mint
+            lp_mint_key: self.pool_mint.as_ref().and_then(|x| Pubkey::from_str(x).ok()).unwrap_or_default(),
+            quote_value_key: Pubkey::from_str(&self.quote.reserves).ok()?,
+            base_value_key: Pubkey::from_str(&self.base.reserves).ok()?,
+            data,
+        })
+    }
+}
 
-            data.insert("poolSource".to_string(), pool.quote.reserves.clone());
-            data.insert("poolDestination".to_string(), pool.base.reserves.clone());
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RawMarketToken {
+    pub mint: String,
+    pub reserves: String,
+}
 
-            let market_pool = MarketPool {
-                pool_key: Pubkey::from_str(&pool.account).unwrap(),
-                quote_mint_key: Pubkey::from_str(&pool.quote.mint).unwrap(),
-                base_mint_key: Pubkey::from_str(&pool.base.mint).unwrap(),
-                quote_value_key: Pubkey::from_str(&pool.quote.reserves).unwrap(),
-                base_value_key: Pubkey::from_str(&pool.base.reserves).unwrap(),
-                is_quote_to_base: true,
-                amp: None,
-                data: data.clone(),
+//加载全部池子，每个池子按两个方向各生成一条边，供路由图使用
+pub fn load_market_pools() -> Result<Vec<MarketPool>> {
+    let vec = read_pools()?;
+
+    let mut res = vec![];
+    for pool in &vec {
+        for is_quote_to_base in [true, false] {
+            let (source, destination) = if is_quote_to_base {
+                (&pool.quote, &pool.base)
+            } else {
+                (&pool.base, &pool.quote)
             };
-            base_map.insert(pool.quote.mint.clone(), market_pool);
+            let data = pool.market_data(source, destination);
+            res.push(MarketPool {
+                market_type: MarketType::Saber(SABER_MARKET.to_string(), SABER_PROGRAM_ID.to_string()),
+                pool_key: Pubkey::from_str(&pool.account)?,
+                quote_mint_key: Pubkey::from_str(&pool.quote.mint)?,
+                base_mint_key: Pubkey::from_str(&pool.base.mint)?,
+                quote_value_key: Pubkey::from_str(&pool.quote.reserves)?,
+                base_value_key: Pubkey::from_str(&pool.base.reserves)?,
+                is_quote_to_base,
+                amp: pool.amp,
+                data,
+            });
         }
     }
+    Ok(res)
+}
+
+//加载saber_pool.json里的全部池子
+pub fn load_pools() -> Result<Vec<PoolInfo>> {
+    Ok(read_pools()?.iter().filter_map(|x| x.pool_info()).collect())
+}
 
-    Ok(MarketOptMap {
-        market_type: MarketType::Saber(SABER_MARKET.to_string().clone(), SABER_PROGRAM_ID.to_string().clone()),
-        quote_mint: quote_mint.clone(),
-        base_mint: base_mint.clone(),
-        quote_map,
-        base_map,
-    })
+//pool_list用的池子，老的池子文件里没有lp mint时为空
+pub fn load_raw_pools() -> Result<Vec<RawPool>> {
+    Ok(read_pools()?.iter().map(|x| RawPool {
+        market: SABER_MARKET.to_string(),
+        pool_key: x.account.clone(),
+        quote_mint: x.quote.mint.clone(),
+        base_mint: x.base.mint.clone(),
+        lp_mint: x.pool_mint.clone().unwrap_or_default(),
+        quote_token: None,
+        base_token: None,
+    }).collect())
 }
 
+fn read_pools() -> Result<Vec<RawMarketPool>> {
+    let raw_info = fs::read_to_string(POOL_PATH)?;
+    Ok(serde_json::from_str(&raw_info)?)
+}
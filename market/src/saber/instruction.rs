@@ -0,0 +1,286 @@
+//! Instruction types
+
+#![allow(clippy::too_many_arguments)]
+
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
+use std::convert::TryInto;
+use std::mem::size_of;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SwapData {
+    /// SOURCE amount to transfer, output to DESTINATION is based on the exchange rate
+    pub amount_in: u64,
+    /// Minimum amount of DESTINATION token to output, prevents excessive slippage
+    pub minimum_amount_out: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DepositData {
+    /// Token A amount to deposit
+    pub token_a_amount: u64,
+    /// Token B amount to deposit
+    pub token_b_amount: u64,
+    /// Minimum LP tokens to mint, prevents excessive slippage
+    pub min_mint_amount: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct WithdrawData {
+    /// Amount of pool tokens to burn. User receives an output of token a
+    /// and b based on the percentage of the pool tokens that are returned.
+    pub pool_token_amount: u64,
+    /// Minimum amount of token A to receive, prevents excessive slippage
+    pub minimum_token_a_amount: u64,
+    /// Minimum amount of token B to receive, prevents excessive slippage
+    pub minimum_token_b_amount: u64,
+}
+
+/// Instructions supported by the StableSwap program.
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub enum SwapInstruction {
+    ///   Swap the tokens in the pool.
+    ///
+    ///   0. `[]` StableSwap
+    ///   1. `[]` $authority
+    ///   2. `[signer]` User authority.
+    ///   3. `[writable]` token_(A|B) SOURCE Account, amount is transferable by $authority,
+    ///   4. `[writable]` token_(A|B) Base Account to swap INTO.  Must be the SOURCE token.
+    ///   5. `[writable]` token_(A|B) Base Account to swap FROM.  Must be the DESTINATION token.
+    ///   6. `[writable]` token_(A|B) DESTINATION Account assigned to USER as the owner.
+    ///   7. `[writable]` token_(A|B) admin fee Account. Must have same mint as DESTINATION token.
+    ///   8. `[]` Token program id
+    Swap(SwapData),
+
+    ///   Deposit some tokens into the pool.  The output is a "pool" token representing ownership
+    ///   into the pool. Inputs are converted to the current ratio.
+    ///
+    ///   0. `[]` StableSwap
+    ///   1. `[]` $authority
+    ///   2. `[signer]` User authority.
+    ///   3. `[writable]` token_a $authority can transfer amount,
+    ///   4. `[writable]` token_b $authority can transfer amount,
+    ///   5. `[writable]` token_a Base Account to deposit into.
+    ///   6. `[writable]` token_b Base Account to deposit into.
+    ///   7. `[writable]` Pool MINT account, $authority is the owner.
+    ///   8. `[writable]` Pool Account to deposit the generated tokens, user is the owner.
+    ///   9. `[]` Token program id
+    Deposit(DepositData),
+
+    ///   Withdraw tokens from the pool at the current ratio.
+    ///
+    ///   0. `[]` StableSwap
+    ///   1. `[]` $authority
+    ///   2. `[signer]` User authority.
+    ///   3. `[writable]` Pool mint account, $authority is the owner
+    ///   4. `[writable]` SOURCE Pool account, amount is transferable by $authority.
+    ///   5. `[writable]` token_a Swap Account to withdraw FROM.
+    ///   6. `[writable]` token_b Swap Account to withdraw FROM.
+    ///   7. `[writable]` token_a user Account to credit.
+    ///   8. `[writable]` token_b user Account to credit.
+    ///   9. `[writable]` admin_fee_a admin fee Account for token_a.
+    ///   10. `[writable]` admin_fee_b admin fee Account for token_b.
+    ///   11. `[]` Token program id
+    Withdraw(WithdrawData),
+}
+
+impl SwapInstruction {
+    /// Unpacks a byte buffer into a [SwapInstruction](enum.SwapInstruction.html).
+    pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+        let (&tag, rest) = input.split_first().ok_or(ProgramError::InvalidInstructionData)?;
+        Ok(match tag {
+            1 => {
+                let (amount_in, rest) = Self::unpack_u64(rest)?;
+                let (minimum_amount_out, _rest) = Self::unpack_u64(rest)?;
+                Self::Swap(SwapData { amount_in, minimum_amount_out })
+            }
+            2 => {
+                let (token_a_amount, rest) = Self::unpack_u64(rest)?;
+                let (token_b_amount, rest) = Self::unpack_u64(rest)?;
+                let (min_mint_amount, _rest) = Self::unpack_u64(rest)?;
+                Self::Deposit(DepositData { token_a_amount, token_b_amount, min_mint_amount })
+            }
+            3 => {
+                let (pool_token_amount, rest) = Self::unpack_u64(rest)?;
+                let (minimum_token_a_amount, rest) = Self::unpack_u64(rest)?;
+                let (minimum_token_b_amount, _rest) = Self::unpack_u64(rest)?;
+                Self::Withdraw(WithdrawData { pool_token_amount, minimum_token_a_amount, minimum_token_b_amount })
+            }
+            _ => return Err(ProgramError::InvalidInstructionData),
+        })
+    }
+
+    fn unpack_u64(input: &[u8]) -> Result<(u64, &[u8]), ProgramError> {
+        if input.len() >= 8 {
+            let (amount, rest) = input.split_at(8);
+            let amount = amount
+                .get(..8)
+                .and_then(|slice| slice.try_into().ok())
+                .map(u64::from_le_bytes)
+                .ok_or(ProgramError::InvalidInstructionData)?;
+            Ok((amount, rest))
+        } else {
+            Err(ProgramError::InvalidInstructionData)
+        }
+    }
+
+    /// Packs a [SwapInstruction](enum.SwapInstruction.html) into a byte buffer.
+    pub fn pack(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(size_of::<Self>());
+        match self {
+            Self::Swap(SwapData { amount_in, minimum_amount_out }) => {
+                buf.push(1);
+                buf.extend_from_slice(&amount_in.to_le_bytes());
+                buf.extend_from_slice(&minimum_amount_out.to_le_bytes());
+            }
+            Self::Deposit(DepositData { token_a_amount, token_b_amount, min_mint_amount }) => {
+                buf.push(2);
+                buf.extend_from_slice(&token_a_amount.to_le_bytes());
+                buf.extend_from_slice(&token_b_amount.to_le_bytes());
+                buf.extend_from_slice(&min_mint_amount.to_le_bytes());
+            }
+            Self::Withdraw(WithdrawData { pool_token_amount, minimum_token_a_amount, minimum_token_b_amount }) => {
+                buf.push(3);
+                buf.extend_from_slice(&pool_token_amount.to_le_bytes());
+                buf.extend_from_slice(&minimum_token_a_amount.to_le_bytes());
+                buf.extend_from_slice(&minimum_token_b_amount.to_le_bytes());
+            }
+        }
+        buf
+    }
+}
+
+/// Creates a 'swap' instruction.
+pub fn swap(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    swap_authority_key: &Pubkey,
+    user_authority_key: &Pubkey,
+    source_pubkey: &Pubkey,
+    swap_source_pubkey: &Pubkey,
+    swap_destination_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    admin_fee_destination_pubkey: &Pubkey,
+    amount_in: u64,
+    minimum_amount_out: u64,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::Swap(SwapData { amount_in, minimum_amount_out }).pack();
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new_readonly(*swap_authority_key, false),
+        AccountMeta::new_readonly(*user_authority_key, true),
+        AccountMeta::new(*source_pubkey, false),
+        AccountMeta::new(*swap_source_pubkey, false),
+        AccountMeta::new(*swap_destination_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new(*admin_fee_destination_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'deposit' instruction.
+pub fn deposit(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    swap_authority_key: &Pubkey,
+    user_authority_key: &Pubkey,
+    deposit_token_a_pubkey: &Pubkey,
+    deposit_token_b_pubkey: &Pubkey,
+    swap_token_a_pubkey: &Pubkey,
+    swap_token_b_pubkey: &Pubkey,
+    pool_mint_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    token_a_amount: u64,
+    token_b_amount: u64,
+    min_mint_amount: u64,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::Deposit(DepositData { token_a_amount, token_b_amount, min_mint_amount }).pack();
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new_readonly(*swap_authority_key, false),
+        AccountMeta::new_readonly(*user_authority_key, true),
+        AccountMeta::new(*deposit_token_a_pubkey, false),
+        AccountMeta::new(*deposit_token_b_pubkey, false),
+        AccountMeta::new(*swap_token_a_pubkey, false),
+        AccountMeta::new(*swap_token_b_pubkey, false),
+        AccountMeta::new(*pool_mint_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'withdraw' instruction.
+pub fn withdraw(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    swap_authority_key: &Pubkey,
+    user_authority_key: &Pubkey,
+    pool_mint_pubkey: &Pubkey,
+    source_pubkey: &Pubkey,
+    swap_token_a_pubkey: &Pubkey,
+    swap_token_b_pubkey: &Pubkey,
+    destination_token_a_pubkey: &Pubkey,
+    destination_token_b_pubkey: &Pubkey,
+    admin_fee_a_pubkey: &Pubkey,
+    admin_fee_b_pubkey: &Pubkey,
+    pool_token_amount: u64,
+    minimum_token_a_amount: u64,
+    minimum_token_b_amount: u64,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::Withdraw(WithdrawData {
+        pool_token_amount,
+        minimum_token_a_amount,
+        minimum_token_b_amount,
+    }).pack();
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new_readonly(*swap_authority_key, false),
+        AccountMeta::new_readonly(*user_authority_key, true),
+        AccountMeta::new(*pool_mint_pubkey, false),
+        AccountMeta::new(*source_pubkey, false),
+        AccountMeta::new(*swap_token_a_pubkey, false),
+        AccountMeta::new(*swap_token_b_pubkey, false),
+        AccountMeta::new(*destination_token_a_pubkey, false),
+        AccountMeta::new(*destination_token_b_pubkey, false),
+        AccountMeta::new(*admin_fee_a_pubkey, false),
+        AccountMeta::new(*admin_fee_b_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Derives the swap authority from the swap account and its nonce.
+pub fn swap_authority(program_id: &Pubkey, swap_pubkey: &Pubkey, nonce: u8) -> Result<Pubkey, ProgramError> {
+    Pubkey::create_program_address(&[&swap_pubkey.to_bytes()[..32], &[nonce]], program_id)
+        .map_err(|_| ProgramError::InvalidSeeds)
+}
@@ -3,4 +3,47 @@ pub mod state;
 pub mod fees;
 pub mod curve;
 pub mod bn;
+pub mod instruction;
+pub mod amm;
 
+use crate::dex::{AmmParams, Dex, RouteAmount, SwapAccounts};
+use crate::amm::Amm;
+use anyhow::Result;
+use solana_program::{instruction::Instruction, pubkey::Pubkey};
+use std::str::FromStr;
+use amm::SaberAmm;
+
+pub const DEX: Dex = Dex {
+    id: "saber",
+    name: data::SABER_MARKET,
+    load_market_pools: data::load_market_pools,
+    load_pools: Some(data::load_pools),
+    load_raw_pools: Some(data::load_raw_pools),
+    pool_files: &[data::POOL_PATH],
+    new_amm,
+    swap_instruction: Some(swap_instruction),
+    exact_out: false,
+    mid_price: true,
+};
+
+fn new_amm(params: &AmmParams) -> Option<Box<dyn Amm>> {
+    let program_id = Pubkey::from_str(params.program_id).ok()?;
+    Some(Box::new(SaberAmm::new(params.pool_key, program_id, params.quote_value_key, params.base_value_key)))
+}
+
+//authority和adminFeeDestination在报价时从链上SwapInfo补进data
+fn swap_instruction(accounts: &SwapAccounts, amount: RouteAmount) -> Result<Instruction> {
+    let (amount_in, minimum_amount_out) = amount.exact_in(data::SABER_MARKET)?;
+    Ok(instruction::swap(&accounts.program_id,
+                         &spl_token::id(),
+                         &accounts.pool_key,
+                         &accounts.data_key("authority")?,
+                         &accounts.wallet,
+                         &accounts.source,
+                         &accounts.data_key("poolSource")?,
+                         &accounts.data_key("poolDestination")?,
+                         &accounts.destination,
+                         &accounts.data_key("adminFeeDestination")?,
+                         amount_in,
+                         minimum_amount_out)?)
+}
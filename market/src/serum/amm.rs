@@ -0,0 +1,110 @@
+//! Serum订单簿按逐档吃单报价，quote和base对应pc和coin或反过来，由市场状态里的pc_mint判断
+
+use crate::amm::{Amm, AmmError, AmmQuote, AccountMap, FeeSide, SwapDirection, fee_rate, get_account};
+use crate::serum::state::{MarketState, Order, TAKER_FEE_BPS, unpack_orders};
+use anyhow::Result;
+use solana_program::pubkey::Pubkey;
+
+pub struct SerumAmm {
+    market_key: Pubkey,
+    bids_key: Pubkey,
+    asks_key: Pubkey,
+    quote_mint: Pubkey,
+    market_state: Option<MarketState>,
+    bids: Vec<Order>,
+    asks: Vec<Order>,
+}
+
+impl SerumAmm {
+    pub fn new(market_key: Pubkey, bids_key: Pubkey, asks_key: Pubkey, quote_mint: Pubkey) -> Self {
+        SerumAmm {
+            market_key,
+            bids_key,
+            asks_key,
+            quote_mint,
+            market_state: None,
+            bids: vec![],
+            asks: vec![],
+        }
+    }
+
+    /// 挂单一侧的(pc, coin)深度，最小单位
+    fn depth(market_state: &MarketState, orders: &[Order]) -> (u64, u64) {
+        let coin_depth: u64 = orders.iter().map(|x| x.quantity).sum();
+        let pc_depth: u128 = orders.iter().map(|x| x.quantity as u128 * x.price as u128).sum();
+        ((pc_depth * market_state.pc_lot_size as u128).min(u64::MAX as u128) as u64,
+         coin_depth.saturating_mul(market_state.coin_lot_size))
+    }
+
+    fn is_quote_pc(&self, market_state: &MarketState) -> bool {
+        market_state.pc_mint.eq(&self.quote_mint)
+    }
+}
+
+impl Amm for SerumAmm {
+    fn key(&self) -> Pubkey {
+        self.market_key
+    }
+
+    fn accounts_needed(&self) -> Vec<Pubkey> {
+        vec![self.market_key, self.bids_key, self.asks_key]
+    }
+
+    fn update(&mut self, accounts: &AccountMap) -> Result<()> {
+        let market_state = MarketState::unpack(&get_account(accounts, &self.market_key)?.data)
+            .map_err(|_| AmmError::AccountDecode(self.market_key))?;
+        let bids = unpack_orders(&get_account(accounts, &self.bids_key)?.data, true)
+            .map_err(|_| AmmError::AccountDecode(self.bids_key))?;
+        let asks = unpack_orders(&get_account(accounts, &self.asks_key)?.data, false)
+            .map_err(|_| AmmError::AccountDecode(self.asks_key))?;
+        self.market_state = Some(market_state);
+        self.bids = bids;
+        self.asks = asks;
+        Ok(())
+    }
+
+    /// bids上的pc和asks上的coin
+    fn reserves(&self) -> (u64, u64) {
+        let market_state = match &self.market_state {
+            Some(market_state) => market_state,
+            None => return (0, 0),
+        };
+        let (bid_pc, _) = SerumAmm::depth(market_state, &self.bids);
+        let (_, ask_coin) = SerumAmm::depth(market_state, &self.asks);
+        if self.is_quote_pc(market_state) {
+            (bid_pc, ask_coin)
+        } else {
+            (ask_coin, bid_pc)
+        }
+    }
+
+    /// taker费都收在pc上：买单从输入扣，卖单从产出扣。
+    /// amount_in是实际成交的输入，不足一个lot的零头留在用户手里；订单簿深度不够时产出为0
+    fn quote(&self, amount_in: u64, direction: SwapDirection) -> Result<AmmQuote> {
+        let market_state = self.market_state.as_ref().ok_or(AmmError::AccountDecode(self.market_key))?;
+        let is_buy = self.is_quote_pc(market_state) == (direction == SwapDirection::QuoteToBase);
+
+        let (amount_out, fee, consumed, fee_side, source_reserve, destination_reserve) = if is_buy {
+            let (amount_out, fee, consumed) = market_state.simulate_buy(&self.asks, amount_in);
+            let (pc_depth, coin_depth) = SerumAmm::depth(market_state, &self.asks);
+            (amount_out, fee, consumed, FeeSide::Source, pc_depth, coin_depth)
+        } else {
+            let (amount_out, fee, consumed) = market_state.simulate_sell(&self.bids, amount_in);
+            let (pc_depth, coin_depth) = SerumAmm::depth(market_state, &self.bids);
+            (amount_out, fee, consumed, FeeSide::Destination, coin_depth, pc_depth)
+        };
+
+        Ok(AmmQuote {
+            amount_in: if amount_out > 0 { consumed } else { amount_in },
+            amount_out,
+            source_reserve,
+            destination_reserve,
+            fee_rate: fee_rate(TAKER_FEE_BPS, 10_000),
+            fee_side,
+            fee,
+            protocol_fee: 0,
+            amp: None,
+            data: Default::default(),
+        })
+    }
+}
@@ -0,0 +1,44 @@
+use crate::market;
+use crate::raydium;
+use anyhow::Result;
+use std::collections::HashSet;
+use solana_program::pubkey::Pubkey;
+use rust_decimal::prelude::FromStr;
+use market::{MarketPool, MarketType};
+
+pub const SERUM_MARKET: &str = "Serum";
+
+//serum市场取自raydium池子关联的订单簿，coin是base，pc是quote。
+//MarketPool里quote_value_key存bids，base_value_key存asks，报价时需要这两个账户
+pub fn load_market_pools() -> Result<Vec<MarketPool>> {
+    let raydium_pools = raydium::data::load_market_pools()?;
+
+    let mut res = vec![];
+    let mut seen = HashSet::new();
+    for pool in raydium_pools {
+        let market_id = match pool.data.get("marketId") {
+            Some(a) => a.clone(),
+            None => continue,
+        };
+        if !seen.insert((market_id.clone(), pool.is_quote_to_base)) {
+            continue;
+        }
+
+        let program_id = pool.data.get("marketProgramId").cloned().unwrap_or_default();
+        let bids = pool.data.get("marketBids").cloned().unwrap_or_default();
+        let asks = pool.data.get("marketAsks").cloned().unwrap_or_default();
+
+        res.push(MarketPool {
+            market_type: MarketType::Serum(SERUM_MARKET.to_string(), program_id),
+            pool_key: Pubkey::from_str(&market_id)?,
+            quote_mint_key: pool.quote_mint_key,
+            base_mint_key: pool.base_mint_key,
+            quote_value_key: Pubkey::from_str(&bids)?,
+            base_value_key: Pubkey::from_str(&asks)?,
+            is_quote_to_base: pool.is_quote_to_base,
+            amp: None,
+            data: pool.data,
+        });
+    }
+    Ok(res)
+}
@@ -0,0 +1,27 @@
+pub mod data;
+pub mod state;
+pub mod amm;
+
+use crate::dex::{AmmParams, Dex};
+use crate::raydium;
+use crate::amm::Amm;
+use amm::SerumAmm;
+
+//raydium池子关联的serum订单簿只用来报价，没有对应的swap指令
+pub const DEX: Dex = Dex {
+    id: "serum",
+    name: data::SERUM_MARKET,
+    load_market_pools: data::load_market_pools,
+    load_pools: None,
+    load_raw_pools: None,
+    pool_files: &[raydium::data::MARKET_POOL_PATH],
+    new_amm,
+    swap_instruction: None,
+    exact_out: false,
+    mid_price: false,
+};
+
+//quote_value_key存bids，base_value_key存asks
+fn new_amm(params: &AmmParams) -> Option<Box<dyn Amm>> {
+    Some(Box::new(SerumAmm::new(params.pool_key, params.quote_value_key, params.base_value_key, params.quote_mint)))
+}
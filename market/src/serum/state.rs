@@ -0,0 +1,285 @@
+//! Serum v3 market and order book slab layouts
+
+use arrayref::{array_ref, array_refs};
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
+
+/// 账户数据前后各有一段"serum"/"padding"填充
+const ACCOUNT_HEAD_PADDING: usize = 5;
+const ACCOUNT_TAIL_PADDING: usize = 7;
+
+const MARKET_STATE_LEN: usize = 376;
+const SLAB_HEADER_LEN: usize = 40;
+const SLAB_NODE_LEN: usize = 72;
+
+const NODE_TAG_LEAF: u32 = 2;
+//open orders里余额之前的部分：account_flags, market, owner, 四个余额
+const OPEN_ORDERS_BALANCE_LEN: usize = 104;
+
+/// 不持有SRM/MSRM时的taker费率(base tier)
+pub const TAKER_FEE_BPS: u64 = 22;
+const FEE_BPS_DENOMINATOR: u64 = 10_000;
+
+/// Market state, only the fields needed for matching.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MarketState {
+    pub own_address: Pubkey,
+    pub vault_signer_nonce: u64,
+    pub coin_mint: Pubkey,
+    pub pc_mint: Pubkey,
+    pub coin_vault: Pubkey,
+    pub pc_vault: Pubkey,
+    pub req_q: Pubkey,
+    pub event_q: Pubkey,
+    pub bids: Pubkey,
+    pub asks: Pubkey,
+    pub coin_lot_size: u64,
+    pub pc_lot_size: u64,
+}
+
+impl MarketState {
+    pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+        if input.len() < ACCOUNT_HEAD_PADDING + MARKET_STATE_LEN + ACCOUNT_TAIL_PADDING {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let input = array_ref![input, ACCOUNT_HEAD_PADDING, MARKET_STATE_LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (
+            _account_flags,
+            own_address,
+            vault_signer_nonce,
+            coin_mint,
+            pc_mint,
+            coin_vault,
+            _coin_deposits_total,
+            _coin_fees_accrued,
+            pc_vault,
+            _pc_deposits_total,
+            _pc_fees_accrued,
+            _pc_dust_threshold,
+            req_q,
+            event_q,
+            bids,
+            asks,
+            coin_lot_size,
+            pc_lot_size,
+            _fee_rate_bps,
+            _referrer_rebates_accrued,
+        ) = array_refs![input, 8, 32, 8, 32, 32, 32, 8, 8, 32, 8, 8, 8, 32, 32, 32, 32, 8, 8, 8, 8];
+
+        Ok(MarketState {
+            own_address: Pubkey::new_from_array(*own_address),
+            vault_signer_nonce: u64::from_le_bytes(*vault_signer_nonce),
+            coin_mint: Pubkey::new_from_array(*coin_mint),
+            pc_mint: Pubkey::new_from_array(*pc_mint),
+            coin_vault: Pubkey::new_from_array(*coin_vault),
+            pc_vault: Pubkey::new_from_array(*pc_vault),
+            req_q: Pubkey::new_from_array(*req_q),
+            event_q: Pubkey::new_from_array(*event_q),
+            bids: Pubkey::new_from_array(*bids),
+            asks: Pubkey::new_from_array(*asks),
+            coin_lot_size: u64::from_le_bytes(*coin_lot_size),
+            pc_lot_size: u64::from_le_bytes(*pc_lot_size),
+        })
+    }
+
+    /// 用pc买coin，按价格从低到高吃asks。taker费从pc里扣，返回买到的coin、taker费(pc)和实际用掉的pc(含费)。
+    /// 不足一个lot的零头不会成交；asks吃完还有剩余时订单簿深度不够，返回全0
+    pub fn simulate_buy(&self, asks: &[Order], native_pc_in: u64) -> (u64, u64, u64) {
+        if self.coin_lot_size == 0 || self.pc_lot_size == 0 {
+            return (0, 0, 0);
+        }
+        let pc_without_fee = native_pc_in as u128 * FEE_BPS_DENOMINATOR as u128 / (FEE_BPS_DENOMINATOR + TAKER_FEE_BPS) as u128;
+        let pc_lots = pc_without_fee / self.pc_lot_size as u128;
+        let mut pc_lots_left = pc_lots;
+        let mut coin_lots_out: u128 = 0;
+        let mut filled = false;
+
+        for order in asks {
+            let price = order.price as u128;
+            if price == 0 {
+                continue;
+            }
+            let take = (order.quantity as u128).min(pc_lots_left / price);
+            coin_lots_out += take;
+            pc_lots_left -= take * price;
+            //这一档没有吃完说明剩下的pc已经不够一个lot
+            if take < order.quantity as u128 {
+                filled = true;
+                break;
+            }
+        }
+        if !filled && pc_lots_left > 0 {
+            return (0, 0, 0);
+        }
+
+        let native_pc_used = (pc_lots - pc_lots_left) * self.pc_lot_size as u128;
+        let fee = (native_pc_used * TAKER_FEE_BPS as u128).div_ceil(FEE_BPS_DENOMINATOR as u128);
+        ((coin_lots_out * self.coin_lot_size as u128).min(u64::MAX as u128) as u64,
+         fee as u64,
+         ((native_pc_used + fee) as u64).min(native_pc_in))
+    }
+
+    /// 卖coin换pc，按价格从高到低吃bids。taker费从得到的pc里扣，返回扣费后的pc、taker费(pc)和实际卖出的coin。
+    /// 不足一个lot的零头不会成交；bids吃完还有剩余时订单簿深度不够，返回全0
+    pub fn simulate_sell(&self, bids: &[Order], native_coin_in: u64) -> (u64, u64, u64) {
+        if self.coin_lot_size == 0 || self.pc_lot_size == 0 {
+            return (0, 0, 0);
+        }
+        let coin_lots = (native_coin_in / self.coin_lot_size) as u128;
+        let mut coin_lots_left = coin_lots;
+        let mut pc_lots_out: u128 = 0;
+
+        for order in bids {
+            if coin_lots_left == 0 {
+                break;
+            }
+            let take = (order.quantity as u128).min(coin_lots_left);
+            pc_lots_out += take * order.price as u128;
+            coin_lots_left -= take;
+        }
+        if coin_lots_left > 0 {
+            return (0, 0, 0);
+        }
+
+        let native_pc_out = pc_lots_out * self.pc_lot_size as u128;
+        let fee = (native_pc_out * TAKER_FEE_BPS as u128).div_ceil(FEE_BPS_DENOMINATOR as u128);
+        ((native_pc_out - fee).min(u64::MAX as u128) as u64,
+         fee.min(u64::MAX as u128) as u64,
+         (coin_lots * self.coin_lot_size as u128) as u64)
+    }
+}
+
+/// Open orders account, only the balances.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct OpenOrders {
+    pub market: Pubkey,
+    pub owner: Pubkey,
+    pub native_coin_free: u64,
+    pub native_coin_total: u64,
+    pub native_pc_free: u64,
+    pub native_pc_total: u64,
+}
+
+impl OpenOrders {
+    pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+        if input.len() < ACCOUNT_HEAD_PADDING + OPEN_ORDERS_BALANCE_LEN {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let input = array_ref![input, ACCOUNT_HEAD_PADDING, OPEN_ORDERS_BALANCE_LEN];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (
+            _account_flags,
+            market,
+            owner,
+            native_coin_free,
+            native_coin_total,
+            native_pc_free,
+            native_pc_total,
+        ) = array_refs![input, 8, 32, 32, 8, 8, 8, 8];
+
+        Ok(OpenOrders {
+            market: Pubkey::new_from_array(*market),
+            owner: Pubkey::new_from_array(*owner),
+            native_coin_free: u64::from_le_bytes(*native_coin_free),
+            native_coin_total: u64::from_le_bytes(*native_coin_total),
+            native_pc_free: u64::from_le_bytes(*native_pc_free),
+            native_pc_total: u64::from_le_bytes(*native_pc_total),
+        })
+    }
+}
+
+/// 订单簿上的一笔挂单，price单位是每个coin lot对应的pc lot，quantity单位是coin lot
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Order {
+    pub price: u64,
+    pub quantity: u64,
+}
+
+/// 解析bids/asks slab账户里的全部挂单。bids按价格从高到低，asks按价格从低到高
+pub fn unpack_orders(input: &[u8], is_bids: bool) -> Result<Vec<Order>, ProgramError> {
+    if input.len() < ACCOUNT_HEAD_PADDING + SLAB_HEADER_LEN + ACCOUNT_TAIL_PADDING {
+        return Err(ProgramError::InvalidAccountData);
+    }
+    let data = &input[ACCOUNT_HEAD_PADDING..input.len() - ACCOUNT_TAIL_PADDING];
+    //header: account_flags, bump_index, free_list_len, free_list_head, root_node, leaf_count
+    let bump_index = u64::from_le_bytes(*array_ref![data, 8, 8]) as usize;
+    let nodes = &data[SLAB_HEADER_LEN..];
+    let node_count = bump_index.min(nodes.len() / SLAB_NODE_LEN);
+
+    let mut orders = vec![];
+    for index in 0..node_count {
+        let node = array_ref![nodes, index * SLAB_NODE_LEN, SLAB_NODE_LEN];
+        let tag = u32::from_le_bytes(*array_ref![node, 0, 4]);
+        if tag != NODE_TAG_LEAF {
+            continue;
+        }
+        //key的高64位是价格，低64位是序号
+        let key = u128::from_le_bytes(*array_ref![node, 8, 16]);
+        let quantity = u64::from_le_bytes(*array_ref![node, 56, 8]);
+        orders.push(Order {
+            price: (key >> 64) as u64,
+            quantity,
+        });
+    }
+
+    if is_bids {
+        orders.sort_by_key(|x| std::cmp::Reverse(x.price));
+    } else {
+        orders.sort_by_key(|x| x.price);
+    }
+    Ok(orders)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn market_state() -> MarketState {
+        MarketState {
+            own_address: Pubkey::new_unique(),
+            vault_signer_nonce: 0,
+            coin_mint: Pubkey::new_unique(),
+            pc_mint: Pubkey::new_unique(),
+            coin_vault: Pubkey::new_unique(),
+            pc_vault: Pubkey::new_unique(),
+            req_q: Pubkey::new_unique(),
+            event_q: Pubkey::new_unique(),
+            bids: Pubkey::new_unique(),
+            asks: Pubkey::new_unique(),
+            coin_lot_size: 100,
+            pc_lot_size: 10,
+        }
+    }
+
+    #[test]
+    fn test_sell_reports_lot_remainder() {
+        let bids = vec![Order { price: 5, quantity: 10 }];
+        //1050个coin只能卖出10个lot，剩下50不成交
+        let (pc_out, fee, coin_used) = market_state().simulate_sell(&bids, 1050);
+        assert_eq!(coin_used, 1000);
+        assert_eq!(pc_out + fee, 10 * 5 * 10);
+    }
+
+    #[test]
+    fn test_sell_exceeding_book() {
+        let bids = vec![Order { price: 5, quantity: 10 }];
+        assert_eq!(market_state().simulate_sell(&bids, 1100), (0, 0, 0));
+    }
+
+    #[test]
+    fn test_buy_reports_pc_used() {
+        //扣掉taker费后是999个pc即99个pc lot，吃完第一档用6个lot，第二档买23个coin lot用92个lot，剩1个lot
+        let native_pc_in = 1000 * (FEE_BPS_DENOMINATOR + TAKER_FEE_BPS) / FEE_BPS_DENOMINATOR;
+        let asks = vec![Order { price: 3, quantity: 2 }, Order { price: 4, quantity: 30 }];
+        let (coin_out, fee, pc_used) = market_state().simulate_buy(&asks, native_pc_in);
+        assert_eq!(coin_out, (2 + 23) * 100);
+        assert_eq!(pc_used - fee, (6 + 92) * 10);
+        assert!(pc_used <= native_pc_in);
+    }
+
+    #[test]
+    fn test_buy_exceeding_book() {
+        let asks = vec![Order { price: 3, quantity: 2 }];
+        assert_eq!(market_state().simulate_buy(&asks, 10_000), (0, 0, 0));
+    }
+}
@@ -0,0 +1,471 @@
+use crate::error::{ApiError, ApiResult};
+use crate::node_client::RpcPool;
+use anyhow::{Result, anyhow};
+use market::amm::Amm;
+use futures::stream::{self, FuturesUnordered, SelectAll, StreamExt};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+use solana_program::pubkey::Pubkey;
+use solana_sdk::{commitment_config::CommitmentConfig, account::Account};
+use solana_client::nonblocking::pubsub_client::{PubsubClient, PubsubClientError, UnsubscribeFn};
+use solana_client::rpc_config::RpcAccountInfoConfig;
+use solana_account_decoder::UiAccountEncoding;
+
+//getMultipleAccounts一次最多100个key
+const MAX_KEYS_PER_REQUEST: usize = 100;
+//同时发出的getMultipleAccounts请求数
+const MAX_PARALLEL_REQUESTS: usize = 8;
+//后台轮询的间隔
+const POLL_INTERVAL_MILLIS: u64 = 2000;
+//超过这个时间没有更新的账户由轮询刷新，websocket断开时自动退回轮询
+const STALE_MILLIS: u64 = 5000;
+//所有订阅共用一条websocket连接，节点对单条连接的订阅数有限制，超出的账户只靠轮询
+const MAX_SUBSCRIPTIONS: usize = 256;
+//websocket连接失败后的重连间隔，每次失败翻倍
+const MIN_RECONNECT_MILLIS: u64 = 2000;
+const MAX_RECONNECT_MILLIS: u64 = 60_000;
+//超过这个时间没有被请求过的账户从缓存里移除，同时取消订阅
+const ACCESS_TTL_MILLIS: u64 = 600_000;
+
+//账户数据的来源，默认是RPC节点，测试时可以换成solana-test-validator或mock
+#[rocket::async_trait]
+pub trait AccountSource: Send + Sync {
+    //返回数据所在的slot和每个key对应的账户，链上不存在的为None
+    async fn fetch(&self, keys: &[Pubkey]) -> Result<(u64, Vec<Option<Account>>)>;
+}
+
+pub struct RpcAccountSource {
+    rpc: Arc<RpcPool>,
+}
+
+impl RpcAccountSource {
+    pub fn new(rpc: Arc<RpcPool>) -> Self {
+        RpcAccountSource { rpc }
+    }
+}
+
+#[rocket::async_trait]
+impl AccountSource for RpcAccountSource {
+    async fn fetch(&self, keys: &[Pubkey]) -> Result<(u64, Vec<Option<Account>>)> {
+        fetch_accounts(&self.rpc, keys).await
+    }
+}
+
+//重复的key只请求一次，按100个一批并发请求，结果按keys的顺序返回。不经过缓存，用于只查询一次的账户
+pub async fn fetch_accounts(rpc: &RpcPool, keys: &[Pubkey]) -> Result<(u64, Vec<Option<Account>>)> {
+    let mut unique = keys.to_vec();
+    unique.sort();
+    unique.dedup();
+
+    let responses: Vec<_> = stream::iter(unique.chunks(MAX_KEYS_PER_REQUEST))
+        .map(|chunk| async move {
+            let response = rpc.call(|client| client.get_multiple_accounts_with_commitment(chunk, rpc.commitment())).await;
+            (chunk, response)
+        })
+        .buffer_unordered(MAX_PARALLEL_REQUESTS)
+        .collect()
+        .await;
+
+    //各批次的slot可能不同，取最小的，保证返回的数据都不早于这个slot
+    let mut slot = u64::MAX;
+    let mut fetched = HashMap::with_capacity(unique.len());
+    for (chunk, response) in responses {
+        let response = response?;
+        slot = slot.min(response.context.slot);
+        fetched.extend(chunk.iter().cloned().zip(response.value));
+    }
+
+    let accounts = keys.iter().map(|x| fetched.get(x).cloned().flatten()).collect();
+    Ok((if unique.is_empty() { 0 } else { slot }, accounts))
+}
+
+struct CachedAccount {
+    //链上不存在的账户也缓存下来，避免每次请求都去查
+    account: Option<Account>,
+    slot: u64,
+    updated: Instant,
+    //最近一次被get_accounts请求的时间
+    accessed: Instant,
+}
+
+//池子、vault、amm等账户的本地缓存。第一次用到的账户同步拉取，之后由后台的websocket订阅和轮询保持最新
+pub struct AccountCache {
+    source: Box<dyn AccountSource>,
+    //websocket节点，连接失败时换下一个。为空时只用轮询
+    ws_urls: Vec<String>,
+    //websocket订阅使用的commitment，和RPC节点的配置一致
+    commitment: CommitmentConfig,
+    accounts: RwLock<HashMap<Pubkey, CachedAccount>>,
+    subscribed: Mutex<HashSet<Pubkey>>,
+}
+
+impl AccountCache {
+    pub fn new(source: Box<dyn AccountSource>, ws_urls: Vec<String>, commitment: CommitmentConfig) -> Self {
+        AccountCache {
+            source,
+            ws_urls,
+            commitment,
+            accounts: RwLock::new(HashMap::new()),
+            subscribed: Mutex::new(HashSet::new()),
+        }
+    }
+
+    //返回存在的账户，以及这些数据中最早的slot，报价结果对应这个slot。
+    //链上不存在的账户不在结果里，由调用方决定是跳过还是报错
+    pub async fn get_accounts(&self, keys: &[Pubkey]) -> ApiResult<(HashMap<String, Account>, u64)> {
+        let missing: Vec<Pubkey> = {
+            let accounts = self.accounts.read().unwrap();
+            let mut seen = HashSet::new();
+            keys.iter().filter(|x| !accounts.contains_key(x) && seen.insert(**x)).cloned().collect()
+        };
+        if !missing.is_empty() {
+            self.refresh(&missing).await?;
+        }
+
+        let now = Instant::now();
+        let mut accounts = self.accounts.write().unwrap();
+        let mut account_map = HashMap::new();
+        let mut slot = u64::MAX;
+        for key in keys {
+            if let Some(cached) = accounts.get_mut(key) {
+                cached.accessed = now;
+                slot = slot.min(cached.slot);
+                if let Some(account) = &cached.account {
+                    account_map.insert(key.to_string(), account.clone());
+                }
+            }
+        }
+        Ok((account_map, if slot == u64::MAX { 0 } else { slot }))
+    }
+
+    //adapter报价需要的账户，加上调用方额外需要的keys。有些账户要读到池子账户之后才知道(如whirlpool的tick array)，
+    //先用第一轮的数据update一次，再补拉新出现的账户。update失败的adapter留到报价时处理
+    pub async fn get_amm_accounts(&self, amms: &mut [Box<dyn Amm>], keys: &[Pubkey]) -> ApiResult<(HashMap<String, Account>, u64)> {
+        let mut requested: Vec<Pubkey> = keys.to_vec();
+        requested.extend(amms.iter().flat_map(|x| x.accounts_needed()));
+        let (mut account_map, slot) = self.get_accounts(&requested).await?;
+
+        let requested: HashSet<Pubkey> = requested.into_iter().collect();
+        let mut more = vec![];
+        for amm in amms.iter_mut() {
+            if amm.update(&account_map).is_ok() {
+                more.extend(amm.accounts_needed().into_iter().filter(|x| !requested.contains(x)));
+            }
+        }
+        if more.is_empty() {
+            return Ok((account_map, slot));
+        }
+        let (more_map, more_slot) = self.get_accounts(&more).await?;
+        account_map.extend(more_map);
+        Ok((account_map, slot.min(more_slot)))
+    }
+
+    //所有key都必须存在，缺少的账户返回AccountNotFound
+    pub async fn get_required_accounts(&self, keys: &[Pubkey]) -> ApiResult<(HashMap<String, Account>, u64)> {
+        let (account_map, slot) = self.get_accounts(keys).await?;
+        if let Some(key) = keys.iter().find(|x| !account_map.contains_key(&x.to_string())) {
+            return Err(ApiError::AccountNotFound(key.to_string()));
+        }
+        Ok((account_map, slot))
+    }
+
+    //只接受不早于缓存里的数据，轮询和推送的先后顺序不影响结果
+    pub fn insert(&self, key: Pubkey, account: Option<Account>, slot: u64) {
+        let mut accounts = self.accounts.write().unwrap();
+        let now = Instant::now();
+        match accounts.get_mut(&key) {
+            Some(cached) if cached.slot > slot => {}
+            Some(cached) => {
+                cached.account = account;
+                cached.slot = slot;
+                cached.updated = now;
+            }
+            None => {
+                accounts.insert(key, CachedAccount {
+                    account,
+                    slot,
+                    updated: now,
+                    accessed: now,
+                });
+            }
+        }
+    }
+
+    //订阅推送的数据只更新还在缓存里的账户，返回false表示账户已被移除，应当取消订阅
+    fn update(&self, key: Pubkey, account: Option<Account>, slot: u64) -> bool {
+        if !self.accounts.read().unwrap().contains_key(&key) {
+            return false;
+        }
+        self.insert(key, account, slot);
+        true
+    }
+
+    pub async fn refresh(&self, keys: &[Pubkey]) -> Result<()> {
+        let (slot, accounts) = self.source.fetch(keys).await?;
+        for (key, account) in keys.iter().zip(accounts) {
+            self.insert(*key, account, slot);
+        }
+        Ok(())
+    }
+
+    //需要轮询的账户。有订阅的账户数据不变时不会推送，由订阅保持最新，连接断开取消标记后才回到轮询
+    fn stale_keys(&self, now: Instant) -> Vec<Pubkey> {
+        let stale = Duration::from_millis(STALE_MILLIS);
+        let subscribed = self.subscribed.lock().unwrap();
+        self.accounts.read().unwrap()
+            .iter()
+            .filter(|(key, x)| now.saturating_duration_since(x.updated) >= stale && !subscribed.contains(key))
+            .map(|(key, _)| *key)
+            .collect()
+    }
+
+    //移除长时间没有被请求的账户，返回移除的数量
+    fn evict(&self, now: Instant) -> usize {
+        let ttl = Duration::from_millis(ACCESS_TTL_MILLIS);
+        let mut accounts = self.accounts.write().unwrap();
+        let before = accounts.len();
+        accounts.retain(|_, x| now.saturating_duration_since(x.accessed) < ttl);
+        before - accounts.len()
+    }
+
+    //后台任务的一轮：移除不再使用的账户，刷新需要轮询的账户
+    async fn poll(&self, now: Instant) -> Result<()> {
+        self.evict(now);
+        let stale = self.stale_keys(now);
+        if !stale.is_empty() {
+            self.refresh(&stale).await?;
+        }
+        Ok(())
+    }
+
+    //后台任务：刷新长时间没有更新的账户，配置了websocket时另起一个任务维护订阅。需要在tokio运行时里调用
+    pub fn start(cache: Arc<AccountCache>) {
+        if !cache.ws_urls.is_empty() {
+            AccountCache::start_subscriptions(cache.clone());
+        }
+        rocket::tokio::spawn(async move {
+            let mut interval = rocket::tokio::time::interval(Duration::from_millis(POLL_INTERVAL_MILLIS));
+            loop {
+                interval.tick().await;
+                if let Err(e) = cache.poll(Instant::now()).await {
+                    println!("account cache poll fail: {}", e);
+                }
+            }
+        });
+    }
+
+    //连接断开后清除订阅标记，期间由轮询刷新。按退避时间换下一个节点重连，重连后重新订阅缓存里的账户
+    fn start_subscriptions(cache: Arc<AccountCache>) {
+        rocket::tokio::spawn(async move {
+            let min_delay = Duration::from_millis(MIN_RECONNECT_MILLIS);
+            let max_delay = Duration::from_millis(MAX_RECONNECT_MILLIS);
+            let mut delay = min_delay;
+            let mut index = 0;
+            loop {
+                let ws_url = &cache.ws_urls[index % cache.ws_urls.len()];
+                let connected = Instant::now();
+                let result = match PubsubClient::new(ws_url).await {
+                    Ok(client) => cache.run_subscriptions(&client).await,
+                    Err(e) => Err(e.into()),
+                };
+                cache.subscribed.lock().unwrap().clear();
+                if let Err(e) = result {
+                    println!("account subscribe {} fail: {}", ws_url, e);
+                }
+
+                //连接保持过一段时间才算恢复，退避时间从头开始
+                if connected.elapsed() >= max_delay {
+                    delay = min_delay;
+                }
+                rocket::tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(max_delay);
+                index += 1;
+            }
+        });
+    }
+
+    //在一条连接上维护全部订阅：每轮给新加入缓存的账户订阅，取消已被移除的账户，推送的数据写入缓存。
+    //订阅请求并发发出，等待结果时照常处理推送。单个订阅结束时退回轮询，下一轮重新订阅；
+    //只有连接本身关闭时才返回错误由调用方重连
+    async fn run_subscriptions(&self, client: &PubsubClient) -> Result<()> {
+        let config = RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            commitment: Some(self.commitment),
+            ..RpcAccountInfoConfig::default()
+        };
+        let mut streams = SelectAll::new();
+        let mut unsubscribes = HashMap::new();
+        //已发出、还没有结果的订阅请求
+        let mut pending = FuturesUnordered::new();
+        let mut subscribing = HashSet::new();
+        let mut interval = rocket::tokio::time::interval(Duration::from_millis(POLL_INTERVAL_MILLIS));
+        loop {
+            rocket::tokio::select! {
+                _ = interval.tick() => {
+                    let keys: HashSet<Pubkey> = self.accounts.read().unwrap().keys().cloned().collect();
+                    let removed: Vec<Pubkey> = unsubscribes.keys().filter(|x| !keys.contains(x)).cloned().collect();
+                    for key in removed {
+                        self.unsubscribe(&mut unsubscribes, &key).await;
+                    }
+                    for key in keys {
+                        if unsubscribes.len() + subscribing.len() >= MAX_SUBSCRIPTIONS {
+                            break;
+                        }
+                        if unsubscribes.contains_key(&key) || !subscribing.insert(key) {
+                            continue;
+                        }
+                        let config = config.clone();
+                        pending.push(async move { (key, client.account_subscribe(&key, Some(config)).await) });
+                    }
+                }
+                Some((key, result)) = pending.next(), if !pending.is_empty() => {
+                    subscribing.remove(&key);
+                    match result {
+                        Ok((stream, unsubscribe)) => {
+                            unsubscribes.insert(key, unsubscribe);
+                            self.subscribed.lock().unwrap().insert(key);
+                            //订阅结束时多发一个None
+                            streams.push(stream.map(move |x| (key, Some(x))).chain(stream::once(async move { (key, None) })).boxed());
+                        }
+                        Err(PubsubClientError::ConnectionClosed(e)) => return Err(anyhow!("connection closed: {}", e)),
+                        //节点拒绝单个订阅时这个账户继续靠轮询，下一轮再试
+                        Err(_) => {}
+                    }
+                }
+                Some((key, response)) = streams.next(), if !streams.is_empty() => {
+                    match response {
+                        Some(response) => {
+                            let account = response.value.decode::<Account>();
+                            if !self.update(key, account, response.context.slot) {
+                                self.unsubscribe(&mut unsubscribes, &key).await;
+                            }
+                        }
+                        //订阅被节点结束，退回轮询。连接断开时所有订阅都会结束，下一轮重新订阅会返回ConnectionClosed
+                        None => {
+                            if unsubscribes.remove(&key).is_some() {
+                                self.subscribed.lock().unwrap().remove(&key);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    async fn unsubscribe(&self, unsubscribes: &mut HashMap<Pubkey, UnsubscribeFn>, key: &Pubkey) {
+        self.subscribed.lock().unwrap().remove(key);
+        if let Some(unsubscribe) = unsubscribes.remove(key) {
+            unsubscribe().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    //每次fetch返回递增的slot，记录请求过的key
+    struct MockSource {
+        slot: AtomicU64,
+        fetched: Arc<Mutex<Vec<Pubkey>>>,
+    }
+
+    #[rocket::async_trait]
+    impl AccountSource for MockSource {
+        async fn fetch(&self, keys: &[Pubkey]) -> Result<(u64, Vec<Option<Account>>)> {
+            self.fetched.lock().unwrap().extend_from_slice(keys);
+            let slot = self.slot.fetch_add(1, Ordering::SeqCst) + 1;
+            let accounts = keys.iter().map(|_| Some(Account { lamports: slot, ..Account::default() })).collect();
+            Ok((slot, accounts))
+        }
+    }
+
+    //缓存和请求过的key
+    fn cache() -> (AccountCache, Arc<Mutex<Vec<Pubkey>>>) {
+        let fetched = Arc::new(Mutex::new(vec![]));
+        let source = MockSource {
+            slot: AtomicU64::new(0),
+            fetched: fetched.clone(),
+        };
+        (AccountCache::new(Box::new(source), vec![], CommitmentConfig::confirmed()), fetched)
+    }
+
+    fn fetch_count(fetched: &Mutex<Vec<Pubkey>>, key: &Pubkey) -> usize {
+        fetched.lock().unwrap().iter().filter(|x| x.eq(&key)).count()
+    }
+
+    #[rocket::async_test]
+    async fn test_missing_accounts_fetched_once() {
+        let (cache, fetched) = cache();
+        let key = Pubkey::new_unique();
+        let (accounts, slot) = cache.get_accounts(&[key, key]).await.unwrap();
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(slot, 1);
+        cache.get_accounts(&[key]).await.unwrap();
+        assert_eq!(fetch_count(&fetched, &key), 1);
+    }
+
+    #[rocket::async_test]
+    async fn test_stale_accounts_refreshed() {
+        let (cache, fetched) = cache();
+        let key = Pubkey::new_unique();
+        cache.get_accounts(&[key]).await.unwrap();
+
+        let now = Instant::now();
+        assert!(cache.stale_keys(now).is_empty());
+        let later = now + Duration::from_millis(STALE_MILLIS);
+        assert_eq!(cache.stale_keys(later), vec![key]);
+
+        cache.poll(later).await.unwrap();
+        assert_eq!(fetch_count(&fetched, &key), 2);
+        let (accounts, slot) = cache.get_accounts(&[key]).await.unwrap();
+        assert_eq!(slot, 2);
+        assert_eq!(accounts[&key.to_string()].lamports, 2);
+    }
+
+    #[rocket::async_test]
+    async fn test_older_slot_ignored() {
+        let (cache, _) = cache();
+        let key = Pubkey::new_unique();
+        cache.insert(key, Some(Account { lamports: 10, ..Account::default() }), 10);
+        cache.insert(key, Some(Account { lamports: 9, ..Account::default() }), 9);
+        let (accounts, slot) = cache.get_accounts(&[key]).await.unwrap();
+        assert_eq!((accounts[&key.to_string()].lamports, slot), (10, 10));
+    }
+
+    #[rocket::async_test]
+    async fn test_subscribed_accounts_fall_back_to_polling() {
+        let (cache, _) = cache();
+        let key = Pubkey::new_unique();
+        cache.get_accounts(&[key]).await.unwrap();
+        let later = Instant::now() + Duration::from_millis(STALE_MILLIS);
+
+        //订阅还在时由推送保持最新，不轮询
+        cache.subscribed.lock().unwrap().insert(key);
+        assert!(cache.stale_keys(later).is_empty());
+        assert!(cache.update(key, None, 5));
+
+        //连接断开取消标记后回到轮询
+        cache.subscribed.lock().unwrap().remove(&key);
+        assert_eq!(cache.stale_keys(later + Duration::from_millis(STALE_MILLIS)), vec![key]);
+    }
+
+    #[rocket::async_test]
+    async fn test_unused_accounts_evicted() {
+        let (cache, _) = cache();
+        let used = Pubkey::new_unique();
+        let unused = Pubkey::new_unique();
+        cache.get_accounts(&[used, unused]).await.unwrap();
+
+        let later = Instant::now() + Duration::from_millis(ACCESS_TTL_MILLIS);
+        cache.accounts.write().unwrap().get_mut(&used).unwrap().accessed = later;
+        assert_eq!(cache.evict(later), 1);
+        assert!(cache.accounts.read().unwrap().contains_key(&used));
+
+        //移除后订阅推送不再写回缓存
+        assert!(!cache.update(unused, None, 100));
+        assert!(!cache.accounts.read().unwrap().contains_key(&unused));
+    }
+}
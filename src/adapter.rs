@@ -0,0 +1,51 @@
+use std::collections::{HashMap, HashSet};
+use solana_program::pubkey::Pubkey;
+use market::amm::{Amm, SwapDirection};
+use market::dex::{self, AmmParams};
+use market::market::{MarketPool, MarketSwap, MarketType};
+use market::pool::PoolInfo;
+
+//按市场类型找到对应DEX模块创建报价adapter，新增DEX只需要在market::dex里注册。Swap市场不支持报价
+pub fn new_amm(market_type: &MarketType,
+               pool_key: Pubkey,
+               quote_mint: Pubkey,
+               quote_value_key: Pubkey,
+               base_value_key: Pubkey,
+               data: &HashMap<String, String>) -> Option<Box<dyn Amm>> {
+    let (name, program_id) = market_type.get_name();
+    let dex = dex::find(&name)?;
+    (dex.new_amm)(&AmmParams {
+        pool_key,
+        program_id: &program_id,
+        quote_mint,
+        quote_value_key,
+        base_value_key,
+        data,
+    })
+}
+
+pub fn market_pool_amm(step: &MarketPool) -> Option<Box<dyn Amm>> {
+    new_amm(&step.market_type, step.pool_key, step.quote_mint_key, step.quote_value_key, step.base_value_key, &step.data)
+}
+
+pub fn pool_info_amm(pool: &PoolInfo) -> Option<Box<dyn Amm>> {
+    new_amm(&pool.market_type, pool.pool_key, pool.quote_mint_key, pool.quote_value_key, pool.base_value_key, &pool.data)
+}
+
+//路径上每个池子一个adapter，用来拉取报价需要的账户
+pub fn route_amms(swaps: &[MarketSwap]) -> Vec<Box<dyn Amm>> {
+    let mut seen = HashSet::new();
+    swaps.iter()
+        .flat_map(|x| x.step.iter())
+        .filter(|x| seen.insert(x.pool_key))
+        .filter_map(market_pool_amm)
+        .collect()
+}
+
+pub fn swap_direction(step: &MarketPool) -> SwapDirection {
+    if step.is_quote_to_base {
+        SwapDirection::QuoteToBase
+    } else {
+        SwapDirection::BaseToQuote
+    }
+}
@@ -1,130 +1,174 @@
-use crate::node_client::NetworkType;
 use crate::opt_core;
 use crate::response;
+use crate::error::{ApiError, ApiResult};
+use crate::registry::RegistryData;
+use crate::account_cache::AccountCache;
+use crate::adapter::route_amms;
 use serde::{Serialize, Deserialize};
 use anyhow::Result;
 use std::fs;
 use std::collections::HashMap;
 use rust_decimal::prelude::FromStr;
-use market::{raydium, saber, orca};
-use market;
 use solana_program::pubkey::Pubkey;
-use solana_sdk::{commitment_config::CommitmentConfig, account::Account};
-use solana_client::rpc_client::RpcClient;
 use opt_core::OptInitData;
-use response::OptRank;
+use response::{OptRank, QuoteSummary, SwapMode, raw_amount, ui_amount};
+use market::dex;
 
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ToDo {
-    pub id: u32,
-    pub title: String,
-    pub description: String,
-    pub done: bool,
-}
+//默认最多三跳
+const DEFAULT_MAX_HOPS: usize = 3;
+const MAX_HOPS: usize = 4;
+//每个跳数参与报价的候选路径数
+const MAX_ROUTE_CANDIDATES: usize = 10;
 
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct OptRequest {
-    pub amount_in: f64,
+    //amount_in和amount_out二选一，给了amount_out按指定输出报价
+    pub amount_in: Option<f64>,
+    pub amount_out: Option<f64>,
     pub quote_mint: String,
     pub base_mint: String,
     pub slippage: f32,
     pub exclude: Option<Vec<String>>,
+    pub max_hops: Option<usize>,
 }
 
 impl OptRequest {
-    pub fn load_data(&self) -> OptRank {
-
-        //查询
-        let token_main_path = "./token_mint.json".to_string();
-        let tokens_adr = load_token_data_from_file(&token_main_path).expect("load token data fail");
-
-        //key不支持返回错误 todo
-        let _quote_token = tokens_adr.get(&self.quote_mint).expect("pubKey not found");
-        let _base_token = tokens_adr.get(&self.base_mint).expect("pubKey not found");
-
-        let mut need_raydium = true;
-        let mut need_orca = true;
-        let mut _need_saber = true;
-        let mut _need_swap = true;
-        let mut _need_serum = true;
-        match &self.exclude {
-            Some(a) => {
-                let exclude_markets = a.clone();
-                need_raydium = !exclude_markets.contains(&"raydium".to_string());
-                need_orca = !exclude_markets.contains(&"orca".to_string());
-                _need_saber = !exclude_markets.contains(&"saber".to_string());
-                _need_swap = !exclude_markets.contains(&"swap".to_string());
-                _need_serum = !exclude_markets.contains(&"serum".to_string());
-            }
-            None => {}
+    pub async fn load_data(&self, registry: &RegistryData, cache: &AccountCache) -> ApiResult<OptRank> {
+        let amount = match (self.amount_in, self.amount_out) {
+            (Some(_), Some(_)) => return Err(ApiError::InvalidRequest("only one of amount_in and amount_out can be set".to_string())),
+            (Some(x), None) | (None, Some(x)) => x,
+            (None, None) => return Err(ApiError::InvalidRequest("amount_in or amount_out required".to_string())),
+        };
+        if !amount.is_finite() || amount <= 0.0 {
+            return Err(ApiError::InvalidRequest(format!("invalid amount {}", amount)));
         }
-
-        let mut market_swap = vec![];
-        if need_orca {
-            let orca_pool = orca::data::load_data_from_file(&self.quote_mint, &self.base_mint).expect("load orca data fail");
-            let mut orca_swap = orca_pool.filer_swap().unwrap();
-            market_swap.append(&mut orca_swap);
+        //滑点是百分比，100%及以上时最少产出为0
+        if !(0.0..100.0).contains(&self.slippage) {
+            return Err(ApiError::InvalidRequest(format!("invalid slippage {}", self.slippage)));
         }
 
-        if need_raydium {
-            let raydium_pool = raydium::data::load_data_from_file(&self.quote_mint, &self.base_mint).expect("load raydium data fail");
-            let mut raydium_swap = raydium_pool.filer_swap().unwrap();
-            market_swap.append(&mut raydium_swap);
+        let tokens_adr = &registry.tokens;
+        let quote_token = tokens_adr.get(&self.quote_mint).ok_or_else(|| ApiError::UnknownMint(self.quote_mint.to_string()))?;
+        let base_token = tokens_adr.get(&self.base_mint).ok_or_else(|| ApiError::UnknownMint(self.base_mint.to_string()))?;
+        let quote_decimals = quote_token.decimal;
+        let base_decimals = base_token.decimal;
+        //换算成最小单位后为0说明数量小于token精度或超出范围
+        let decimals = if self.amount_out.is_some() { base_decimals } else { quote_decimals };
+        if raw_amount(amount, decimals) == 0 {
+            return Err(ApiError::InvalidRequest(format!("amount {} out of range for {} decimals", amount, decimals)));
         }
 
-        //saber支持，但前端没实现，先屏蔽
-        // if need_saber {
-        //     let saber_pool = saber::data::load_data_from_file(&self.quote_mint, &self.base_mint).expect("load orca data fail");
-        //     let mut saber_swap = saber_pool.filer_swap().unwrap();
-        //     market_swap.append(&mut saber_swap);
-        // }
+        //raydium池子关联的serum订单簿也作为单独的路径来源
+        let exclude = self.exclude.clone().unwrap_or_default();
+        let markets: Vec<&str> = dex::ids().into_iter().filter(|x| !exclude.iter().any(|m| m == x)).collect();
 
-        let mut keys: Vec<Pubkey> = vec![];
-        for swap in &market_swap {
-            for step in &swap.step {
-                keys.push(step.pool_key.clone());
-                keys.push(step.quote_value_key.clone());
-                keys.push(step.base_value_key.clone());
-            }
-        }
+        let max_hops = self.max_hops.unwrap_or(DEFAULT_MAX_HOPS).min(MAX_HOPS);
+        let mut market_swap = registry.graph.find_swaps(&quote_token.mint, &base_token.mint, max_hops, MAX_ROUTE_CANDIDATES, &markets);
 
-        let client = RpcClient::new(NetworkType::Mainnet.url().to_string());
-        let commitment_config = CommitmentConfig::processed();
-        let accounts: Vec<Option<Account>> = client.get_multiple_accounts_with_commitment(&keys, commitment_config).unwrap().value;
+        //中间token必须有精度信息才能报价
+        market_swap.retain(|swap| {
+            swap.step.iter().all(|x| tokens_adr.contains_key(&x.quote_mint_key.to_string()) &&
+                tokens_adr.contains_key(&x.base_mint_key.to_string()))
+        });
+        if market_swap.is_empty() {
+            return Err(ApiError::NoRoute(self.quote_mint.to_string(), self.base_mint.to_string()));
+        }
 
-        let mut account_map = HashMap::new();
-        for (index, value) in accounts.iter().enumerate() {
-            let pubkey = &keys[index];
-            match value {
-                Some(account) => {
-                    account_map.insert(pubkey.to_string(), account.clone());
+        //报价只需要路径上token的精度信息
+        let mut route_tokens = HashMap::new();
+        for swap in &market_swap {
+            for step in &swap.step {
+                for mint in [step.quote_mint_key.to_string(), step.base_mint_key.to_string()] {
+                    if let Some(token) = tokens_adr.get(&mint) {
+                        route_tokens.insert(mint, token.clone());
+                    }
                 }
-                None => {}
             }
         }
 
-        //todo 暂时写死 50%拆单
-        let route_percent = 0.5;
-        let amount_in: f64 = self.amount_in.clone() * route_percent;
+        //缺少账户的池子在exclude_unavailable里剔除，不影响其它路径
+        let mut amms = route_amms(&market_swap);
+        let (account_map, slot) = cache.get_amm_accounts(&mut amms, &[]).await?;
 
-        let opt_init_data = OptInitData {
-            amount_in,
-            tokens_adr,
+        //请求里是带精度的数量，报价全程使用最小单位
+        let amount_in_raw = match self.amount_out {
+            Some(_) => 0,
+            None => raw_amount(amount, quote_decimals),
+        };
+        let mut opt_init_data = OptInitData {
+            amount_in: amount_in_raw,
+            tokens_adr: route_tokens,
             account_map,
             swaps: market_swap,
-            slippage: self.slippage.clone(),
+            amms: HashMap::new(),
+        };
+        //链上状态不允许swap的池子不参与报价，在响应里说明原因
+        let excluded = opt_init_data.exclude_unavailable();
+
+        let rank = match self.amount_out {
+            Some(amount_out) => {
+                //指定输出只走单条路径，反推各路径所需输入
+                let amount_out_raw = raw_amount(amount_out, base_decimals);
+                let opt = opt_init_data.calculate_exact_out(amount_out_raw);
+                let amount_in_raw = opt.iter().map(|x| x.amount_in_raw).min().unwrap_or(0);
+
+                OptRank {
+                    swap_mode: SwapMode::ExactOut,
+                    amount_in: ui_amount(amount_in_raw, quote_decimals),
+                    amount_in_raw,
+                    amount_out: ui_amount(amount_out_raw, base_decimals),
+                    amount_out_raw,
+                    max_amount_in: None,
+                    max_amount_in_raw: None,
+                    quote_mint: self.quote_mint.to_string(),
+                    base_mint: self.base_mint.to_string(),
+                    slippage: self.slippage,
+                    opt,
+                    excluded,
+                    slot,
+                    split: vec![],
+                    split_error: None,
+                    summary: QuoteSummary::default(),
+                }
+            }
+            None => {
+                //每条路径单独走全额的报价，以及多路径最优拆单
+                let opt = opt_init_data.calculate();
+                //拆单没能分配完整个amount_in时只返回单条路径的方案，并在响应里说明原因
+                let (split, split_error) = match opt_init_data.optimal_split() {
+                    Ok(split) => (split, None),
+                    Err(e) => (vec![], Some(e.to_string())),
+                };
+
+                let amount_out_raw = opt.iter().map(|x| x.amount_out_raw).max().unwrap_or(0);
+
+                OptRank {
+                    swap_mode: SwapMode::ExactIn,
+                    amount_in: ui_amount(amount_in_raw, quote_decimals),
+                    amount_in_raw,
+                    amount_out: ui_amount(amount_out_raw, base_decimals),
+                    amount_out_raw,
+                    max_amount_in: None,
+                    max_amount_in_raw: None,
+                    quote_mint: self.quote_mint.to_string(),
+                    base_mint: self.base_mint.to_string(),
+                    slippage: self.slippage,
+                    opt,
+                    excluded,
+                    slot,
+                    split,
+                    split_error,
+                    summary: QuoteSummary::default(),
+                }
+            }
         };
-        let opt = opt_init_data.calculate().unwrap();
-
-        OptRank {
-            amount_out: self.amount_in,
-            quote_mint: self.quote_mint.to_string(),
-            base_mint: self.base_mint.to_string(),
-            slippage: self.slippage,
-            opt,
+
+        //候选池子都被剔除或深度不够时没有可用路径
+        if rank.opt.is_empty() {
+            return Err(ApiError::NoRoute(self.quote_mint.to_string(), self.base_mint.to_string()));
         }
+        Ok(rank)
     }
 }
 
@@ -151,21 +195,21 @@ pub struct TokenAddr {
 }
 
 pub fn load_token_data_from_file(path: &String) -> Result<HashMap<String, TokenAddr>> {
-    let raw_info = fs::read_to_string(path).expect("Error read file");
+    let raw_info = fs::read_to_string(path)?;
     let vec: Vec<RawTokenAddr> = serde_json::from_str(&raw_info)?;
     let res: HashMap<String, TokenAddr> = vec
         .iter()
-        .map(|x| {
+        .map(|x| -> Result<(String, TokenAddr)> {
             let key = x.address.clone();
-            (key, TokenAddr {
+            Ok((key, TokenAddr {
                 name: x.symbol.to_string(),
-                mint: Pubkey::from_str(&x.address).unwrap(),
+                mint: Pubkey::from_str(&x.address)?,
                 decimal: x.decimals,
                 description: x.name.to_string(),
                 icon_uri: x.icon_uri.to_string(),
                 is_native: x.is_native.clone(),
-            })
+            }))
         })
-        .collect();
+        .collect::<Result<_>>()?;
     Ok(res)
 }
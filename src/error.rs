@@ -0,0 +1,155 @@
+use serde::{Serialize, Deserialize};
+use thiserror::Error;
+use std::io::Cursor;
+use rocket::http::{ContentType, Status};
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+use solana_client::client_error::ClientError;
+use market::amm::AmmError;
+
+pub type ApiResult<T> = std::result::Result<T, ApiError>;
+
+//接口出错时统一返回的body，code和错误类型一一对应，不随文案变化
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ErrorResponse {
+    pub code: u32,
+    pub msg: String,
+}
+
+#[derive(Debug, Error)]
+pub enum ApiError {
+    #[error("internal error: {0}")]
+    Internal(String),
+    #[error("build transaction failed: {0}")]
+    BuildTransaction(String),
+    #[error("unknown mint {0}")]
+    UnknownMint(String),
+    #[error("no route from {0} to {1}")]
+    NoRoute(String, String),
+    #[error("rpc request failed: {0}")]
+    Rpc(String),
+    #[error("account {0} not found")]
+    AccountNotFound(String),
+    #[error("decode account {0} failed")]
+    AccountDecode(String),
+    #[error("bad pagination: {0}")]
+    BadPagination(String),
+    #[error("invalid request: {0}")]
+    InvalidRequest(String),
+    #[error("upstream request failed: {0}")]
+    Upstream(String),
+    #[error("not found: {0}")]
+    NotFound(String),
+}
+
+impl ApiError {
+    //101、102沿用之前opt_swap和build_swap返回的code
+    pub fn code(&self) -> u32 {
+        match self {
+            ApiError::Internal(_) => 101,
+            ApiError::BuildTransaction(_) => 102,
+            ApiError::UnknownMint(_) => 103,
+            ApiError::NoRoute(_, _) => 104,
+            ApiError::Rpc(_) => 105,
+            ApiError::AccountNotFound(_) => 106,
+            ApiError::AccountDecode(_) => 107,
+            ApiError::BadPagination(_) => 108,
+            ApiError::InvalidRequest(_) => 109,
+            ApiError::Upstream(_) => 110,
+            ApiError::NotFound(_) => 111,
+        }
+    }
+
+    pub fn status(&self) -> Status {
+        match self {
+            ApiError::Internal(_) => Status::InternalServerError,
+            ApiError::BuildTransaction(_) => Status::UnprocessableEntity,
+            ApiError::UnknownMint(_) => Status::BadRequest,
+            ApiError::NoRoute(_, _) => Status::NotFound,
+            ApiError::Rpc(_) => Status::BadGateway,
+            ApiError::AccountNotFound(_) => Status::BadGateway,
+            ApiError::AccountDecode(_) => Status::BadGateway,
+            ApiError::BadPagination(_) => Status::BadRequest,
+            ApiError::InvalidRequest(_) => Status::BadRequest,
+            ApiError::Upstream(_) => Status::BadGateway,
+            ApiError::NotFound(_) => Status::NotFound,
+        }
+    }
+}
+
+impl From<ClientError> for ApiError {
+    fn from(e: ClientError) -> Self {
+        ApiError::Rpc(e.to_string())
+    }
+}
+
+impl From<reqwest::Error> for ApiError {
+    fn from(e: reqwest::Error) -> Self {
+        ApiError::Upstream(e.to_string())
+    }
+}
+
+impl From<std::io::Error> for ApiError {
+    fn from(e: std::io::Error) -> Self {
+        ApiError::Internal(e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for ApiError {
+    fn from(e: serde_json::Error) -> Self {
+        ApiError::Internal(e.to_string())
+    }
+}
+
+//opt_core和market里用的是anyhow，里面包着ApiError时原样取出来
+impl From<anyhow::Error> for ApiError {
+    fn from(e: anyhow::Error) -> Self {
+        match e.downcast::<ApiError>() {
+            Ok(api_error) => api_error,
+            Err(e) => match e.downcast::<ClientError>() {
+                Ok(client_error) => client_error.into(),
+                Err(e) => match e.downcast::<AmmError>() {
+                    Ok(AmmError::AccountNotFound(key)) => ApiError::AccountNotFound(key.to_string()),
+                    Ok(AmmError::AccountDecode(key)) => ApiError::AccountDecode(key.to_string()),
+                    Err(e) => ApiError::Internal(e.to_string()),
+                },
+            },
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, _req: &'r Request<'_>) -> response::Result<'static> {
+        println!("api error: {}", self);
+        let body = serde_json::to_string(&ErrorResponse {
+            code: self.code(),
+            msg: self.to_string(),
+        }).unwrap_or_default();
+        Response::build()
+            .status(self.status())
+            .header(ContentType::JSON)
+            .sized_body(body.len(), Cursor::new(body))
+            .ok()
+    }
+}
+
+//rocket自己产生的错误也按ErrorResponse返回：没有匹配的路由、请求体不是合法的JSON或缺少字段
+#[catch(400)]
+pub fn bad_request(_req: &Request) -> ApiError {
+    ApiError::InvalidRequest("malformed request".to_string())
+}
+
+#[catch(404)]
+pub fn not_found(req: &Request) -> ApiError {
+    ApiError::NotFound(req.uri().path().to_string())
+}
+
+#[catch(422)]
+pub fn unprocessable_entity(_req: &Request) -> ApiError {
+    ApiError::InvalidRequest("malformed JSON body".to_string())
+}
+
+#[catch(500)]
+pub fn internal_error(_req: &Request) -> ApiError {
+    ApiError::Internal("internal server error".to_string())
+}
@@ -1,18 +1,19 @@
-#![feature(proc_macro_hygiene)]
-#![feature(decl_macro)]
-#![feature(total_cmp)]
-
 pub mod api;
 pub mod response;
 pub mod node_client;
 mod opt_core;
+mod adapter;
 mod rpc_client;
 pub mod pool;
 pub mod token;
+pub mod transaction;
+pub mod error;
+pub mod registry;
+pub mod account_cache;
+pub mod price;
 
 #[macro_use]
 extern crate rocket;
-extern crate rocket_contrib;
 extern crate rocket_cors;
 extern crate serde;
 extern crate anyhow;
@@ -23,23 +24,32 @@ extern crate rust_decimal;
 extern crate market;
 extern crate solana_client;
 extern crate bytemuck;
-extern crate spl_token_swap;
 extern crate num_traits;
 extern crate reqwest;
 extern crate safe_transmute;
+extern crate thiserror;
 
 
-use rocket_contrib::json::Json;
+use rocket::serde::json::Json;
 use api::OptRequest;
-use response::{OptResponse, TokenListResponse};
-use rpc_client::{AccountRequest, TxResponse};
+use response::{OptResponse, TokenListResponse, BuildSwapResponse};
+use rpc_client::{AccountRequest, HistorySource, TxResponse};
 use rocket::http::Method;
 use rocket_cors::{Cors, AllowedOrigins, AllowedHeaders};
 use pool::pool::PoolRequest;
 use market::pool::PoolResponse;
 use crate::response::PoolListResponse;
-use crate::rpc_client::{AssetResponse, EtherscanResp, EthFee, OutApiResponse};
+use crate::rpc_client::{AssetResponse, EtherscanResp, EthFee, OutApiResponse, PortfolioResponse};
 use crate::token::token::WoreholeAddress;
+use transaction::BuildSwapRequest;
+use error::{ApiError, ApiResult};
+use registry::{Registry, AdminToken};
+use account_cache::{AccountCache, RpcAccountSource};
+use node_client::{RpcConfig, RpcPool};
+use response::ReloadResponse;
+use price::{PriceConfig, PriceHistory, PriceResponse};
+use rocket::State;
+use std::sync::Arc;
 
 
 #[get("/")]
@@ -47,122 +57,172 @@ fn index() -> &'static str {
     "Hello, world!"
 }
 
-#[get("/history?<address>&<before>")]
-fn history(address: String, before: Option<String>) -> Json<Vec<TxResponse>> {
+//source为solscan(默认)或rpc，limit最多50
+#[get("/history?<address>&<before>&<limit>&<source>")]
+async fn history(rpc: &State<Arc<RpcPool>>, address: String, before: Option<String>,
+                 limit: Option<u32>, source: Option<String>) -> ApiResult<Json<Vec<TxResponse>>> {
+    let source = match source {
+        Some(source) => source.parse::<HistorySource>()?,
+        None => HistorySource::Solscan,
+    };
     let req = AccountRequest {
         address,
         before,
+        limit,
     };
-    Json(req.get_history())
+    Ok(Json(req.get_history(source, rpc).await?))
 }
 
 #[get("/assets?<address>")]
-fn assets(address: String) -> Json<OutApiResponse<AssetResponse>> {
+async fn assets(registry: &State<Arc<Registry>>, rpc: &State<Arc<RpcPool>>,
+                cache: &State<Arc<AccountCache>>, address: String) -> ApiResult<Json<OutApiResponse<AssetResponse>>> {
     let req = AccountRequest {
         address,
         before: None,
+        limit: None,
     };
 
-    Json(OutApiResponse {
+    Ok(Json(OutApiResponse {
         success: true,
-        data: req.get_assets(),
-    })
+        data: req.get_assets(&registry.data(), rpc, cache).await?,
+    }))
+}
+
+//按链上储备给钱包资产估值，单位USDC
+#[get("/portfolio?<address>")]
+async fn portfolio(registry: &State<Arc<Registry>>, rpc: &State<Arc<RpcPool>>,
+                   cache: &State<Arc<AccountCache>>, address: String) -> ApiResult<Json<PortfolioResponse>> {
+    let req = AccountRequest {
+        address,
+        before: None,
+        limit: None,
+    };
+    Ok(Json(req.get_portfolio(&registry.data(), rpc, cache).await?))
+}
+
+//base相对quote的中间价、标准交易额的价格冲击和twap，quote_mint默认USDC
+#[get("/price?<base_mint>&<quote_mint>&<twap_secs>")]
+async fn pair_price(registry: &State<Arc<Registry>>, cache: &State<Arc<AccountCache>>, history: &State<Arc<PriceHistory>>,
+                    base_mint: String, quote_mint: Option<String>, twap_secs: Option<u64>) -> ApiResult<Json<PriceResponse>> {
+    Ok(Json(price::get_price(&registry.data(), cache, history, &base_mint, quote_mint.as_deref(), twap_secs).await?))
 }
 
 #[get("/token_list?<page>&<pagesize>&<search>&<address>&<symbol>&<chain>")]
-fn token_list(page: Option<u32>, pagesize: Option<u32>,
+fn token_list(registry: &State<Arc<Registry>>,
+              page: Option<u32>, pagesize: Option<u32>,
               search: Option<String>, address: Option<String>,
-              symbol: Option<String>, chain: Option<String>) -> Json<TokenListResponse> {
-    match chain {
-        None => {
-            println!("none");
-            token::token::token_list(page, pagesize, search, address, symbol)
-        }
-        Some(chain_type) => {
-            if chain_type.eq("solana") {
-                println!("solana");
-                token::token::token_list(page, pagesize, search, address, symbol)
-            } else if chain_type.eq("ethereum") {
-                println!("ethereum");
-                token::token::eth_tokens(page, pagesize, search, address, symbol)
-            } else {
-                println!("else");
-                Json(TokenListResponse {
-                    total: 0,
-                    pagesize: 0,
-                    page: 0,
-                    data: vec![],
-                })
-            }
-        }
+              symbol: Option<String>, chain: Option<String>) -> ApiResult<Json<TokenListResponse>> {
+    let registry = registry.data();
+    match chain.as_deref() {
+        None | Some("solana") => token::token::token_list(&registry, page, pagesize, search, address, symbol),
+        Some("ethereum") => token::token::eth_tokens(&registry, page, pagesize, search, address, symbol),
+        Some(_) => Ok(Json(TokenListResponse {
+            total: 0,
+            pagesize: 0,
+            page: 0,
+            data: vec![],
+        })),
     }
 }
 
 #[get("/pool_list?<page>&<pagesize>&<lp_mint>&<farm_mint>&<address>&<market>&<search>")]
-fn pool_list(page: Option<u32>, pagesize: Option<u32>,
+fn pool_list(registry: &State<Arc<Registry>>,
+             page: Option<u32>, pagesize: Option<u32>,
              lp_mint: Option<String>, farm_mint: Option<String>,
              address: Option<String>, market: Option<String>,
-             search: Option<String>) -> Json<PoolListResponse> {
-    pool::pool::pool_list(page, pagesize, lp_mint, farm_mint, address, market, search)
+             search: Option<String>) -> ApiResult<Json<PoolListResponse>> {
+    pool::pool::pool_list(&registry.data(), page, pagesize, lp_mint, farm_mint, address, market, search)
 }
 
 #[post("/opt_swap", data = "<req>")]
-fn opt_swap(req: Json<OptRequest>) -> Json<OptResponse> {
-    println!("req={:?}", req.0);
-
-    let mut opt_market = req.0.load_data();
-    let opt_rank = opt_market.opt_best();
-    let response;
-    match opt_rank {
-        Ok(res) => {
-            response = Json(OptResponse {
-                code: 0,
-                msg: "success".to_string(),
-                data: res,
-            });
-        }
-        Err(_e) => {
-            response = Json(OptResponse {
-                code: 101,
-                msg: "error".to_string(),
-                data: vec![],
-            });
-        }
-    }
-    response
+async fn opt_swap(registry: &State<Arc<Registry>>, cache: &State<Arc<AccountCache>>, req: Json<OptRequest>) -> ApiResult<Json<OptResponse>> {
+    let mut opt_market = req.0.load_data(&registry.data(), cache).await?;
+    let opt_rank = opt_market.opt_best()?;
+    Ok(Json(OptResponse {
+        code: 0,
+        msg: "success".to_string(),
+        data: opt_rank,
+    }))
+}
+
+#[post("/build_swap", data = "<req>")]
+async fn build_swap(rpc: &State<Arc<RpcPool>>, req: Json<BuildSwapRequest>) -> ApiResult<Json<BuildSwapResponse>> {
+    req.validate()?;
+    let transaction = req.0.build(rpc).await.map_err(|e| ApiError::BuildTransaction(e.to_string()))?;
+    Ok(Json(BuildSwapResponse {
+        code: 0,
+        msg: "success".to_string(),
+        transaction: Some(transaction),
+    }))
 }
 
 #[post("/pool_info", data = "<req>")]
-fn pool_info(req: Json<PoolRequest>) -> Json<Vec<PoolResponse>> {
-    pool::pool::pool_info(req)
+async fn pool_info(registry: &State<Arc<Registry>>, cache: &State<Arc<AccountCache>>, req: Json<PoolRequest>) -> ApiResult<Json<Vec<PoolResponse>>> {
+    pool::pool::pool_info(&registry.data(), cache, req).await
+}
+
+//重新加载池子和token文件，文件变化时后台也会自动加载
+#[post("/admin/reload")]
+fn admin_reload(registry: &State<Arc<Registry>>, _token: AdminToken) -> ApiResult<Json<ReloadResponse>> {
+    let data = registry.reload()?;
+    Ok(Json(ReloadResponse {
+        code: 0,
+        msg: "success".to_string(),
+        tokens: data.tokens.len(),
+        pools: data.pools.len(),
+    }))
 }
 
 #[get("/bridge_token?<source_chain>&<to_chain>&<origin_address>&<wrap_address>")]
 fn bridge_token(source_chain: String, to_chain: String,
-                origin_address: Option<String>, wrap_address: Option<String>) -> Json<WoreholeAddress> {
-    if origin_address.is_some() {
-        Json(token::token::bridge_token_by_origin(source_chain, to_chain, origin_address.unwrap()))
-    } else if wrap_address.is_some() {
-        Json(token::token::bridge_token_by_wrap(source_chain, to_chain, wrap_address.unwrap()))
+                origin_address: Option<String>, wrap_address: Option<String>) -> ApiResult<Json<WoreholeAddress>> {
+    if let Some(origin_address) = origin_address {
+        Ok(Json(token::token::bridge_token_by_origin(source_chain, to_chain, origin_address)?))
+    } else if let Some(wrap_address) = wrap_address {
+        Ok(Json(token::token::bridge_token_by_wrap(source_chain, to_chain, wrap_address)?))
     } else {
-        Json(WoreholeAddress {
+        Ok(Json(WoreholeAddress {
             origin_address: "".to_string(),
             target_token: None,
-        })
+        }))
     }
 }
 
 #[get("/eth_fee")]
-fn eth_fee() -> Json<EtherscanResp<EthFee>> {
-    Json(rpc_client::EthFee::gastracker())
+async fn eth_fee() -> ApiResult<Json<EtherscanResp<EthFee>>> {
+    Ok(Json(rpc_client::EthFee::gastracker().await?))
 }
 
-fn main() {
-    rocket::ignite()
+#[launch]
+async fn rocket() -> _ {
+    //池子和token文件只在启动时解析一次，之后按文件修改时间热加载
+    let registry = Arc::new(Registry::load().expect("load registry fail"));
+    Registry::watch(registry.clone());
+
+    let rocket = rocket::build();
+    //节点地址、commitment和超时从Rocket.toml或ROCKET_RPC_*环境变量读取
+    let rpc_config = RpcConfig::from_rocket(rocket.figment()).expect("rpc config error");
+    let rpc = Arc::new(RpcPool::new(&rpc_config));
+
+    //报价和pool_info读取的链上账户由后台订阅和轮询保持最新
+    let source = Box::new(RpcAccountSource::new(rpc.clone()));
+    let cache = Arc::new(AccountCache::new(source, rpc_config.ws_urls.clone(), rpc_config.commitment));
+    AccountCache::start(cache.clone());
+
+    //查询过的交易对定期记录中间价，用于计算twap
+    let price_config = PriceConfig::from_rocket(rocket.figment()).expect("price config error");
+    let price_history = Arc::new(PriceHistory::new(price_config));
+    PriceHistory::start(price_history.clone(), registry.clone(), cache.clone());
+
+    rocket
+        .manage(registry)
+        .manage(rpc)
+        .manage(cache)
+        .manage(price_history)
         .mount("/", routes![index, assets, opt_swap, token_list,
-            pool_list, history, pool_info, bridge_token,eth_fee])
+            pool_list, history, pool_info, bridge_token,eth_fee, build_swap, admin_reload, portfolio, pair_price])
+        .register("/", catchers![error::bad_request, error::not_found, error::unprocessable_entity, error::internal_error])
         .attach(get_cors())
-        .launch();
 }
 
 fn get_cors() -> Cors {
@@ -1,18 +1,36 @@
-use std::error::Error;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
 
-use anyhow::Result;
-use solana_program::pubkey::Pubkey;
-use solana_sdk::{signature::Keypair, signer::Signer};
-use solana_client::{client_error::Result as ClientResult, rpc_client::RpcClient};
+use anyhow::{Result, anyhow};
+use rocket::figment::Figment;
+use serde::de::DeserializeOwned;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_client::client_error::{ClientErrorKind, Result as ClientResult};
+use solana_client::nonblocking::rpc_client::RpcClient as NonblockingRpcClient;
+use solana_client::rpc_request::RpcError;
+use solana_client::rpc_custom_error::JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY;
+use reqwest::Url;
+
+//Rocket.toml里的配置项，也可以用ROCKET_RPC_CLUSTER这样的环境变量覆盖
+const RPC_CLUSTER: &str = "rpc_cluster";
+const RPC_FALLBACK_URLS: &str = "rpc_fallback_urls";
+const RPC_WS_URL: &str = "rpc_ws_url";
+const RPC_COMMITMENT: &str = "rpc_commitment";
+const RPC_TIMEOUT_SECS: &str = "rpc_timeout_secs";
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+//JSON-RPC的Internal error，节点自身出错
+const JSON_RPC_INTERNAL_ERROR: i64 = -32603;
 
 pub struct NetworkOpts {
-    url: String,
+    pub url: String,
 }
 
 pub enum NetworkType {
     Mainnet,
     Devnet,
     Serum,
+    Localnet,
     Custom(NetworkOpts),
 }
 
@@ -22,35 +40,151 @@ impl NetworkType {
             NetworkType::Devnet => "https://api.devnet.solana.com",
             NetworkType::Mainnet => "https://api.mainnet-beta.solana.com",
             NetworkType::Serum => "https://solana-api.projectserum.com",
+            NetworkType::Localnet => "http://127.0.0.1:8899",
             NetworkType::Custom(nework_opts) => &nework_opts.url,
         }
     }
+
+    //mainnet/devnet/serum/localnet，其它值当作节点地址
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "mainnet" => NetworkType::Mainnet,
+            "devnet" => NetworkType::Devnet,
+            "serum" => NetworkType::Serum,
+            "localnet" => NetworkType::Localnet,
+            url => NetworkType::Custom(NetworkOpts { url: url.to_string() }),
+        }
+    }
+}
+
+pub struct RpcConfig {
+    //第一个是主节点，后面按顺序故障切换
+    pub urls: Vec<String>,
+    //websocket订阅的节点，连接失败时按顺序切换。为空时不使用websocket订阅
+    pub ws_urls: Vec<String>,
+    pub commitment: CommitmentConfig,
+    pub timeout: Duration,
 }
 
-pub fn get_rpc_client(network: &NetworkType) -> ClientResult<RpcClient> {
-    let client = RpcClient::new(network.url().to_string());
+impl RpcConfig {
+    //没有配置时使用mainnet公共节点、processed和30秒超时
+    pub fn from_rocket(figment: &Figment) -> Result<Self> {
+        let cluster: String = extract(figment, RPC_CLUSTER)?.unwrap_or_else(|| "mainnet".to_string());
+        let mut urls = vec![NetworkType::from_name(&cluster).url().to_string()];
+        let fallback: Vec<String> = extract(figment, RPC_FALLBACK_URLS)?.unwrap_or_default();
+        for url in fallback {
+            urls.push(NetworkType::from_name(&url).url().to_string());
+        }
+
+        //没有单独配置时由各个节点地址推出，和节点一起切换。配置成空字符串表示只用轮询
+        let ws_urls = match extract::<String>(figment, RPC_WS_URL)? {
+            Some(url) if url.is_empty() => vec![],
+            Some(url) => vec![url],
+            None => urls.iter().filter_map(|x| compute_websocket_url(x)).collect(),
+        };
+
+        let commitment: String = extract(figment, RPC_COMMITMENT)?.unwrap_or_else(|| "processed".to_string());
+        let commitment = match commitment.as_str() {
+            "processed" => CommitmentConfig::processed(),
+            "confirmed" => CommitmentConfig::confirmed(),
+            "finalized" => CommitmentConfig::finalized(),
+            other => return Err(anyhow!("unknown {} {}", RPC_COMMITMENT, other)),
+        };
 
-    let version = client.get_version()?;
-    println!("RPC version: {:?}", version);
-    Ok(client)
+        let timeout = match extract::<u64>(figment, RPC_TIMEOUT_SECS)? {
+            Some(0) => return Err(anyhow!("{} must be positive", RPC_TIMEOUT_SECS)),
+            Some(secs) => Duration::from_secs(secs),
+            None => Duration::from_secs(DEFAULT_TIMEOUT_SECS),
+        };
+
+        Ok(RpcConfig {
+            urls,
+            ws_urls,
+            commitment,
+            timeout,
+        })
+    }
 }
 
-pub struct Client {
-    rpc_client: RpcClient,
-    payer: Keypair,
+//和solana_cli_config::compute_websocket_url一致：http换成ws，https换成wss，指定了端口时用下一个端口
+pub fn compute_websocket_url(json_rpc_url: &str) -> Option<String> {
+    let json_rpc_url: Url = json_rpc_url.parse().ok()?;
+    let mut ws_url = json_rpc_url.clone();
+    let scheme = if json_rpc_url.scheme().eq_ignore_ascii_case("https") { "wss" } else { "ws" };
+    ws_url.set_scheme(scheme).ok()?;
+    if let Some(port) = json_rpc_url.port() {
+        ws_url.set_port(Some(port.checked_add(1)?)).ok()?;
+    }
+    Some(ws_url.to_string())
 }
 
-impl Client {
-    pub fn new(network_type: NetworkType, payer: Keypair, _path: &String) -> Result<Self, Box::<dyn Error>> {
-        let client = get_rpc_client(&network_type)?;
-        Ok(Client { rpc_client: client, payer })
+//没有配置的项返回None，配置了但类型不对时报错
+pub(crate) fn extract<T: DeserializeOwned>(figment: &Figment, key: &str) -> Result<Option<T>> {
+    if !figment.contains(key) {
+        return Ok(None);
     }
+    Ok(Some(figment.extract_inner(key)?))
+}
+
+//按配置顺序的一组节点，请求失败时换下一个节点重试，成功的节点作为之后请求的首选
+pub struct RpcPool {
+    clients: Vec<(String, NonblockingRpcClient)>,
+    commitment: CommitmentConfig,
+    current: AtomicUsize,
+}
 
-    pub fn rpc(&self) -> &RpcClient {
-        &self.rpc_client
+impl RpcPool {
+    pub fn new(config: &RpcConfig) -> Self {
+        let clients = config.urls.iter()
+            .map(|url| (url.clone(), NonblockingRpcClient::new_with_timeout_and_commitment(url.clone(), config.timeout, config.commitment)))
+            .collect();
+        RpcPool {
+            clients,
+            commitment: config.commitment,
+            current: AtomicUsize::new(0),
+        }
     }
-    pub fn payer(&self) -> Pubkey {
-        self.payer.pubkey()
+
+    pub fn commitment(&self) -> CommitmentConfig {
+        self.commitment
+    }
+
+    pub async fn call<'a, T, F, Fut>(&'a self, f: F) -> ClientResult<T>
+        where F: Fn(&'a NonblockingRpcClient) -> Fut,
+              Fut: Future<Output = ClientResult<T>> {
+        let start = self.current.load(Ordering::Relaxed);
+        let mut last_error = None;
+        for offset in 0..self.clients.len() {
+            let index = (start + offset) % self.clients.len();
+            let (url, client) = &self.clients[index];
+            match f(client).await {
+                Ok(value) => {
+                    if index != start {
+                        println!("rpc switch to {}", url);
+                        self.current.store(index, Ordering::Relaxed);
+                    }
+                    return Ok(value);
+                }
+                //只有网络和节点本身的错误才切换，参数错误换节点也一样
+                Err(e) if should_failover(e.kind()) => {
+                    println!("rpc {} fail: {}", url, e);
+                    last_error = Some(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Err(last_error.expect("rpc pool has no url"))
     }
 }
 
+//网络错误、HTTP错误和节点不健康时切换。账户不存在、参数不对这类RPC返回的错误换节点结果也一样
+fn should_failover(kind: &ClientErrorKind) -> bool {
+    match kind {
+        ClientErrorKind::Io(_) | ClientErrorKind::Reqwest(_) => true,
+        ClientErrorKind::RpcError(RpcError::RpcRequestError(_)) => true,
+        ClientErrorKind::RpcError(RpcError::RpcResponseError { code, .. }) => {
+            *code == JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY || *code == JSON_RPC_INTERNAL_ERROR
+        }
+        _ => false,
+    }
+}
@@ -1,444 +1,692 @@
 use crate::market;
 use crate::response;
 use crate::api;
+use crate::adapter::{market_pool_amm, swap_direction};
+use crate::error::ApiError;
+use crate::transaction::is_buildable;
 use serde::{Serialize, Deserialize};
-use market::market::MarketSwap;
-use std::collections::HashMap;
+use market::market::{MarketSwap, MarketPool};
+use market::amm::{Amm, AmmQuote, FeeSide, SwapDirection};
+use std::collections::{HashMap, HashSet};
 use solana_sdk::account::Account;
-use market::market::MarketType::*;
-use response::{OptRoute, OptMarket};
+use response::{OptRoute, OptMarket, ExcludedPool, RouteFee, ui_amount};
 use anyhow::Result;
-use market::raydium::stats::AmmInfo;
-use market::saber::state::SwapInfo;
-use solana_program::account_info::AccountInfo;
-use spl_token_swap::processor::Processor;
-use rust_decimal::Decimal;
 use api::TokenAddr;
-use bytemuck::__core::ops::{Add, Mul, Div, Sub};
-use rust_decimal::prelude::{ToPrimitive, FromPrimitive};
 use solana_program::pubkey::Pubkey;
-use spl_token_swap::state::SwapV1;
-use spl_token_swap::solana_program::program_pack::Pack;
-use spl_token_swap::curve::stable::StableCurve;
-use spl_token_swap::curve::calculator::{TradeDirection, CurveCalculator};
-use spl_token_swap::curve::base::SwapCurve;
-use market::saber::curve::StableSwap;
-use solana_program::clock::Clock;
-
-#[derive(Debug, Serialize, Deserialize)]
+
+//拆单份数，每份占amount_in的2%
+const SPLIT_PARTS: u64 = 50;
+//参与拆单的最多路径数
+const SPLIT_MAX_ROUTES: usize = 5;
+//反推输入时的上限(最小单位)
+const EXACT_OUT_MAX_INPUT: u64 = 1 << 60;
+//按池子储备的十万分之一报价，价格冲击可以忽略
+pub const MID_PROBE_DIVISOR: u64 = 100_000;
+
+//数量全部使用链上最小单位，只在OptRoute/OptMarket里换算成带精度的数量
+#[derive(Serialize, Deserialize)]
 pub struct OptInitData {
-    pub amount_in: f64,
+    pub amount_in: u64,
     pub tokens_adr: HashMap<String, TokenAddr>,
     pub account_map: HashMap<String, Account>,
     pub swaps: Vec<MarketSwap>,
-    pub slippage: f32,
+    //exclude_unavailable里按账户数据更新好的各池子adapter，报价时直接使用
+    #[serde(skip)]
+    pub amms: HashMap<Pubkey, Box<dyn Amm>>,
 }
 
 impl OptInitData {
-    pub fn calculate(&self) -> Result<Vec<OptMarket>> {
+    //为每个池子加载adapter，剔除链上状态不允许swap的池子，经过这些池子的路径都不参与报价。
+    //账户缺失或解析失败的池子同样剔除。返回被剔除的池子和原因
+    pub fn exclude_unavailable(&mut self) -> Vec<ExcludedPool> {
+        let mut excluded = vec![];
+        let mut checked = HashSet::new();
+        for swap in self.swaps.iter() {
+            for step in swap.step.iter() {
+                if !checked.insert(step.pool_key) {
+                    continue;
+                }
+                let mut amm = match market_pool_amm(step) {
+                    Some(amm) => amm,
+                    None => continue,
+                };
+                let reason = match amm.update(&self.account_map) {
+                    Ok(()) => amm.unavailable_reason(),
+                    Err(e) => Some(e.to_string()),
+                };
+                match reason {
+                    Some(reason) => {
+                        let (market, _program_id) = step.market_type.get_name();
+                        excluded.push(ExcludedPool {
+                            pool_key: step.pool_key.to_string(),
+                            market,
+                            reason,
+                        });
+                    }
+                    None => {
+                        self.amms.insert(step.pool_key, amm);
+                    }
+                }
+            }
+        }
+
+        let excluded_keys: HashSet<String> = excluded.iter().map(|x: &ExcludedPool| x.pool_key.clone()).collect();
+        self.swaps.retain(|swap| swap.step.iter().all(|x| !excluded_keys.contains(&x.pool_key.to_string())));
+        excluded
+    }
+
+    //每条路径按全额报价。报价失败的路径(例如输入超出池子能接受的范围)和剔除的池子一样丢弃，不影响其它路径
+    pub fn calculate(&self) -> Vec<OptMarket> {
         let mut res = vec![];
 
         for swap in self.swaps.iter() {
-            let market_type = swap.market_type.clone();
-            let swap_amount_in = self.amount_in.clone();
-            match market_type {
-                Raydium(x, y) => {
-                    let mut market_swap = cal_raydium(swap_amount_in, swap,
-                                                      &self.account_map, &self.tokens_adr, self.slippage).unwrap();
-                    market_swap.set_info(x, y);
-                    res.push(market_swap);
+            if let Ok(Some(mut market_swap)) = self.quote(swap, self.amount_in) {
+                market_swap.mid_rate = self.mid_rate(swap).unwrap_or(None);
+                res.push(market_swap);
+            }
+        }
+
+        res
+    }
+
+    //指定输出数量，对每条路径反推所需输入。结果不含滑点，滑点体现在最大输入上。报价失败的路径同样丢弃
+    pub fn calculate_exact_out(&self, amount_out: u64) -> Vec<OptMarket> {
+        let mut res = vec![];
+
+        for swap in self.swaps.iter() {
+            if let Ok(Some(market_swap)) = self.quote_exact_out(swap, amount_out) {
+                res.push(market_swap);
+            }
+        }
+
+        res
+    }
+
+    //产出随输入单调递增，按源token最小单位二分查找恰好得到amount_out的最少输入，
+    //三种曲线(恒定乘积、spl-token-swap stable、saber StableSwap)都适用且和正向报价的取整一致
+    fn quote_exact_out(&self, swap: &MarketSwap, amount_out: u64) -> Result<Option<OptMarket>> {
+        let mut low: u64 = 0;
+        let mut high: u64 = 1;
+        let mut max_input = EXACT_OUT_MAX_INPUT;
+        loop {
+            match self.quote(swap, high)? {
+                Some(market_swap) => {
+                    if market_swap.amount_out_raw >= amount_out {
+                        break;
+                    }
+                    //输入到首个池子储备的10倍还不够，认为池子深度不够
+                    max_input = max_input.min(market_swap.routes[0].source_value.saturating_mul(10));
                 }
-                Orca(x, y) => {
-                    let mut market_swap = cal_orca(swap_amount_in, swap,
-                                                   &self.account_map, &self.tokens_adr, self.slippage).unwrap();
-                    market_swap.set_info(x, y);
-                    res.push(market_swap);
+                None => return Ok(None),
+            }
+            if high >= max_input {
+                return Ok(None);
+            }
+            low = high;
+            high = high.saturating_mul(2).min(max_input);
+        }
+
+        while high - low > 1 {
+            let mid = low + (high - low) / 2;
+            match self.quote(swap, mid)? {
+                Some(market_swap) => {
+                    if market_swap.amount_out_raw >= amount_out {
+                        high = mid;
+                    } else {
+                        low = mid;
+                    }
+                }
+                None => return Ok(None),
+            }
+        }
+
+        match self.quote(swap, high)? {
+            Some(mut market_swap) => {
+                market_swap.mid_rate = self.mid_rate(swap)?;
+                Ok(Some(market_swap))
+            }
+            None => Ok(None),
+        }
+    }
+
+    //池子的adapter，没有经过exclude_unavailable时临时加载到loaded里
+    fn step_amm<'a>(&'a self, step: &MarketPool, loaded: &'a mut Option<Box<dyn Amm>>) -> Result<Option<&'a dyn Amm>> {
+        if let Some(amm) = self.amms.get(&step.pool_key) {
+            return Ok(Some(amm.as_ref()));
+        }
+        match market_pool_amm(step) {
+            Some(mut amm) => {
+                amm.update(&self.account_map)?;
+                Ok(Some(loaded.insert(amm).as_ref()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    //按给定数量对单条路径报价，每一跳按所在池子的市场选择曲线。
+    //结果是不含滑点的预期产出，滑点在OptRank的min_out里统一扣除
+    pub fn quote(&self, swap: &MarketSwap, amount_in: u64) -> Result<Option<OptMarket>> {
+        let mut routes = vec![];
+        let mut amount = amount_in;
+
+        for step in swap.step.iter() {
+            let mut loaded = None;
+            let amm = match self.step_amm(step, &mut loaded)? {
+                Some(amm) => amm,
+                None => return Ok(None),
+            };
+            let quote = amm.quote(amount, swap_direction(step))?;
+            let route = build_route(step, &self.tokens_adr, quote)?;
+            amount = route.destination_amount_raw;
+            routes.push(route);
+        }
+
+        let source_decimals = routes[0].source_decimals;
+        let destination_decimals = routes[routes.len() - 1].destination_decimals;
+
+        let (market, program_id) = swap.market_name();
+        Ok(Some(OptMarket {
+            market,
+            program_id,
+            amount_in: ui_amount(amount_in, source_decimals),
+            amount_in_raw: amount_in,
+            amount_out: ui_amount(amount, destination_decimals),
+            amount_out_raw: amount,
+            percentage: 1.0,
+            mid_rate: None,
+            buildable: routes.iter().all(|x| is_buildable(&x.market)),
+            routes,
+        }))
+    }
+
+    //不计手续费和价格冲击时1个输入能换多少输出(带精度)。每一跳按储备的一小部分报价再还原手续费，
+    //订单簿挂单太薄、报价产出为0时返回None
+    pub fn mid_rate(&self, swap: &MarketSwap) -> Result<Option<f64>> {
+        let mut rate = 1.0;
+        for step in swap.step.iter() {
+            let mut loaded = None;
+            let amm = match self.step_amm(step, &mut loaded)? {
+                Some(amm) => amm,
+                None => return Ok(None),
+            };
+            let direction = swap_direction(step);
+            let (quote_reserve, base_reserve) = amm.reserves();
+            let reserve = match direction {
+                SwapDirection::QuoteToBase => quote_reserve,
+                SwapDirection::BaseToQuote => base_reserve,
+            };
+            let quote = amm.quote((reserve / MID_PROBE_DIVISOR).max(1), direction)?;
+            if quote.amount_out == 0 || quote.fee_rate >= 1.0 {
+                return Ok(None);
+            }
+            let route = build_route(step, &self.tokens_adr, quote)?;
+            rate *= route.destination_amount / route.source_amount / route.fee_factor;
+        }
+        Ok(Some(rate))
+    }
+
+    //第parts份对应的输入数量
+    fn part_amount(&self, parts: u64) -> u64 {
+        (self.amount_in as u128 * parts as u128 / SPLIT_PARTS as u128) as u64
+    }
+
+    //最优拆单：把amount_in切成SPLIT_PARTS份，每一份分给当前边际产出最大的路径。
+    //各路径产出曲线是凹的，逐份贪心分配即可逼近边际价格相等的最优解。
+    //没有路径能接下剩余的份数时返回错误，返回的方案各路径输入之和总是等于amount_in
+    pub fn optimal_split(&self) -> Result<Vec<OptMarket>> {
+        //先用全额报价给路径排序，全额报价失败的路径仍然可以分到一部分，排在最后
+        let mut ranked = vec![];
+        for (index, swap) in self.swaps.iter().enumerate() {
+            match self.quote(swap, self.amount_in) {
+                Ok(Some(market_swap)) => ranked.push((index, market_swap.amount_out_raw)),
+                Ok(None) => {}
+                Err(_) => ranked.push((index, 0)),
+            }
+        }
+        ranked.sort_by(|a, b| b.1.cmp(&a.1));
+
+        //共用池子的路径互相影响储备，报价不能独立相加，只保留产出更高的那条
+        let mut used_pools = HashSet::new();
+        let mut candidates = vec![];
+        for (index, _) in ranked {
+            if candidates.len() >= SPLIT_MAX_ROUTES {
+                break;
+            }
+            let pools: Vec<Pubkey> = self.swaps[index].step.iter().map(|x| x.pool_key).collect();
+            if pools.iter().any(|x| used_pools.contains(x)) {
+                continue;
+            }
+            used_pools.extend(pools);
+            candidates.push(index);
+        }
+
+        if candidates.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let mut allocated = vec![0u64; candidates.len()];
+        let mut outputs = vec![0u64; candidates.len()];
+
+        for _ in 0..SPLIT_PARTS {
+            let mut best: Option<(usize, u64, u64)> = None;
+            for (i, swap_index) in candidates.iter().enumerate() {
+                let amount = self.part_amount(allocated[i] + 1);
+                //报价失败说明这条路径接不下更多的输入
+                let amount_out = match self.quote(&self.swaps[*swap_index], amount) {
+                    Ok(Some(market_swap)) => market_swap.amount_out_raw,
+                    Ok(None) | Err(_) => continue,
+                };
+                let gain = amount_out.saturating_sub(outputs[i]);
+                if best.map_or(true, |b| gain > b.1) {
+                    best = Some((i, gain, amount_out));
                 }
-                Saber(x, y) => {
-                    let mut market_swap = cal_saber(swap_amount_in, swap,
-                                                    &self.account_map, &self.tokens_adr, self.slippage).unwrap();
-                    market_swap.set_info(x, y);
-                    res.push(market_swap);
+            }
+            match best {
+                Some((i, _gain, amount_out)) => {
+                    allocated[i] += 1;
+                    outputs[i] = amount_out;
                 }
-                Swap(_x, _y) => {}
-                Serum(_x, _y) => {}
+                None => break,
+            }
+        }
+
+        let allocated_parts: u64 = allocated.iter().sum();
+        if allocated_parts < SPLIT_PARTS {
+            return Err(ApiError::Internal(format!("split stopped at {} of {} parts, no route can take more",
+                                                  allocated_parts, SPLIT_PARTS)).into());
+        }
+
+        //按份数取整后的零头归到分得最多的路径上，保证各路径输入之和等于amount_in
+        let mut amounts: Vec<u64> = allocated.iter().map(|x| self.part_amount(*x)).collect();
+        let remainder = self.amount_in - amounts.iter().sum::<u64>();
+        let largest = (0..amounts.len()).max_by_key(|i| amounts[*i]).unwrap();
+        amounts[largest] += remainder;
+
+        let mut res = vec![];
+        for (i, swap_index) in candidates.iter().enumerate() {
+            if amounts[i] == 0 {
+                continue;
             }
+            let mut market_swap = self.quote(&self.swaps[*swap_index], amounts[i])?
+                .ok_or_else(|| ApiError::Internal("split route can not be quoted".to_string()))?;
+            market_swap.percentage = (amounts[i] as f64 / self.amount_in as f64) as f32;
+            market_swap.mid_rate = self.mid_rate(&self.swaps[*swap_index])?;
+            res.push(market_swap);
         }
+        res.sort_by(|a, b| b.partial_cmp(&a).unwrap());
 
         Ok(res)
     }
 }
 
-fn cal_raydium(amount_in: f64,
-               swap: &MarketSwap,
-               account_map: &HashMap<String, Account>,
-               token_map: &HashMap<String, TokenAddr>,
-               slippage: f32) -> Result<OptMarket> {
-    let mut res = vec![];
-
-    let mut amount_in = amount_in;
-
-    let mut to_amount: f64 = 0.0;
-
-    for step in swap.step.iter() {
-        let pool_ac = account_map.get(&step.pool_key.to_string()).unwrap();
-        let mut pool_clone = pool_ac.clone();
-        let pool_ac_info = convert_to_info(&step.pool_key, &mut pool_clone);
-        let pool_info = AmmInfo::load_amm_mut(&pool_ac_info, false).unwrap();
-
-        let quote_ac = account_map.get(&step.quote_value_key.to_string()).unwrap();
-        let mut quote_clone = quote_ac.clone();
-        let quote_ac_info = convert_to_info(&step.quote_value_key, &mut quote_clone);
-        let quote_info = Processor::unpack_token_account(&quote_ac_info, &quote_ac.owner).unwrap();
-
-        let base_ac = account_map.get(&step.base_value_key.to_string()).unwrap();
-        let mut base_clone = base_ac.clone();
-        let base_ac_info = convert_to_info(&step.base_value_key, &mut base_clone);
-        let base_info = Processor::unpack_token_account(&base_ac_info, &base_ac.owner).unwrap();
-
-        let basic: i128 = 10;
-        let quote_token = token_map.get(&step.quote_mint_key.to_string()).unwrap();
-        let base_token = token_map.get(&step.base_mint_key.to_string()).unwrap();
-        let quote_pow = basic.pow(quote_token.decimal as u32);
-        let base_pow = basic.pow(base_token.decimal as u32);
-        let quote_amount = Decimal::from(quote_info.amount);
-        let base_amount = Decimal::from(base_info.amount);
-        if step.is_quote_to_base {
-            let from_amount = Decimal::from_f64(amount_in * (quote_pow as f64)).unwrap();
-            let from_amount_with_fee = from_amount.mul(Decimal::from(pool_info.fees.swap_fee_denominator - pool_info.fees.swap_fee_numerator)).div(Decimal::from(pool_info.fees.swap_fee_denominator));
-            let denominator = quote_amount.add(from_amount_with_fee);
-            let amount_out = base_amount.mul(from_amount_with_fee).div(denominator);
-            let mut amount_out_format = amount_out.div(Decimal::from(base_pow)).div(Decimal::from_f32(1.0 + slippage / 100.0).unwrap());
-            amount_out_format.rescale(base_token.decimal as u32);
-
-            res.push(OptRoute {
-                route_key: step.pool_key.to_string(),
-                source_amount: amount_in,
-                source_name: quote_token.name.to_string(),
-                source_mint: quote_token.mint.to_string(),
-                source_decimals: quote_token.decimal,
-                destination_amount: amount_out_format.to_f64().unwrap(),
-                destination_name: base_token.name.to_string(),
-                destination_mint: base_token.mint.to_string(),
-                destination_decimals: base_token.decimal,
-                source_value: quote_info.amount,
-                destination_value: base_info.amount,
-                fee_factor: ((pool_info.fees.trade_fee_denominator - pool_info.fees.trade_fee_numerator) as f64).div(pool_info.fees.trade_fee_denominator as f64),
-                amp: None,
-                data: step.data.clone(),
-            });
-            amount_in = amount_out_format.to_f64().unwrap();
-            to_amount = amount_in.clone();
-        } else {
-            let from_amount = Decimal::from_f64(amount_in * (base_pow as f64)).unwrap();
-            let from_amount_with_fee = from_amount.mul(Decimal::from(pool_info.fees.swap_fee_denominator - pool_info.fees.swap_fee_numerator)).div(Decimal::from(pool_info.fees.swap_fee_denominator));
-            let denominator = base_amount.add(from_amount_with_fee);
-            let amount_out = quote_amount.mul(from_amount_with_fee).div(denominator);
-            let mut amount_out_format = amount_out.div(Decimal::from(quote_pow)).div(Decimal::from_f32(1.0 + slippage / 100.0).unwrap());
-            amount_out_format.rescale(quote_token.decimal as u32);
-            res.push(OptRoute {
-                route_key: step.pool_key.to_string(),
-                source_amount: amount_in,
-                source_name: base_token.name.to_string(),
-                source_mint: base_token.mint.to_string(),
-                source_decimals: base_token.decimal,
-                destination_amount: amount_out_format.to_f64().unwrap(),
-                destination_name: quote_token.name.to_string(),
-                destination_mint: quote_token.mint.to_string(),
-                destination_decimals: quote_token.decimal,
-                source_value: base_info.amount,
-                destination_value: quote_info.amount,
-                fee_factor: ((pool_info.fees.trade_fee_denominator - pool_info.fees.trade_fee_numerator) as f64).div(pool_info.fees.trade_fee_denominator as f64),
-                amp: None,
-                data: step.data.clone(),
-            });
-            amount_in = amount_out_format.to_f64().unwrap();
-            to_amount = amount_in.clone();
+//按这一跳扣费后的成交比例把手续费折算到另一边
+fn convert_fee(fee: u64, from_amount: u64, to_amount: u64) -> u64 {
+    if from_amount == 0 {
+        return 0;
+    }
+    (fee as u128 * to_amount as u128 / from_amount as u128).min(u64::MAX as u128) as u64
+}
+
+fn route_fee(step: &MarketPool, source_token: &TokenAddr, destination_token: &TokenAddr, quote: &AmmQuote) -> RouteFee {
+    let (fee_mint, source_fee_raw, destination_fee_raw) = match quote.fee_side {
+        FeeSide::Source => {
+            (step.source_mint(), quote.fee, convert_fee(quote.fee, quote.amount_in.saturating_sub(quote.fee), quote.amount_out))
+        }
+        FeeSide::Destination => {
+            (step.destination_mint(), convert_fee(quote.fee, quote.amount_out.saturating_add(quote.fee), quote.amount_in), quote.fee)
         }
+    };
+    RouteFee {
+        fee_rate: quote.fee_rate,
+        fee_mint: fee_mint.to_string(),
+        source_fee: ui_amount(source_fee_raw, source_token.decimal),
+        source_fee_raw,
+        destination_fee: ui_amount(destination_fee_raw, destination_token.decimal),
+        destination_fee_raw,
+        protocol_fee_raw: quote.protocol_fee,
     }
+}
 
-    Ok(OptMarket {
-        market: "".to_string(),
-        program_id: "".to_string(),
-        amount_out: to_amount,
-        percentage: 0.5,
-        routes: res,
+//adapter的报价结果加上token信息。组装交易需要的账户在池子文件的data基础上补齐
+fn build_route(step: &MarketPool, token_map: &HashMap<String, TokenAddr>, quote: AmmQuote) -> Result<OptRoute> {
+    let (market, program_id) = step.market_type.get_name();
+    let source_token = token_map.get(&step.source_mint().to_string())
+        .ok_or_else(|| ApiError::UnknownMint(step.source_mint().to_string()))?;
+    let destination_token = token_map.get(&step.destination_mint().to_string())
+        .ok_or_else(|| ApiError::UnknownMint(step.destination_mint().to_string()))?;
+
+    let fee = route_fee(step, source_token, destination_token, &quote);
+    let mut data = step.data.clone();
+    data.extend(quote.data);
+    Ok(OptRoute {
+        route_key: step.pool_key.to_string(),
+        market,
+        program_id,
+        source_amount: ui_amount(quote.amount_in, source_token.decimal),
+        source_amount_raw: quote.amount_in,
+        source_name: source_token.name.to_string(),
+        source_mint: source_token.mint.to_string(),
+        source_decimals: source_token.decimal,
+        destination_amount: ui_amount(quote.amount_out, destination_token.decimal),
+        destination_amount_raw: quote.amount_out,
+        destination_name: destination_token.name.to_string(),
+        destination_mint: destination_token.mint.to_string(),
+        destination_decimals: destination_token.decimal,
+        source_value: quote.source_reserve,
+        destination_value: quote.destination_reserve,
+        fee_factor: 1.0 - quote.fee_rate,
+        fee,
+        amp: quote.amp.or(step.amp),
+        data,
     })
 }
 
-fn cal_orca(amount_in: f64,
-            swap: &MarketSwap,
-            account_map: &HashMap<String, Account>,
-            token_map: &HashMap<String, TokenAddr>,
-            slippage: f32) -> Result<OptMarket> {
-    let mut res = vec![];
-
-    let mut amount_in = amount_in;
-
-    let mut to_amount: f64 = 0.0;
-
-    for step in swap.step.iter() {
-        let pool_ac = account_map.get(&step.pool_key.to_string()).unwrap();
-        let pool_clone = pool_ac.clone();
-        let pool_info = SwapV1::unpack_from_slice(&pool_clone.data).unwrap();
-        println!("pool_info={:?}", pool_info);
-        let quote_ac = account_map.get(&step.quote_value_key.to_string()).unwrap();
-        let mut quote_clone = quote_ac.clone();
-        let quote_ac_info = convert_to_info(&step.quote_value_key, &mut quote_clone);
-        let quote_info = Processor::unpack_token_account(&quote_ac_info, &quote_ac.owner).unwrap();
-        println!("quote_info={:?}", quote_info);
-        let base_ac = account_map.get(&step.base_value_key.to_string()).unwrap();
-        let mut base_clone = base_ac.clone();
-        let base_ac_info = convert_to_info(&step.base_value_key, &mut base_clone);
-        let base_info = Processor::unpack_token_account(&base_ac_info, &base_ac.owner).unwrap();
-        println!("base_info={:?}", base_info);
-        let basic: i128 = 10;
-        let quote_token = token_map.get(&step.quote_mint_key.to_string()).unwrap();
-        let base_token = token_map.get(&step.base_mint_key.to_string()).unwrap();
-        let quote_pow = basic.pow(quote_token.decimal as u32);
-        let base_pow = basic.pow(base_token.decimal as u32);
-
-        let fee_ratio = get_swap_fee_ratio(pool_info.fees.trade_fee_numerator,
-                                           pool_info.fees.trade_fee_denominator,
-                                           pool_info.fees.owner_trade_fee_numerator,
-                                           pool_info.fees.owner_trade_fee_denominator);
-
-        if step.is_quote_to_base {
-            let from_amount = amount_in * (quote_pow as f64);
-
-            let from_amount_with_fee = from_amount.sub(from_amount * (fee_ratio as f64));
-
-            let amount_out;
-
-            match step.amp {
-                Some(amp) => {
-                    let sc = StableCurve {
-                        amp
-                    };
-                    let sc_result = sc.swap_without_fees(from_amount_with_fee.to_u128().unwrap(),
-                                                         quote_info.amount as u128,
-                                                         base_info.amount as u128,
-                                                         TradeDirection::AtoB).unwrap();
-                    amount_out = Decimal::from_u128(sc_result.destination_amount_swapped).unwrap();
-                }
-                None => {
-                    let sc = SwapCurve::default();
-                    let sc_result = sc.calculator.swap_without_fees(from_amount_with_fee.to_u128().unwrap(),
-                                                                    quote_info.amount as u128,
-                                                                    base_info.amount as u128,
-                                                                    TradeDirection::AtoB).unwrap();
-                    amount_out = Decimal::from_u128(sc_result.destination_amount_swapped).unwrap();
-                }
-            };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use market::amm::AccountMap;
+    use market::market::MarketType;
+    use market::saber::amm::SaberAmm;
+    use market::saber::fees::Fees;
+    use market::saber::state::{SwapInfo, SwapTokenInfo};
+    use response::{OptRank, QuoteSummary, SwapMode};
+    use solana_program::program_pack::Pack;
+    use spl_token::state::{Account as TokenAccount, AccountState};
+    use std::str::FromStr;
+
+    const SABER_PROGRAM_ID: &str = "SSwpkEEcbUqx4vtoEByFjSkhKdCT862DNVb52nZg1UZ";
+
+    //恒定乘积、不收手续费的池子，输入超过max_input时报错
+    struct FakeAmm {
+        key: Pubkey,
+        quote_reserve: u64,
+        base_reserve: u64,
+        max_input: u64,
+    }
 
-            let mut amount_out_format = amount_out.div(Decimal::from(base_pow)).div(Decimal::from_f32(1.0 + slippage / 100.0).unwrap());
-            amount_out_format.rescale(base_token.decimal as u32);
-            res.push(OptRoute {
-                route_key: step.pool_key.to_string(),
-                source_amount: amount_in,
-                source_name: quote_token.name.to_string(),
-                source_mint: quote_token.mint.to_string(),
-                source_decimals: quote_token.decimal,
-                destination_amount: amount_out_format.to_f64().unwrap(),
-                destination_name: base_token.name.to_string(),
-                destination_mint: base_token.mint.to_string(),
-                destination_decimals: base_token.decimal,
-                source_value: quote_info.amount,
-                destination_value: base_info.amount,
-                fee_factor: fee_ratio as f64,
-                amp: step.amp,
-                data: step.data.clone(),
-            });
-            amount_in = amount_out_format.to_f64().unwrap();
-            to_amount = amount_in.clone();
-        } else {
-            let from_amount = amount_in * (base_pow as f64);
-
-            let from_amount_with_fee = from_amount - (from_amount * (fee_ratio as f64));
-
-            let amount_out;
-
-            match step.amp {
-                Some(amp) => {
-                    let sc = StableCurve {
-                        amp
-                    };
-                    let sc_result = sc.swap_without_fees(from_amount_with_fee.to_u128().unwrap(),
-                                                         base_info.amount as u128,
-                                                         quote_info.amount as u128,
-                                                         TradeDirection::BtoA).unwrap();
-                    amount_out = Decimal::from_u128(sc_result.destination_amount_swapped).unwrap();
-                }
-                None => {
-                    let sc = SwapCurve::default();
-                    let sc_result = sc.calculator.swap_without_fees(from_amount_with_fee.to_u128().unwrap(),
-                                                                    base_info.amount as u128,
-                                                                    quote_info.amount as u128,
-                                                                    TradeDirection::BtoA).unwrap();
-                    amount_out = Decimal::from_u128(sc_result.destination_amount_swapped).unwrap();
-                }
+    impl Amm for FakeAmm {
+        fn key(&self) -> Pubkey {
+            self.key
+        }
+
+        fn accounts_needed(&self) -> Vec<Pubkey> {
+            vec![]
+        }
+
+        fn update(&mut self, _accounts: &AccountMap) -> Result<()> {
+            Ok(())
+        }
+
+        fn reserves(&self) -> (u64, u64) {
+            (self.quote_reserve, self.base_reserve)
+        }
+
+        fn quote(&self, amount_in: u64, direction: SwapDirection) -> Result<AmmQuote> {
+            if amount_in > self.max_input {
+                return Err(anyhow!("amount {} too large", amount_in));
+            }
+            let (source_reserve, destination_reserve) = match direction {
+                SwapDirection::QuoteToBase => (self.quote_reserve, self.base_reserve),
+                SwapDirection::BaseToQuote => (self.base_reserve, self.quote_reserve),
             };
+            let amount_out = destination_reserve as u128 * amount_in as u128 / (source_reserve as u128 + amount_in as u128);
+            Ok(AmmQuote {
+                amount_in,
+                amount_out: amount_out as u64,
+                source_reserve,
+                destination_reserve,
+                fee_rate: 0.0,
+                fee_side: FeeSide::Source,
+                fee: 0,
+                protocol_fee: 0,
+                amp: None,
+                data: HashMap::new(),
+            })
+        }
+    }
 
-            let mut amount_out_format = amount_out.div(Decimal::from(quote_pow)).div(Decimal::from_f32(1.0 + slippage / 100.0).unwrap());
-            amount_out_format.rescale(quote_token.decimal as u32);
-            res.push(OptRoute {
-                route_key: step.pool_key.to_string(),
-                source_amount: amount_in,
-                source_name: base_token.name.to_string(),
-                source_mint: base_token.mint.to_string(),
-                source_decimals: base_token.decimal,
-                destination_amount: amount_out_format.to_f64().unwrap(),
-                destination_name: quote_token.name.to_string(),
-                destination_mint: quote_token.mint.to_string(),
-                destination_decimals: quote_token.decimal,
-                source_value: base_info.amount,
-                destination_value: quote_info.amount,
-                fee_factor: fee_ratio as f64,
-                amp: step.amp,
-                data: step.data.clone(),
+    fn token(mint: Pubkey, name: &str) -> TokenAddr {
+        TokenAddr {
+            name: name.to_string(),
+            mint,
+            decimal: 6,
+            description: name.to_string(),
+            icon_uri: String::new(),
+            is_native: None,
+        }
+    }
+
+    //quote_mint到base_mint的直连池子，每个元素是(储备, 最大输入)
+    fn init_data(amount_in: u64, pools: &[(u64, u64)]) -> OptInitData {
+        let quote_mint = Pubkey::new_unique();
+        let base_mint = Pubkey::new_unique();
+        let mut tokens_adr = HashMap::new();
+        tokens_adr.insert(quote_mint.to_string(), token(quote_mint, "A"));
+        tokens_adr.insert(base_mint.to_string(), token(base_mint, "B"));
+
+        let mut swaps = vec![];
+        let mut amms: HashMap<Pubkey, Box<dyn Amm>> = HashMap::new();
+        for (reserve, max_input) in pools {
+            let pool_key = Pubkey::new_unique();
+            swaps.push(MarketSwap {
+                step: vec![MarketPool {
+                    market_type: MarketType::Raydium("Raydium".to_string(), Pubkey::new_unique().to_string()),
+                    pool_key,
+                    quote_mint_key: quote_mint,
+                    base_mint_key: base_mint,
+                    quote_value_key: Pubkey::new_unique(),
+                    base_value_key: Pubkey::new_unique(),
+                    is_quote_to_base: true,
+                    amp: None,
+                    data: HashMap::new(),
+                }],
             });
-            amount_in = amount_out_format.to_f64().unwrap();
-            to_amount = amount_in.clone();
+            amms.insert(pool_key, Box::new(FakeAmm {
+                key: pool_key,
+                quote_reserve: *reserve,
+                base_reserve: *reserve,
+                max_input: *max_input,
+            }));
+        }
+        OptInitData {
+            amount_in,
+            tokens_adr,
+            account_map: HashMap::new(),
+            swaps,
+            amms,
         }
     }
 
-    Ok(OptMarket {
-        market: "".to_string(),
-        program_id: "".to_string(),
-        amount_out: to_amount,
-        percentage: 0.5,
-        routes: res,
-    })
-}
+    fn token_account(mint: Pubkey, amount: u64) -> Account {
+        let mut data = vec![0u8; TokenAccount::LEN];
+        TokenAccount::pack(TokenAccount {
+            mint,
+            owner: Pubkey::new_unique(),
+            amount,
+            state: AccountState::Initialized,
+            ..TokenAccount::default()
+        }, &mut data).unwrap();
+        Account { lamports: 0, data, owner: spl_token::id(), executable: false, rent_epoch: 0 }
+    }
 
-fn cal_saber(amount_in: f64,
-             swap: &MarketSwap,
-             account_map: &HashMap<String, Account>,
-             token_map: &HashMap<String, TokenAddr>,
-             slippage: f32) -> Result<OptMarket> {
-    let mut res = vec![];
-
-    let mut amount_in = amount_in;
-
-    let mut to_amount: f64 = 0.0;
-
-    for step in swap.step.iter() {
-        let pool_ac = account_map.get(&step.pool_key.to_string()).unwrap();
-        let pool_clone = pool_ac.clone();
-        let pool_info = SwapInfo::unpack_from_slice(&pool_clone.data).unwrap();
-
-        let quote_ac = account_map.get(&step.quote_value_key.to_string()).unwrap();
-        let mut quote_clone = quote_ac.clone();
-        let quote_ac_info = convert_to_info(&step.quote_value_key, &mut quote_clone);
-        let quote_info = Processor::unpack_token_account(&quote_ac_info, &quote_ac.owner).unwrap();
-
-        let base_ac = account_map.get(&step.base_value_key.to_string()).unwrap();
-        let mut base_clone = base_ac.clone();
-        let base_ac_info = convert_to_info(&step.base_value_key, &mut base_clone);
-        let base_info = Processor::unpack_token_account(&base_ac_info, &base_ac.owner).unwrap();
-
-        let basic: i128 = 10;
-        let quote_token = token_map.get(&step.quote_mint_key.to_string()).unwrap();
-        let base_token = token_map.get(&step.base_mint_key.to_string()).unwrap();
-        let quote_pow = basic.pow(quote_token.decimal as u32);
-        let base_pow = basic.pow(base_token.decimal as u32);
-        let fee_ratio = get_swap_fee_ratio(pool_info.fees.trade_fee_numerator,
-                                           pool_info.fees.trade_fee_denominator,
-                                           pool_info.fees.admin_trade_fee_numerator,
-                                           pool_info.fees.admin_withdraw_fee_denominator);
-
-        let clock = Clock::default();
-
-        let stable_swap = StableSwap::new(pool_info.initial_amp_factor, pool_info.target_amp_factor, clock.unix_timestamp,
-                                          pool_info.start_ramp_ts, pool_info.stop_ramp_ts);
-
-        if step.is_quote_to_base {
-            let from_amount = amount_in * (quote_pow as f64);
-
-            let sc_result = stable_swap.swap_to(from_amount as u64, quote_info.amount, base_info.amount, &pool_info.fees).unwrap();
-            let amount_out = Decimal::from_u64(sc_result.amount_swapped).unwrap();
-
-            let mut amount_out_format = amount_out.div(Decimal::from(base_pow)).div(Decimal::from_f32(1.0 + slippage / 100.0).unwrap());
-            amount_out_format.rescale(base_token.decimal as u32);
-            res.push(OptRoute {
-                route_key: step.pool_key.to_string(),
-                source_amount: amount_in,
-                source_name: quote_token.name.to_string(),
-                source_mint: quote_token.mint.to_string(),
-                source_decimals: quote_token.decimal,
-                destination_amount: amount_out_format.to_f64().unwrap(),
-                destination_name: base_token.name.to_string(),
-                destination_mint: base_token.mint.to_string(),
-                destination_decimals: base_token.decimal,
-                source_value: quote_info.amount,
-                destination_value: base_info.amount,
-                fee_factor: fee_ratio as f64,
-                amp: Some(pool_info.target_amp_factor),
-                data: step.data.clone(),
-            });
-            amount_in = amount_out_format.to_f64().unwrap();
-            to_amount = amount_in.clone();
-        } else {
-            let from_amount = amount_in * (base_pow as f64);
-
-            let sc_result = stable_swap.swap_to(from_amount as u64, base_info.amount, quote_info.amount, &pool_info.fees).unwrap();
-            let amount_out = Decimal::from_u64(sc_result.amount_swapped).unwrap();
-            let mut amount_out_format = amount_out.div(Decimal::from(quote_pow)).div(Decimal::from_f32(1.0 + slippage / 100.0).unwrap());
-            amount_out_format.rescale(quote_token.decimal as u32);
-            res.push(OptRoute {
-                route_key: step.pool_key.to_string(),
-                source_amount: amount_in,
-                source_name: base_token.name.to_string(),
-                source_mint: base_token.mint.to_string(),
-                source_decimals: base_token.decimal,
-                destination_amount: amount_out_format.to_f64().unwrap(),
-                destination_name: quote_token.name.to_string(),
-                destination_mint: quote_token.mint.to_string(),
-                destination_decimals: quote_token.decimal,
-                source_value: base_info.amount,
-                destination_value: quote_info.amount,
-                fee_factor: fee_ratio as f64,
-                amp: Some(pool_info.initial_amp_factor),
-                data: step.data.clone(),
-            });
-            amount_in = amount_out_format.to_f64().unwrap();
-            to_amount = amount_in.clone();
+    //一个saber稳定币池子的路径，is_quote_to_base决定报价方向
+    fn saber_init_data(amount_in: u64, is_quote_to_base: bool) -> OptInitData {
+        let program_id = Pubkey::from_str(SABER_PROGRAM_ID).unwrap();
+        let swap_key = Pubkey::new_unique();
+        let (_, nonce) = Pubkey::find_program_address(&[&swap_key.to_bytes()[..32]], &program_id);
+        let quote_mint = Pubkey::new_unique();
+        let base_mint = Pubkey::new_unique();
+        let quote_vault = Pubkey::new_unique();
+        let base_vault = Pubkey::new_unique();
+
+        let swap_info = SwapInfo {
+            is_initialized: true,
+            is_paused: false,
+            nonce,
+            initial_amp_factor: 100,
+            target_amp_factor: 100,
+            start_ramp_ts: 0,
+            stop_ramp_ts: 0,
+            future_admin_deadline: 0,
+            future_admin_key: Pubkey::default(),
+            admin_key: Pubkey::default(),
+            token_a: SwapTokenInfo { reserves: quote_vault, mint: quote_mint, admin_fees: Pubkey::new_unique(), index: 0 },
+            token_b: SwapTokenInfo { reserves: base_vault, mint: base_mint, admin_fees: Pubkey::new_unique(), index: 1 },
+            pool_mint: Pubkey::new_unique(),
+            fees: Fees {
+                admin_trade_fee_numerator: 50,
+                admin_trade_fee_denominator: 100,
+                admin_withdraw_fee_numerator: 0,
+                admin_withdraw_fee_denominator: 1,
+                trade_fee_numerator: 4,
+                trade_fee_denominator: 10_000,
+                withdraw_fee_numerator: 0,
+                withdraw_fee_denominator: 1,
+            },
+        };
+        let mut data = vec![0u8; SwapInfo::LEN];
+        SwapInfo::pack(swap_info, &mut data).unwrap();
+
+        let mut account_map: AccountMap = HashMap::new();
+        account_map.insert(swap_key.to_string(), Account { lamports: 0, data, owner: program_id, executable: false, rent_epoch: 0 });
+        account_map.insert(quote_vault.to_string(), token_account(quote_mint, 1_000_000_000_000));
+        account_map.insert(base_vault.to_string(), token_account(base_mint, 800_000_000_000));
+
+        let mut tokens_adr = HashMap::new();
+        tokens_adr.insert(quote_mint.to_string(), token(quote_mint, "A"));
+        tokens_adr.insert(base_mint.to_string(), token(base_mint, "B"));
+
+        OptInitData {
+            amount_in,
+            tokens_adr,
+            account_map,
+            swaps: vec![MarketSwap {
+                step: vec![MarketPool {
+                    market_type: MarketType::Saber("Saber".to_string(), SABER_PROGRAM_ID.to_string()),
+                    pool_key: swap_key,
+                    quote_mint_key: quote_mint,
+                    base_mint_key: base_mint,
+                    quote_value_key: quote_vault,
+                    base_value_key: base_vault,
+                    is_quote_to_base,
+                    amp: None,
+                    data: HashMap::new(),
+                }],
+            }],
+            amms: HashMap::new(),
         }
     }
 
-    Ok(OptMarket {
-        market: "".to_string(),
-        program_id: "".to_string(),
-        amount_out: to_amount,
-        percentage: 0.5,
-        routes: res,
-    })
-}
+    #[test]
+    fn test_single_route_matches_saber_quote() {
+        let amount_in = 50_000_000_000;
+        for is_quote_to_base in [true, false] {
+            let mut data = saber_init_data(amount_in, is_quote_to_base);
+            assert!(data.exclude_unavailable().is_empty());
+
+            //直接用saber的adapter报价
+            let step = data.swaps[0].step[0].clone();
+            let mut amm = SaberAmm::new(step.pool_key, Pubkey::from_str(SABER_PROGRAM_ID).unwrap(),
+                                        step.quote_value_key, step.base_value_key);
+            amm.update(&data.account_map).unwrap();
+            let expected = amm.quote(amount_in, swap_direction(&step)).unwrap().amount_out;
+            assert!(expected > 0);
+
+            let mut rank = OptRank {
+                swap_mode: SwapMode::ExactIn,
+                amount_in: 0.0,
+                amount_in_raw: amount_in,
+                amount_out: 0.0,
+                amount_out_raw: 0,
+                max_amount_in: None,
+                max_amount_in_raw: None,
+                quote_mint: step.source_mint().to_string(),
+                base_mint: step.destination_mint().to_string(),
+                slippage: 1.0,
+                opt: data.calculate(),
+                excluded: vec![],
+                slot: 0,
+                split: vec![],
+                split_error: None,
+                summary: QuoteSummary::default(),
+            };
+            let ranks = rank.opt_best().unwrap();
+            assert_eq!(ranks.len(), 1);
+            assert_eq!(ranks[0].opt[0].percentage, 1.0);
+            assert_eq!(ranks[0].opt[0].routes[0].destination_amount_raw, expected);
+            assert_eq!(ranks[0].amount_out_raw, expected);
+            assert_eq!(ranks[0].summary.expected_out_raw, expected);
+        }
+    }
 
-pub fn convert_to_info<'a>(key: &'a Pubkey, account: &'a mut Account) -> AccountInfo<'a> {
-    AccountInfo::new(key,
-                     false, false,
-                     &mut account.lamports,
-                     &mut account.data,
-                     &account.owner, false,
-                     *&account.rent_epoch)
-}
+    #[test]
+    fn test_split_allocates_whole_input() {
+        let amount_in = 1_000_000_007;
+        let data = init_data(amount_in, &[(10_000_000_000, u64::MAX), (5_000_000_000, u64::MAX)]);
+        let split = data.optimal_split().unwrap();
+
+        assert_eq!(split.len(), 2);
+        assert_eq!(split.iter().map(|x| x.amount_in_raw).sum::<u64>(), amount_in);
+        //边际价格相等时深的池子分到约2/3
+        let deep = split.iter().find(|x| x.routes[0].source_value == 10_000_000_000).unwrap();
+        assert!(deep.percentage > 0.6 && deep.percentage < 0.72);
+        //拆单比全额走任何一条路径都多
+        let single = data.calculate().iter().map(|x| x.amount_out_raw).max().unwrap();
+        assert!(split.iter().map(|x| x.amount_out_raw).sum::<u64>() > single);
+    }
 
-pub fn get_swap_fee_ratio(trade_fee_numerator: u64,
-                          trade_fee_denominator: u64,
-                          owner_trade_fee_numerator: u64,
-                          owner_trade_fee_denominator: u64) -> u64 {
-    let trade_fee = if trade_fee_numerator == 0 {
-        0
-    } else {
-        trade_fee_numerator / trade_fee_denominator
-    };
+    #[test]
+    fn test_split_uses_routes_that_can_not_take_full_amount() {
+        let amount_in = 1_000_000_000;
+        let data = init_data(amount_in, &[(10_000_000_000, u64::MAX), (10_000_000_000, 300_000_000)]);
+        let split = data.optimal_split().unwrap();
 
-    let owner_fee = if owner_trade_fee_numerator == 0 {
-        0
-    } else {
-        owner_trade_fee_numerator / owner_trade_fee_denominator
-    };
+        assert_eq!(split.iter().map(|x| x.amount_in_raw).sum::<u64>(), amount_in);
+        let capped = split.iter().find(|x| x.amount_in_raw <= 300_000_000).unwrap();
+        assert_eq!(capped.amount_in_raw, 300_000_000);
+    }
 
-    trade_fee + owner_fee
+    #[test]
+    fn test_split_reports_early_exit() {
+        //两条路径加起来只能接下60%的输入
+        let amount_in = 1_000_000_000;
+        let data = init_data(amount_in, &[(10_000_000_000, 300_000_000), (10_000_000_000, 300_000_000)]);
+        let err = data.optimal_split().unwrap_err();
+        assert!(err.to_string().contains("30 of 50 parts"));
+    }
+
+    #[test]
+    fn test_calculate_drops_failing_route() {
+        //第二条路径接不下全额输入，报价报错
+        let amount_in = 1_000_000_000;
+        let data = init_data(amount_in, &[(10_000_000_000, u64::MAX), (20_000_000_000, 300_000_000)]);
+        let opt = data.calculate();
+        assert_eq!(opt.len(), 1);
+        assert_eq!(opt[0].routes[0].route_key, data.swaps[0].step[0].pool_key.to_string());
+        assert_eq!(opt[0].amount_in_raw, amount_in);
+
+        let opt = data.calculate_exact_out(amount_in);
+        assert_eq!(opt.len(), 1);
+        assert_eq!(opt[0].routes[0].route_key, data.swaps[0].step[0].pool_key.to_string());
+    }
+
+    #[test]
+    fn test_exact_out_finds_minimum_input() {
+        let data = init_data(0, &[(10_000_000_000, u64::MAX)]);
+        let swap = &data.swaps[0];
+        for amount_out in [1, 999, 123_456_789, 5_000_000_000] {
+            let market_swap = data.quote_exact_out(swap, amount_out).unwrap().unwrap();
+            assert!(market_swap.amount_out_raw >= amount_out);
+            let less = data.quote(swap, market_swap.amount_in_raw - 1).unwrap().unwrap();
+            assert!(less.amount_out_raw < amount_out);
+        }
+    }
+
+    #[test]
+    fn test_exact_out_without_depth() {
+        //恒定乘积池子永远拿不到全部储备
+        let data = init_data(0, &[(10_000_000_000, u64::MAX)]);
+        assert!(data.quote_exact_out(&data.swaps[0], 10_000_000_000).unwrap().is_none());
+    }
 }
@@ -1,33 +1,25 @@
 use crate::{api, PoolListResponse};
-use crate::opt_core;
-use crate::node_client;
+use crate::error::{ApiError, ApiResult};
+use crate::registry::RegistryData;
+use crate::account_cache::AccountCache;
+use crate::adapter::pool_info_amm;
+use crate::response::ui_amount;
 use market::pool::{PoolInfo, PoolResponse, RawPool, TokenInfo};
-use market::{orca, raydium};
+use market::market::MarketType;
+use market::dex::{Dex, DEXES};
+use market::saber::state::SwapInfo;
+use market::amm::{Amm, SwapDirection, get_account, load_token_amount};
 use serde::{Serialize, Deserialize};
 use solana_program::pubkey::Pubkey;
-use solana_sdk::{commitment_config::CommitmentConfig, account::Account};
-use solana_client::rpc_client::RpcClient;
-use node_client::NetworkType;
-use market::market::MarketType::*;
+use solana_sdk::account::Account;
 use anyhow::Result;
-use market::raydium::stats::AmmInfo;
-use opt_core::convert_to_info;
-use api::load_token_data_from_file;
-use spl_token_swap::processor::Processor;
 use api::TokenAddr;
-use rust_decimal::Decimal;
-use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
-use bytemuck::__core::ops::{Add, Mul, Div};
 use std::collections::HashMap;
 use std::fs;
-use rocket_contrib::json::Json;
+use rocket::serde::json::Json;
 use solana_program::program_pack::Pack;
 use spl_token::state::Mint;
-use spl_token_swap::curve::base::SwapCurve;
-use spl_token_swap::curve::calculator::{CurveCalculator, TradeDirection};
-use spl_token_swap::curve::stable::StableCurve;
-use spl_token_swap::state::SwapV1;
-use crate::opt_core::get_swap_fee_ratio;
+use rust_decimal::prelude::FromStr;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PoolRequest {
@@ -41,121 +33,65 @@ pub struct PoolRequest {
 }
 
 impl PoolRequest {
-    pub fn load_data(&self) -> Vec<PoolInfo> {
+    pub fn load_data(&self, registry: &RegistryData) -> ApiResult<Vec<PoolInfo>> {
 
         //参数校验
         if self.lp_mint.is_none() {
             if self.token_mint_a.is_none() || self.token_mint_b.is_none() {
-                return vec![];
+                return Err(ApiError::InvalidRequest("lp_mint or token_mint_a and token_mint_b required".to_string()));
             }
         }
-        let mut need_raydium = false;
-        let mut need_orca = false;
-        match &self.market {
-            Some(a) => {
-                if a.eq("raydium") {
-                    need_raydium = true;
-                } else if a.eq("orca") {
-                    need_orca = true;
-                } else {
-                    return vec![];
-                }
-            }
-            None => {
-                need_raydium = true;
-                need_orca = true;
+        //只有提供pool_info池子的市场能查询
+        let dexes: Vec<&Dex> = DEXES.iter().copied().filter(|x| x.load_pools.is_some()).collect();
+        if let Some(market) = &self.market {
+            if !dexes.iter().any(|x| x.id.eq(market)) {
+                return Err(ApiError::InvalidRequest(format!("unsupported market {}", market)));
             }
         }
 
+        let market_pool: Vec<PoolInfo> = dexes.iter()
+            .filter(|x| self.market.as_ref().map_or(true, |m| x.id.eq(m)))
+            .filter_map(|x| self.find_pool(registry, x.id))
+            .cloned()
+            .collect();
 
-        let mut market_pool = vec![];
-
-        if need_raydium {
-            let raydium_pool = raydium::data::load_pool_from_file(self.lp_mint.clone(), self.token_mint_a.clone(),
-                                                                  self.token_mint_b.clone());
-            if raydium_pool.is_some() {
-                market_pool.push(raydium_pool.unwrap());
-            }
-        }
+        Ok(market_pool)
+    }
 
-        if need_orca {
-            let orca_pool = orca::data::load_pool_from_file(self.lp_mint.clone(), self.token_mint_a.clone(),
-                                                            self.token_mint_b.clone());
-            if orca_pool.is_some() {
-                market_pool.push(orca_pool.unwrap());
-            }
+    //给了lp_mint按lp_mint查找，否则按交易对查找
+    fn find_pool<'a>(&self, registry: &'a RegistryData, market: &str) -> Option<&'a PoolInfo> {
+        match &self.lp_mint {
+            Some(lp) => registry.pool_by_lp(lp, Some(market)),
+            None => registry.pool_by_pair(self.token_mint_a.as_ref()?, self.token_mint_b.as_ref()?, Some(market)),
         }
-
-        market_pool
     }
 }
 
-pub fn cal_rate(pools: &[PoolInfo], slippage: &Option<f32>) -> Vec<PoolResponse> {
+pub async fn cal_rate(pools: &[PoolInfo],
+                      slippage: &Option<f32>,
+                      tokens_adr: &HashMap<String, TokenAddr>,
+                      cache: &AccountCache) -> ApiResult<Vec<PoolResponse>> {
 
-    //查询
-    let token_main_path = "./token_mint.json".to_string();
-    let tokens_adr = load_token_data_from_file(&token_main_path).expect("load token data fail");
-
-    let mut keys: Vec<Pubkey> = vec![];
-    for pool in pools {
-        keys.push(pool.pool_key.clone());
-        keys.push(pool.quote_value_key.clone());
-        keys.push(pool.base_value_key.clone());
-        keys.push(pool.lp_mint_key.clone());
-    }
-
-    let client = RpcClient::new(NetworkType::Mainnet.url().to_string());
-    let commitment_config = CommitmentConfig::processed();
-    let accounts: Vec<Option<Account>> = client.get_multiple_accounts_with_commitment(&keys, commitment_config).unwrap().value;
-
-    let mut account_map = HashMap::new();
-    for (index, value) in accounts.iter().enumerate() {
-        let pubkey = &keys[index];
-        match value {
-            Some(account) => {
-                account_map.insert(pubkey.to_string(), account.clone());
-            }
-            None => {}
-        }
-    }
+    //池子文件里的账户必须存在，whirlpool的tick array要读到池子之后才知道，不存在时按深度不够报价
+    let keys: Vec<Pubkey> = pools.iter().flat_map(pool_account_keys).collect();
+    cache.get_required_accounts(&keys).await?;
+    let mut amms: Vec<Box<dyn Amm>> = pools.iter().filter_map(pool_info_amm).collect();
+    let (account_map, slot) = cache.get_amm_accounts(&mut amms, &keys).await?;
 
     let mut res = vec![];
 
     for pool in pools {
-        match &pool.market_type {
-            Raydium(_x, _y) => {
-                let mut pool_info = cal_raydium(&account_map, &tokens_adr, &pool).unwrap();
-                match slippage {
-                    Some(a) => {
-                        let rate_fix = pool_info.rate.unwrap() / (1.0 + (a.clone() / 100.0));
-                        pool_info.rate = Some(rate_fix);
-                    }
-                    None => {}
-                }
-                res.push(pool_info);
-            }
-            Orca(_x, _y) => {
-                let pool_info = cal_orca(&account_map, &tokens_adr, &pool).unwrap();
-                // match slippage {
-                //     Some(a) => {
-                //         let rate_fix = pool_info.rate.unwrap() / (1.0 + (a.clone() / 100.0));
-                //         pool_info.rate = Some(rate_fix);
-                //     }
-                //     None => {}
-                // }
-                res.push(pool_info);
+        if let Some(mut pool_info) = cal_pool(&account_map, tokens_adr, pool)? {
+            //所有市场都按同样的口径扣除滑点
+            if let (Some(rate), Some(slippage)) = (pool_info.rate, slippage) {
+                pool_info.rate = Some(rate / (1.0 + slippage / 100.0));
             }
-            Saber(_x, _y) => {}
-            Swap(_x, _y) => {}
-            Serum(_x, _y) => {}
+            pool_info.slot = Some(slot);
+            res.push(pool_info);
         }
     }
 
-    res
-}
-
-pub fn load_pool_data(market: Option<String>) -> Vec<RawPool> {
-    RawPool::load_all_pool_data(market)
+    Ok(res)
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -167,7 +103,7 @@ pub struct RawFarm {
 }
 
 pub fn load_farm_data_from_file(path: &String) -> Result<HashMap<String, String>> {
-    let raw_info = fs::read_to_string(path).expect("Error read file");
+    let raw_info = fs::read_to_string(path)?;
     let vec: Vec<RawFarm> = serde_json::from_str(&raw_info)?;
     let res: HashMap<String, String> = vec
         .iter()
@@ -179,7 +115,7 @@ pub fn load_farm_data_from_file(path: &String) -> Result<HashMap<String, String>
 }
 
 pub fn load_pool_farm_data_from_file(path: &String) -> Result<HashMap<String, String>> {
-    let raw_info = fs::read_to_string(path).expect("Error read file");
+    let raw_info = fs::read_to_string(path)?;
     let vec: Vec<RawFarm> = serde_json::from_str(&raw_info)?;
     let res: HashMap<String, String> = vec
         .iter()
@@ -209,152 +145,102 @@ pub fn fill_token_info(token_map: &HashMap<String, TokenAddr>, token_address: &s
     info
 }
 
-fn cal_raydium(account_map: &HashMap<String, Account>,
-               token_map: &HashMap<String, TokenAddr>,
-               pool: &PoolInfo) -> Result<PoolResponse> {
-    let amount_in = 1.0;
-
-    let pool_ac = account_map.get(&pool.pool_key.to_string()).unwrap();
-    let mut pool_clone = pool_ac.clone();
-    let pool_ac_info = convert_to_info(&pool.pool_key, &mut pool_clone);
-    let pool_info = AmmInfo::load_amm_mut(&pool_ac_info, false).unwrap();
-
-    let quote_ac = account_map.get(&pool.quote_value_key.to_string()).unwrap();
-    let mut quote_clone = quote_ac.clone();
-    let quote_ac_info = convert_to_info(&pool.quote_value_key, &mut quote_clone);
-    let quote_info = Processor::unpack_token_account(&quote_ac_info, &quote_ac.owner).unwrap();
-
-    let base_ac = account_map.get(&pool.base_value_key.to_string()).unwrap();
-    let mut base_clone = base_ac.clone();
-    let base_ac_info = convert_to_info(&pool.base_value_key, &mut base_clone);
-    let base_info = Processor::unpack_token_account(&base_ac_info, &base_ac.owner).unwrap();
-
-    let basic: i128 = 10;
-    let quote_token = token_map.get(&pool.quote_mint_key.to_string()).unwrap();
-    let base_token = token_map.get(&pool.base_mint_key.to_string()).unwrap();
-    let quote_pow = basic.pow(quote_token.decimal as u32);
-    let base_pow = basic.pow(base_token.decimal as u32);
-    let quote_amount = Decimal::from(quote_info.amount);
-    let base_amount = Decimal::from(base_info.amount);
-
-    let from_amount = Decimal::from_f64(amount_in * (base_pow as f64)).unwrap();
-    let from_amount_with_fee = from_amount.mul(Decimal::from(pool_info.fees.swap_fee_denominator - pool_info.fees.swap_fee_numerator)).div(Decimal::from(pool_info.fees.swap_fee_denominator));
-    let denominator = base_amount.add(from_amount_with_fee);
-    let amount_out = quote_amount.mul(from_amount_with_fee).div(denominator);
-    let mut amount_out_format = amount_out.div(Decimal::from(quote_pow));
-    amount_out_format.rescale(quote_token.decimal as u32);
+//池子两边token的精度信息
+fn load_pair_tokens<'a>(token_map: &'a HashMap<String, TokenAddr>, pool: &PoolInfo) -> Result<(&'a TokenAddr, &'a TokenAddr)> {
+    let quote_token = token_map.get(&pool.quote_mint_key.to_string())
+        .ok_or_else(|| ApiError::UnknownMint(pool.quote_mint_key.to_string()))?;
+    let base_token = token_map.get(&pool.base_mint_key.to_string())
+        .ok_or_else(|| ApiError::UnknownMint(pool.base_mint_key.to_string()))?;
+    Ok((quote_token, base_token))
+}
 
-    let matket_type = pool.market_type.get_name();
-    Ok(PoolResponse {
-        market: matket_type.0,
-        program_id: matket_type.1,
-        pool_account: pool.pool_key.to_string(),
-        quote_mint: pool.quote_mint_key.to_string(),
-        base_mint: pool.base_mint_key.to_string(),
-        lp_mint: pool.lp_mint_key.to_string(),
-        quote_value: pool.quote_value_key.to_string(),
-        base_value: pool.base_value_key.to_string(),
-        rate: Some(amount_out_format.to_f32().unwrap()),
-        data: pool.data.clone(),
-    })
+//计算储备和lp总量需要的账户
+pub fn pool_account_keys(pool: &PoolInfo) -> Vec<Pubkey> {
+    let mut keys = match pool_info_amm(pool) {
+        Some(amm) => amm.accounts_needed(),
+        None => vec![pool.pool_key, pool.quote_value_key, pool.base_value_key],
+    };
+    //saber池子文件里可能没有lp mint，从SwapInfo里取
+    if pool.lp_mint_key != Pubkey::default() {
+        keys.push(pool.lp_mint_key);
+    }
+    keys
 }
 
-fn cal_orca(account_map: &HashMap<String, Account>,
-            token_map: &HashMap<String, TokenAddr>,
-            pool: &PoolInfo) -> Result<PoolResponse> {
-    let amount_in = 1.0;
-
-    let pool_ac = account_map.get(&pool.pool_key.to_string()).unwrap();
-    let pool_clone = pool_ac.clone();
-    let pool_info = SwapV1::unpack_from_slice(&pool_clone.data).unwrap();
-
-    let quote_ac = account_map.get(&pool.quote_value_key.to_string()).unwrap();
-    let mut quote_clone = quote_ac.clone();
-    let quote_ac_info = convert_to_info(&pool.quote_value_key, &mut quote_clone);
-    let quote_info = Processor::unpack_token_account(&quote_ac_info, &quote_ac.owner).unwrap();
-
-    let base_ac = account_map.get(&pool.base_value_key.to_string()).unwrap();
-    let mut base_clone = base_ac.clone();
-    let base_ac_info = convert_to_info(&pool.base_value_key, &mut base_clone);
-    let base_info = Processor::unpack_token_account(&base_ac_info, &base_ac.owner).unwrap();
-
-    let pl_ac = account_map.get(&pool.lp_mint_key.to_string()).unwrap();
-    let pl_info = Mint::unpack(&pl_ac.data).unwrap();
-
-    let basic: i128 = 10;
-    let quote_token = token_map.get(&pool.quote_mint_key.to_string()).unwrap();
-    let base_token = token_map.get(&pool.base_mint_key.to_string()).unwrap();
-    let quote_pow = basic.pow(quote_token.decimal as u32);
-    let base_pow = basic.pow(base_token.decimal as u32);
-
-    let fee_ratio = get_swap_fee_ratio(pool_info.fees.trade_fee_numerator,
-                                       pool_info.fees.trade_fee_denominator,
-                                       pool_info.fees.owner_trade_fee_numerator,
-                                       pool_info.fees.owner_trade_fee_denominator);
-
-    let from_amount = amount_in * (base_pow as f64);
-    let from_amount_with_fee = from_amount - (from_amount * (fee_ratio as f64));
-    let amount_out;
-
-    let amp = pool.data.get("amp");
-    if amp.is_some() {
-        let amp_u64 = amp.unwrap().parse::<u64>().unwrap();
-        let sc = StableCurve {
-            amp: amp_u64
-        };
-        let sc_result = sc.swap_without_fees(from_amount_with_fee.to_u128().unwrap(),
-                                             base_info.amount as u128,
-                                             quote_info.amount as u128,
-                                             TradeDirection::BtoA).unwrap();
-        amount_out = Decimal::from_u128(sc_result.destination_amount_swapped).unwrap();
-    } else {
-        let sc = SwapCurve::default();
-        let sc_result = sc.calculator.swap_without_fees(from_amount_with_fee.to_u128().unwrap(),
-                                                        base_info.amount as u128,
-                                                        quote_info.amount as u128,
-                                                        TradeDirection::BtoA).unwrap();
-        amount_out = Decimal::from_u128(sc_result.destination_amount_swapped).unwrap();
+//池子的(quote, base)储备，raydium加上open orders里的资金并扣除待结算的pnl
+pub fn pool_reserves(account_map: &HashMap<String, Account>, pool: &PoolInfo) -> Result<(u64, u64)> {
+    match pool_info_amm(pool) {
+        Some(mut amm) => {
+            amm.update(account_map)?;
+            Ok(amm.reserves())
+        }
+        None => Ok((load_token_amount(account_map, &pool.quote_value_key)?,
+                    load_token_amount(account_map, &pool.base_value_key)?)),
     }
+}
 
-    let mut amount_out_format = amount_out.div(Decimal::from(quote_pow));
-    amount_out_format.rescale(quote_token.decimal as u32);
+//lp token的总发行量
+pub fn pool_lp_supply(account_map: &HashMap<String, Account>, pool: &PoolInfo) -> Result<u64> {
+    let lp_ac = get_account(account_map, &pool.lp_mint_key)?;
+    let lp_info = Mint::unpack(&lp_ac.data).map_err(|_| ApiError::AccountDecode(pool.lp_mint_key.to_string()))?;
+    Ok(lp_info.supply)
+}
 
-    let matket_type = pool.market_type.get_name();
+//按池子的adapter计算1个base能换多少quote，附上两边储备和lp总量。没有adapter的市场不返回
+fn cal_pool(account_map: &HashMap<String, Account>,
+            token_map: &HashMap<String, TokenAddr>,
+            pool: &PoolInfo) -> Result<Option<PoolResponse>> {
+    let mut amm = match pool_info_amm(pool) {
+        Some(amm) => amm,
+        None => return Ok(None),
+    };
+    amm.update(account_map)?;
+
+    let (quote_token, base_token) = load_pair_tokens(token_map, pool)?;
+    let quote = amm.quote(10u64.pow(base_token.decimal as u32), SwapDirection::BaseToQuote)?;
+    let (quote_amount, base_amount) = amm.reserves();
 
     let mut pool_data = pool.data.clone();
-    pool_data.insert("poolSupply".to_string(), pl_info.supply.to_string());
-    pool_data.insert("quoteAmount".to_string(), quote_info.amount.to_string());
-    pool_data.insert("baseAmount".to_string(), base_info.amount.to_string());
+    pool_data.extend(amm.pool_data());
+    pool_data.insert("quoteAmount".to_string(), quote_amount.to_string());
+    pool_data.insert("baseAmount".to_string(), base_amount.to_string());
+
+    //池子文件里没有lp mint时用链上池子账户里的
+    let lp_mint = match pool_data.get("poolMint").and_then(|x| Pubkey::from_str(x).ok()) {
+        Some(pool_mint) if pool.lp_mint_key == Pubkey::default() => pool_mint,
+        _ => pool.lp_mint_key,
+    };
+    if let Some(lp_info) = account_map.get(&lp_mint.to_string()).and_then(|x| Mint::unpack(&x.data).ok()) {
+        pool_data.insert("poolSupply".to_string(), lp_info.supply.to_string());
+    }
 
-    // let pool_token_amount = orca::data::calculate_pool_deposit_amount(quote_info.amount, base_info.amount, pl_info.supply);
-    // pool_data.insert("poolTokenAmount".to_string(), pool_token_amount.to_string());
-    Ok(PoolResponse {
+    let matket_type = pool.market_type.get_name();
+    Ok(Some(PoolResponse {
         market: matket_type.0,
         program_id: matket_type.1,
         pool_account: pool.pool_key.to_string(),
         quote_mint: pool.quote_mint_key.to_string(),
         base_mint: pool.base_mint_key.to_string(),
-        lp_mint: pool.lp_mint_key.to_string(),
+        lp_mint: lp_mint.to_string(),
         quote_value: pool.quote_value_key.to_string(),
         base_value: pool.base_value_key.to_string(),
-        rate: Some(amount_out_format.to_f32().unwrap()),
+        rate: Some(ui_amount(quote.amount_out, quote_token.decimal) as f32),
         data: pool_data,
-    })
+        slot: None,
+    }))
 }
 
-
-pub fn pool_list(page: Option<u32>, pagesize: Option<u32>,
+pub fn pool_list(registry: &RegistryData,
+                 page: Option<u32>, pagesize: Option<u32>,
              lp_mint: Option<String>, farm_mint: Option<String>,
              address: Option<String>, market: Option<String>,
-             search: Option<String>) -> Json<PoolListResponse> {
-    let mut vec: Vec<RawPool> = load_pool_data(market);
-    //查询
-    let token_main_path = "./token_mint.json".to_string();
-    let tokens_adr = api::load_token_data_from_file(&token_main_path).expect("load token data fail");
+             search: Option<String>) -> ApiResult<Json<PoolListResponse>> {
+    let mut vec: Vec<RawPool> = registry.raw_pools(market.as_deref());
+    let tokens_adr = &registry.tokens;
 
     for mut pool in &mut vec {
-        pool.quote_token = fill_token_info(&tokens_adr, &pool.quote_mint);
-        pool.base_token = fill_token_info(&tokens_adr, &pool.base_mint);
+        pool.quote_token = fill_token_info(tokens_adr, &pool.quote_mint);
+        pool.base_token = fill_token_info(tokens_adr, &pool.base_mint);
     }
 
     //查询固定某一个lp_mint
@@ -362,20 +248,20 @@ pub fn pool_list(page: Option<u32>, pagesize: Option<u32>,
         Some(a) => {
             for pool in &mut vec {
                 if pool.lp_mint.eq(&a) {
-                    return Json(PoolListResponse {
+                    return Ok(Json(PoolListResponse {
                         total: 1,
                         pagesize: 1,
                         page: 1,
                         data: vec![pool.clone()],
-                    });
+                    }));
                 }
             }
-            return Json(PoolListResponse {
+            return Ok(Json(PoolListResponse {
                 total: 0,
                 pagesize: 1,
                 page: 1,
                 data: vec![],
-            });
+            }));
         }
         None => {}
     }
@@ -383,37 +269,23 @@ pub fn pool_list(page: Option<u32>, pagesize: Option<u32>,
     //查询固定某一个farm_mint
     match farm_mint {
         Some(a) => {
-
-            //加载farm-pool对应关系
-            let farm_main_path = "./resource/farm/orca.json".to_string();
-            let farm_pool_map = load_farm_data_from_file(&farm_main_path).unwrap();
-            let lp_mint = farm_pool_map.get(&a);
-            if lp_mint.is_some() {
-                for pool in &mut vec {
-                    if pool.lp_mint.eq(lp_mint.unwrap()) {
-                        return Json(PoolListResponse {
-                            total: 1,
-                            pagesize: 1,
-                            page: 1,
-                            data: vec![pool.clone()],
-                        });
-                    }
+            if let Some(lp_mint) = registry.farm_to_lp.get(&a) {
+                if let Some(pool) = vec.iter().find(|x| x.lp_mint.eq(lp_mint)) {
+                    return Ok(Json(PoolListResponse {
+                        total: 1,
+                        pagesize: 1,
+                        page: 1,
+                        data: vec![pool.clone()],
+                    }));
                 }
-            } else {
-                return Json(PoolListResponse {
-                    total: 0,
-                    pagesize: 1,
-                    page: 1,
-                    data: vec![],
-                });
             }
 
-            return Json(PoolListResponse {
+            return Ok(Json(PoolListResponse {
                 total: 0,
                 pagesize: 1,
                 page: 1,
                 data: vec![],
-            });
+            }));
         }
         None => {}
     }
@@ -438,8 +310,8 @@ pub fn pool_list(page: Option<u32>, pagesize: Option<u32>,
             vec = vec
                 .into_iter()
                 .filter(|x|
-                    (x.quote_token.is_some() && x.quote_token.as_ref().unwrap().symbol.to_uppercase().trim().contains(match_symbol)) ||
-                        (x.base_token.is_some() && x.base_token.as_ref().unwrap().symbol.clone().to_uppercase().trim().contains(match_symbol))
+                    x.quote_token.as_ref().map_or(false, |t| t.symbol.to_uppercase().trim().contains(match_symbol)) ||
+                        x.base_token.as_ref().map_or(false, |t| t.symbol.to_uppercase().trim().contains(match_symbol))
                 ).collect();
         }
         None => {}
@@ -450,37 +322,45 @@ pub fn pool_list(page: Option<u32>, pagesize: Option<u32>,
     let total = vec.len() as u32;
 
     if total == 0 {
-        return Json(PoolListResponse {
+        return Ok(Json(PoolListResponse {
             total,
             pagesize: total,
             page: 1,
             data: vec![],
-        });
+        }));
     }
 
     match page {
         Some(p) => {
+            if p == 0 {
+                return Err(ApiError::BadPagination("page starts from 1".to_string()));
+            }
             start_page = p - 1;
         }
         None => {
-            return Json(PoolListResponse {
+            return Ok(Json(PoolListResponse {
                 total,
                 pagesize: total,
                 page: 1,
                 data: vec,
-            });
+            }));
         }
     }
 
     match pagesize {
         Some(s) => {
+            if s == 0 {
+                return Err(ApiError::BadPagination("pagesize must be greater than 0".to_string()));
+            }
             size = s;
         }
         None => {}
     }
 
-    start_index = start_page * size;
-    let mut end_index = start_index + size;
+    start_index = start_page.checked_mul(size)
+        .filter(|x| *x < total)
+        .ok_or_else(|| ApiError::BadPagination(format!("page {} out of range", start_page + 1)))?;
+    let mut end_index = start_index.saturating_add(size);
 
     if end_index >= total {
         end_index = total;
@@ -488,36 +368,62 @@ pub fn pool_list(page: Option<u32>, pagesize: Option<u32>,
 
     let res = vec[start_index as usize..end_index as usize].to_vec();
 
-    Json(PoolListResponse {
+    Ok(Json(PoolListResponse {
         total,
         pagesize: size,
         page: start_page + 1,
         data: res,
-    })
+    }))
+}
+
+//没有配置lp mint的saber池子里pool_mint等于lp_mint的那个，lp mint补进返回的PoolInfo
+async fn saber_pool_by_lp(registry: &RegistryData, cache: &AccountCache, lp_mint: &str) -> ApiResult<Option<PoolInfo>> {
+    let pools: Vec<&PoolInfo> = registry.pools.iter()
+        .filter(|x| matches!(x.market_type, MarketType::Saber(_, _)) && x.lp_mint_key == Pubkey::default())
+        .collect();
+    if pools.is_empty() {
+        return Ok(None);
+    }
+    let keys: Vec<Pubkey> = pools.iter().map(|x| x.pool_key).collect();
+    let (account_map, _) = cache.get_accounts(&keys).await?;
+    for pool in pools {
+        let swap_info = match account_map.get(&pool.pool_key.to_string()).map(|x| SwapInfo::unpack_from_slice(&x.data)) {
+            Some(Ok(swap_info)) => swap_info,
+            _ => continue,
+        };
+        if swap_info.pool_mint.to_string().eq(lp_mint) {
+            let mut pool = pool.clone();
+            pool.lp_mint_key = swap_info.pool_mint;
+            return Ok(Some(pool));
+        }
+    }
+    Ok(None)
 }
 
-pub fn pool_info(req: Json<PoolRequest>) -> Json<Vec<PoolResponse>> {
+pub async fn pool_info(registry: &RegistryData, cache: &AccountCache, req: Json<PoolRequest>) -> ApiResult<Json<Vec<PoolResponse>>> {
     let mut request = req.0;
 
-    if request.farm_mint.is_some() {
-        //加载farm-pool对应关系
-        let farm_mint = request.farm_mint.clone().unwrap();
-        let farm_main_path = "./resource/farm/orca.json".to_string();
-        let farm_pool_map = load_farm_data_from_file(&farm_main_path).unwrap();
-        let lp_mint = farm_pool_map.get(&farm_mint);
-        if lp_mint.is_some() {
-            request.lp_mint = lp_mint.cloned();
-        } else {
-            return Json(vec![]);
-        }
+    if let Some(farm_mint) = &request.farm_mint {
+        let lp_mint = registry.farm_to_lp.get(farm_mint)
+            .ok_or_else(|| ApiError::InvalidRequest(format!("unknown farm_mint {}", farm_mint)))?;
+        request.lp_mint = Some(lp_mint.clone());
     }
 
-    let opt_pool = request.load_data();
+    let mut opt_pool = request.load_data(registry)?;
+    //按lp_mint查找时，池子文件里没有lp mint的saber池子用链上SwapInfo的pool_mint匹配
+    let need_saber = request.market.as_ref().map_or(true, |x| x.eq("saber"));
+    if let Some(lp_mint) = &request.lp_mint {
+        if need_saber && registry.pool_by_lp(lp_mint, Some("saber")).is_none() {
+            if let Some(pool) = saber_pool_by_lp(registry, cache, lp_mint).await? {
+                opt_pool.push(pool);
+            }
+        }
+    }
 
     let pool_info = match request.need_rate {
         Some(bool) => {
             if bool {
-                cal_rate(&opt_pool, &request.slippage)
+                cal_rate(&opt_pool, &request.slippage, &registry.tokens, cache).await?
             } else {
                 opt_pool.iter()
                     .map(|x| -> PoolResponse{
@@ -533,6 +439,7 @@ pub fn pool_info(req: Json<PoolRequest>) -> Json<Vec<PoolResponse>> {
                             base_value: x.base_value_key.to_string(),
                             rate: None,
                             data: x.data.clone(),
+                            slot: None,
                         }
                     }).collect()
             }
@@ -552,10 +459,11 @@ pub fn pool_info(req: Json<PoolRequest>) -> Json<Vec<PoolResponse>> {
                         base_value: x.base_value_key.to_string(),
                         rate: None,
                         data: x.data.clone(),
+                        slot: None,
                     }
                 }).collect()
         }
     };
 
-    Json(pool_info)
+    Ok(Json(pool_info))
 }
\ No newline at end of file
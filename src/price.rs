@@ -0,0 +1,708 @@
+use crate::account_cache::AccountCache;
+use crate::adapter::route_amms;
+use crate::error::{ApiError, ApiResult};
+use crate::opt_core::{OptInitData, MID_PROBE_DIVISOR};
+use crate::registry::{Registry, RegistryData};
+use crate::node_client::extract;
+use crate::response::{OptMarket, raw_amount, ui_amount};
+use serde::{Serialize, Deserialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use rocket::figment::Figment;
+use rust_decimal::prelude::FromStr;
+use solana_program::pubkey::Pubkey;
+use market::market::MarketSwap;
+use market::dex::{self, DEXES};
+
+pub const USDC_MINT: &str = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
+const PRICE_MAX_HOPS: usize = 3;
+//每个跳数的候选路径数
+const PRICE_ROUTE_CANDIDATES: usize = 5;
+//路径上每一跳折算成USDC的深度都要达到这个值才用来定价
+const MIN_LIQUIDITY_USDC: f64 = 1_000.0;
+//深度达到这个值时深度部分的置信度为满分
+const FULL_CONFIDENCE_LIQUIDITY_USDC: f64 = 1_000_000.0;
+//按价值约10 USDC的数量报价，价格冲击可以忽略
+const PROBE_VALUE_USDC: f64 = 10.0;
+//1个token换不到1个USDC最小单位时依次放大报价数量
+const PROBE_SCALES: [u64; 3] = [1, 1_000, 1_000_000];
+//最优路径和次优路径价差达到这个比例时置信度为0
+const MAX_PRICE_SPREAD: f64 = 0.1;
+//只有一条路径时无法交叉验证，置信度打折
+const SINGLE_PATH_FACTOR: f64 = 0.8;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TokenPrice {
+    pub mint: String,
+    //1个token值多少USDC，包含路径上的手续费
+    pub price: f64,
+    //定价路径经过的市场，USDC本身为peg
+    pub source: String,
+    //定价路径上的池子
+    pub route: Vec<String>,
+    //路径上最浅的一跳折算成USDC的深度
+    pub liquidity: f64,
+    //0到1，由深度和多条路径之间的价差决定
+    pub confidence: f64,
+    pub slot: u64,
+}
+
+pub fn usdc_mint() -> ApiResult<Pubkey> {
+    Pubkey::from_str(USDC_MINT).map_err(|e| ApiError::Internal(e.to_string()))
+}
+
+//每个mint按路由图找到USDC的最优路径定价。不在token列表里或没有满足深度要求的路径时不在结果里
+pub async fn price_tokens(registry: &RegistryData, cache: &AccountCache, mints: &[Pubkey]) -> ApiResult<HashMap<Pubkey, TokenPrice>> {
+    let usdc = usdc_mint()?;
+    let tokens_adr = &registry.tokens;
+
+    let mut res = HashMap::new();
+    //定价使用全部市场
+    let markets = dex::ids();
+    let mut swaps = vec![];
+    let mut seen = HashSet::new();
+    for mint in mints {
+        if !seen.insert(*mint) {
+            continue;
+        }
+        if mint.eq(&usdc) {
+            res.insert(*mint, TokenPrice {
+                mint: mint.to_string(),
+                price: 1.0,
+                source: "peg".to_string(),
+                route: vec![],
+                liquidity: 0.0,
+                confidence: 1.0,
+                slot: 0,
+            });
+            continue;
+        }
+        if !tokens_adr.contains_key(&mint.to_string()) {
+            continue;
+        }
+        swaps.extend(registry.graph.find_swaps(mint, &usdc, PRICE_MAX_HOPS, PRICE_ROUTE_CANDIDATES, &markets));
+    }
+    //中间token必须有精度信息才能报价
+    swaps.retain(|swap| {
+        swap.step.iter().all(|x| tokens_adr.contains_key(&x.quote_mint_key.to_string()) &&
+            tokens_adr.contains_key(&x.base_mint_key.to_string()))
+    });
+    if swaps.is_empty() {
+        return Ok(res);
+    }
+
+    let (opt_init_data, slot) = quote_data(registry, cache, swaps).await?;
+
+    //按起点mint分组，每组取满足深度要求的路径里按深度加权的价格中位数
+    let mut quotes: HashMap<Pubkey, Vec<(f64, f64, OptMarket)>> = HashMap::new();
+    for swap in opt_init_data.swaps.iter() {
+        let mint = *swap.step[0].source_mint();
+        let decimals = tokens_adr.get(&mint.to_string()).map(|x| x.decimal).unwrap_or_default();
+        if let Some(market_swap) = probe_quote(&opt_init_data, swap, decimals)? {
+            let price = market_swap.amount_out / market_swap.amount_in;
+            let liquidity = path_liquidity(&market_swap);
+            if liquidity >= MIN_LIQUIDITY_USDC {
+                quotes.entry(mint).or_default().push((price, liquidity, market_swap));
+            }
+        }
+    }
+
+    for (mint, mut paths) in quotes {
+        let index = weighted_median(&mut paths);
+        let (price, liquidity, market_swap) = &paths[index];
+        //和深度最大的另一条路径比较价格一致程度
+        let second = paths.iter().enumerate()
+            .filter(|(i, _)| *i != index)
+            .max_by(|a, b| a.1.1.total_cmp(&b.1.1))
+            .map(|(_, x)| x.0);
+        res.insert(mint, TokenPrice {
+            mint: mint.to_string(),
+            price: *price,
+            source: market_swap.market.clone(),
+            route: market_swap.routes.iter().map(|x| x.route_key.clone()).collect(),
+            liquidity: *liquidity,
+            confidence: confidence(*price, *liquidity, second),
+            slot,
+        });
+    }
+    Ok(res)
+}
+
+//中间价只取有池子储备的市场的直连池子，serum订单簿没有
+fn mid_price_markets() -> Vec<&'static str> {
+    DEXES.iter().filter(|x| x.mid_price).map(|x| x.id).collect()
+}
+
+//加载路径上的账户和token精度，剔除链上状态不允许swap的池子
+async fn quote_data(registry: &RegistryData, cache: &AccountCache, swaps: Vec<MarketSwap>) -> ApiResult<(OptInitData, u64)> {
+    let mut route_tokens = HashMap::new();
+    for swap in &swaps {
+        for step in &swap.step {
+            for mint in [step.quote_mint_key.to_string(), step.base_mint_key.to_string()] {
+                if let Some(token) = registry.tokens.get(&mint) {
+                    route_tokens.insert(mint, token.clone());
+                }
+            }
+        }
+    }
+    let mut amms = route_amms(&swaps);
+    let (account_map, slot) = cache.get_amm_accounts(&mut amms, &[]).await?;
+
+    let mut opt_init_data = OptInitData {
+        amount_in: 0,
+        tokens_adr: route_tokens,
+        account_map,
+        swaps,
+        amms: HashMap::new(),
+    };
+    opt_init_data.exclude_unavailable();
+    Ok((opt_init_data, slot))
+}
+
+//先用1个token估出大致价格，再按价值约PROBE_VALUE_USDC的数量报价
+fn probe_quote(opt_init_data: &OptInitData, swap: &MarketSwap, decimals: u8) -> ApiResult<Option<OptMarket>> {
+    let one = 10u64.saturating_pow(decimals as u32);
+    for scale in PROBE_SCALES {
+        let amount = one.saturating_mul(scale);
+        let rough = match opt_init_data.quote(swap, amount)? {
+            Some(market_swap) if market_swap.amount_out_raw > 0 => market_swap,
+            Some(_) => continue,
+            None => return Ok(None),
+        };
+        let unit_price = rough.amount_out / rough.amount_in;
+        let amount = raw_amount(PROBE_VALUE_USDC / unit_price, decimals).max(1);
+        return match opt_init_data.quote(swap, amount)? {
+            Some(market_swap) if market_swap.amount_out_raw > 0 => Ok(Some(market_swap)),
+            _ => Ok(Some(rough)),
+        };
+    }
+    Ok(None)
+}
+
+//从最后一跳往前，把每一跳目标一侧的储备按后续各跳的成交价折算成USDC，取最小值
+fn path_liquidity(market_swap: &OptMarket) -> f64 {
+    let mut rate = 1.0;
+    let mut liquidity = f64::MAX;
+    for route in market_swap.routes.iter().rev() {
+        liquidity = liquidity.min(ui_amount(route.destination_value, route.destination_decimals) * rate);
+        if route.source_amount > 0.0 {
+            rate *= route.destination_amount / route.source_amount;
+        }
+    }
+    liquidity
+}
+
+//按价格排序后累计深度过半的那条路径，浅池子报出的离谱价格不会被选中
+fn weighted_median<T>(paths: &mut [(f64, f64, T)]) -> usize {
+    paths.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let total: f64 = paths.iter().map(|x| x.1).sum();
+    let mut cumulative = 0.0;
+    for (index, path) in paths.iter().enumerate() {
+        cumulative += path.1;
+        if cumulative * 2.0 >= total {
+            return index;
+        }
+    }
+    paths.len() - 1
+}
+
+//深度按对数在[MIN, FULL]之间映射到0.5到1，再乘上和次优路径价格的一致程度
+fn confidence(price: f64, liquidity: f64, second_price: Option<f64>) -> f64 {
+    let depth = ((liquidity.log10() - MIN_LIQUIDITY_USDC.log10()) /
+        (FULL_CONFIDENCE_LIQUIDITY_USDC.log10() - MIN_LIQUIDITY_USDC.log10())).clamp(0.0, 1.0);
+    let agreement = match second_price {
+        Some(second) => (1.0 - (price - second).abs() / price / MAX_PRICE_SPREAD).clamp(0.0, 1.0),
+        None => SINGLE_PATH_FACTOR,
+    };
+    (0.5 + 0.5 * depth) * agreement
+}
+
+//一个交易对最多参与聚合的池子数
+const MAX_PAIR_POOLS: usize = 16;
+//价格冲击的标准交易额，单位USDC
+pub const IMPACT_SIZES_USDC: [f64; 3] = [1_000.0, 10_000.0, 100_000.0];
+
+const PRICE_SNAPSHOT_SECS: &str = "price_snapshot_secs";
+const PRICE_HISTORY_PATH: &str = "price_history_path";
+const DEFAULT_SNAPSHOT_SECS: u64 = 60;
+//快照保留的时长，也是twap窗口的上限
+pub const MAX_HISTORY_SECS: u64 = 86_400;
+pub const DEFAULT_TWAP_SECS: u64 = 3_600;
+//后台定期快照的交易对数上限，已满时移除最久没有被查询的交易对
+const MAX_TRACKED_PAIRS: usize = 64;
+
+//单个池子的中间价，price是1个base值多少quote
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PoolPrice {
+    pub market: String,
+    pub pool_account: String,
+    pub price: f64,
+    //两边储备折算成quote的总量
+    pub liquidity: f64,
+    pub weight: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct MidPrice {
+    //各池子中间价按深度加权
+    pub price: f64,
+    pub liquidity: f64,
+    pub pools: Vec<PoolPrice>,
+    pub slot: u64,
+}
+
+//按交易额拆单成交的结果，价格冲击包含手续费
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ImpactQuote {
+    pub amount_in: f64,
+    pub amount_out: f64,
+    //1个base成交了多少quote
+    pub effective_price: f64,
+    //相对中间价更差的百分比
+    pub price_impact_pct: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PriceImpact {
+    pub size_usdc: f64,
+    //用quote买base
+    pub buy: Option<ImpactQuote>,
+    //卖base换quote
+    pub sell: Option<ImpactQuote>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PriceResponse {
+    pub base_mint: String,
+    pub quote_mint: String,
+    pub price: f64,
+    pub liquidity: f64,
+    pub pools: Vec<PoolPrice>,
+    //quote没有USDC价格时为空
+    pub impact: Vec<PriceImpact>,
+    //窗口内没有快照时为None
+    pub twap: Option<f64>,
+    pub twap_window_secs: u64,
+    pub twap_samples: usize,
+    pub slot: u64,
+}
+
+//交易对两个方向的直连池子
+struct PairData {
+    base: Pubkey,
+    quote: Pubkey,
+    base_decimals: u8,
+    quote_decimals: u8,
+    //quote -> base
+    buy: OptInitData,
+    //base -> quote
+    sell: OptInitData,
+    slot: u64,
+}
+
+async fn load_pair(registry: &RegistryData, cache: &AccountCache, base: &Pubkey, quote: &Pubkey) -> ApiResult<PairData> {
+    let base_token = registry.tokens.get(&base.to_string()).ok_or_else(|| ApiError::UnknownMint(base.to_string()))?;
+    let quote_token = registry.tokens.get(&quote.to_string()).ok_or_else(|| ApiError::UnknownMint(quote.to_string()))?;
+
+    let markets = mid_price_markets();
+    let buy_swaps = registry.graph.find_swaps(quote, base, 1, MAX_PAIR_POOLS, &markets);
+    let sell_swaps = registry.graph.find_swaps(base, quote, 1, MAX_PAIR_POOLS, &markets);
+    if buy_swaps.is_empty() || sell_swaps.is_empty() {
+        return Err(ApiError::NoRoute(base.to_string(), quote.to_string()));
+    }
+
+    let (buy, buy_slot) = quote_data(registry, cache, buy_swaps).await?;
+    let (sell, sell_slot) = quote_data(registry, cache, sell_swaps).await?;
+    Ok(PairData {
+        base: *base,
+        quote: *quote,
+        base_decimals: base_token.decimal,
+        quote_decimals: quote_token.decimal,
+        buy,
+        sell,
+        slot: buy_slot.min(sell_slot),
+    })
+}
+
+//按池子储备的一小部分报价，先报1个最小单位读出储备
+fn marginal_quote(opt_init_data: &OptInitData, swap: &MarketSwap) -> ApiResult<Option<OptMarket>> {
+    let reserve = match opt_init_data.quote(swap, 1)? {
+        Some(market_swap) => market_swap.routes[0].source_value,
+        None => return Ok(None),
+    };
+    let amount = (reserve / MID_PROBE_DIVISOR).max(1);
+    match opt_init_data.quote(swap, amount)? {
+        Some(market_swap) if market_swap.amount_out_raw > 0 => Ok(Some(market_swap)),
+        _ => Ok(None),
+    }
+}
+
+//同一个池子两个方向的边际价格都扣了一次手续费，几何平均后手续费抵消，曲线类型不影响结果
+fn pair_mid_price(pair: &PairData) -> ApiResult<MidPrice> {
+    let mut pools = vec![];
+    for sell_swap in pair.sell.swaps.iter() {
+        let pool_key = sell_swap.step[0].pool_key;
+        let buy_swap = match pair.buy.swaps.iter().find(|x| x.step[0].pool_key.eq(&pool_key)) {
+            Some(swap) => swap,
+            None => continue,
+        };
+        let (sell, buy) = match (marginal_quote(&pair.sell, sell_swap)?, marginal_quote(&pair.buy, buy_swap)?) {
+            (Some(sell), Some(buy)) => (sell, buy),
+            _ => continue,
+        };
+        let sell_rate = sell.amount_out / sell.amount_in;
+        let buy_rate = buy.amount_out / buy.amount_in;
+        let price = (sell_rate / buy_rate).sqrt();
+
+        let route = &sell.routes[0];
+        let liquidity = ui_amount(route.destination_value, pair.quote_decimals) +
+            ui_amount(route.source_value, pair.base_decimals) * price;
+        pools.push(PoolPrice {
+            market: sell.market.clone(),
+            pool_account: pool_key.to_string(),
+            price,
+            liquidity,
+            weight: 0.0,
+        });
+    }
+
+    let liquidity: f64 = pools.iter().map(|x| x.liquidity).sum();
+    if pools.is_empty() || liquidity <= 0.0 {
+        return Err(ApiError::NoRoute(pair.base.to_string(), pair.quote.to_string()));
+    }
+    for pool in pools.iter_mut() {
+        pool.weight = pool.liquidity / liquidity;
+    }
+    pools.sort_by(|a, b| b.liquidity.total_cmp(&a.liquidity));
+
+    Ok(MidPrice {
+        price: pools.iter().map(|x| x.price * x.weight).sum(),
+        liquidity,
+        pools,
+        slot: pair.slot,
+    })
+}
+
+pub async fn mid_price(registry: &RegistryData, cache: &AccountCache, base: &Pubkey, quote: &Pubkey) -> ApiResult<MidPrice> {
+    let pair = load_pair(registry, cache, base, quote).await?;
+    pair_mid_price(&pair)
+}
+
+//输入数量在直连池子之间最优拆单，按总产出计算成交价
+fn split_quote(opt_init_data: &mut OptInitData, amount_in: u64) -> ApiResult<Option<(f64, f64)>> {
+    if amount_in == 0 {
+        return Ok(None);
+    }
+    opt_init_data.amount_in = amount_in;
+    let split = opt_init_data.optimal_split()?;
+    let amount_out: f64 = split.iter().map(|x| x.amount_out).sum();
+    let amount_in: f64 = split.iter().map(|x| x.amount_in).sum();
+    if amount_out <= 0.0 {
+        return Ok(None);
+    }
+    Ok(Some((amount_in, amount_out)))
+}
+
+//quote_usdc是1个quote值多少USDC
+fn pair_impact(pair: &mut PairData, mid: f64, quote_usdc: f64) -> ApiResult<Vec<PriceImpact>> {
+    let mut res = vec![];
+    for size in IMPACT_SIZES_USDC {
+        let quote_amount = size / quote_usdc;
+        let buy = split_quote(&mut pair.buy, raw_amount(quote_amount, pair.quote_decimals))?
+            .map(|(amount_in, amount_out)| {
+                let effective_price = amount_in / amount_out;
+                ImpactQuote {
+                    amount_in,
+                    amount_out,
+                    effective_price,
+                    price_impact_pct: (effective_price / mid - 1.0) * 100.0,
+                }
+            });
+        let sell = split_quote(&mut pair.sell, raw_amount(quote_amount / mid, pair.base_decimals))?
+            .map(|(amount_in, amount_out)| {
+                let effective_price = amount_out / amount_in;
+                ImpactQuote {
+                    amount_in,
+                    amount_out,
+                    effective_price,
+                    price_impact_pct: (1.0 - effective_price / mid) * 100.0,
+                }
+            });
+        res.push(PriceImpact {
+            size_usdc: size,
+            buy,
+            sell,
+        });
+    }
+    Ok(res)
+}
+
+//quote_mint为None时按USDC计价，twap_secs不超过快照保留时长
+pub async fn get_price(registry: &RegistryData, cache: &AccountCache, history: &PriceHistory,
+                       base_mint: &str, quote_mint: Option<&str>, twap_secs: Option<u64>) -> ApiResult<PriceResponse> {
+    let usdc = usdc_mint()?;
+    let base = Pubkey::from_str(base_mint).map_err(|_| ApiError::UnknownMint(base_mint.to_string()))?;
+    let quote = match quote_mint {
+        Some(mint) => Pubkey::from_str(mint).map_err(|_| ApiError::UnknownMint(mint.to_string()))?,
+        None => usdc,
+    };
+    if base.eq(&quote) {
+        return Err(ApiError::InvalidRequest("base_mint and quote_mint are the same".to_string()));
+    }
+
+    let mut pair = load_pair(registry, cache, &base, &quote).await?;
+    let mid = pair_mid_price(&pair)?;
+
+    let quote_usdc = if quote.eq(&usdc) {
+        Some(1.0)
+    } else if base.eq(&usdc) {
+        Some(1.0 / mid.price)
+    } else {
+        price_tokens(registry, cache, &[quote]).await?.get(&quote).map(|x| x.price)
+    };
+    let impact = match quote_usdc {
+        Some(quote_usdc) if quote_usdc > 0.0 => pair_impact(&mut pair, mid.price, quote_usdc)?,
+        _ => vec![],
+    };
+
+    //第一次查询的交易对加入后台快照，没有快照时先记一条当前价格
+    let key = pair_key(&base, &quote);
+    history.track(&key, now_secs());
+    if history.is_empty(&key) {
+        history.record(&key, PriceSnapshot {
+            timestamp: now_secs(),
+            price: mid.price,
+            slot: mid.slot,
+        });
+    }
+    let twap_window_secs = twap_secs.unwrap_or(DEFAULT_TWAP_SECS).min(MAX_HISTORY_SECS);
+    let (twap, twap_samples) = match history.twap(&key, twap_window_secs, now_secs()) {
+        Some((twap, samples)) => (Some(twap), samples),
+        None => (None, 0),
+    };
+
+    Ok(PriceResponse {
+        base_mint: base.to_string(),
+        quote_mint: quote.to_string(),
+        price: mid.price,
+        liquidity: mid.liquidity,
+        pools: mid.pools,
+        impact,
+        twap,
+        twap_window_secs,
+        twap_samples,
+        slot: mid.slot,
+    })
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|x| x.as_secs()).unwrap_or_default()
+}
+
+//快照按base/quote记录
+fn pair_key(base: &Pubkey, quote: &Pubkey) -> String {
+    format!("{}/{}", base, quote)
+}
+
+fn parse_pair_key(key: &str) -> Option<(Pubkey, Pubkey)> {
+    let (base, quote) = key.split_once('/')?;
+    Some((Pubkey::from_str(base).ok()?, Pubkey::from_str(quote).ok()?))
+}
+
+#[derive(Debug, Clone)]
+pub struct PriceConfig {
+    pub snapshot_secs: u64,
+    //为None时快照只保存在内存里
+    pub history_path: Option<String>,
+}
+
+impl PriceConfig {
+    pub fn from_rocket(figment: &Figment) -> anyhow::Result<Self> {
+        let snapshot_secs = extract::<u64>(figment, PRICE_SNAPSHOT_SECS)?.unwrap_or(DEFAULT_SNAPSHOT_SECS).max(1);
+        let history_path = extract::<String>(figment, PRICE_HISTORY_PATH)?.filter(|x| !x.is_empty());
+        Ok(PriceConfig {
+            snapshot_secs,
+            history_path,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct PriceSnapshot {
+    pub timestamp: u64,
+    pub price: f64,
+    pub slot: u64,
+}
+
+//被查询过的交易对由后台定期记录中间价，twap由这些快照计算
+pub struct PriceHistory {
+    config: PriceConfig,
+    pairs: RwLock<HashMap<String, VecDeque<PriceSnapshot>>>,
+    //交易对最近一次被查询的时间，只保存在内存里
+    requested: RwLock<HashMap<String, u64>>,
+}
+
+impl PriceHistory {
+    //配置了文件时加载之前保存的快照，文件不存在或解析失败时从空开始
+    pub fn new(config: PriceConfig) -> Self {
+        let mut pairs = HashMap::new();
+        if let Some(path) = &config.history_path {
+            match fs::read_to_string(path) {
+                Ok(raw) => match serde_json::from_str::<HashMap<String, VecDeque<PriceSnapshot>>>(&raw) {
+                    Ok(saved) => pairs = saved,
+                    Err(e) => println!("parse price history {} fail: {}", path, e),
+                },
+                Err(e) => println!("load price history {} fail: {}", path, e),
+            }
+        }
+        //加载的交易对从启动时开始计算查询时间
+        let now = now_secs();
+        let requested = pairs.keys().map(|x| (x.clone(), now)).collect();
+        PriceHistory {
+            config,
+            pairs: RwLock::new(pairs),
+            requested: RwLock::new(requested),
+        }
+    }
+
+    //记录查询时间，不在快照列表里时加入，已满时先移除最久没有被查询的交易对
+    pub fn track(&self, key: &str, now: u64) {
+        let mut pairs = self.pairs.write().unwrap();
+        let mut requested = self.requested.write().unwrap();
+        requested.insert(key.to_string(), now);
+        if pairs.contains_key(key) {
+            return;
+        }
+        while pairs.len() >= MAX_TRACKED_PAIRS {
+            let oldest = pairs.keys()
+                .min_by_key(|x| requested.get(*x).copied().unwrap_or_default())
+                .cloned();
+            match oldest {
+                Some(oldest) => {
+                    pairs.remove(&oldest);
+                    requested.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+        pairs.insert(key.to_string(), VecDeque::new());
+    }
+
+    //超过快照保留时长没有被查询的交易对不再记录
+    fn evict(&self, now: u64) {
+        let mut pairs = self.pairs.write().unwrap();
+        let mut requested = self.requested.write().unwrap();
+        let expired: Vec<String> = requested.iter()
+            .filter(|(_, x)| now.saturating_sub(**x) > MAX_HISTORY_SECS)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in expired {
+            pairs.remove(&key);
+            requested.remove(&key);
+        }
+    }
+
+    pub fn is_empty(&self, key: &str) -> bool {
+        self.pairs.read().unwrap().get(key).map_or(true, |x| x.is_empty())
+    }
+
+    //快照按时间递增追加，超出保留时长的从头部丢弃
+    pub fn record(&self, key: &str, snapshot: PriceSnapshot) {
+        let mut pairs = self.pairs.write().unwrap();
+        let snapshots = match pairs.get_mut(key) {
+            Some(snapshots) => snapshots,
+            None => return,
+        };
+        if snapshots.back().map_or(false, |x| x.timestamp > snapshot.timestamp) {
+            return;
+        }
+        snapshots.push_back(snapshot);
+        let oldest = snapshot.timestamp.saturating_sub(MAX_HISTORY_SECS);
+        while snapshots.front().map_or(false, |x| x.timestamp < oldest) {
+            snapshots.pop_front();
+        }
+    }
+
+    //每个快照的价格持续到下一个快照，最后一个持续到now。窗口开始前的最后一个快照从窗口开始计算。
+    //返回twap和参与计算的快照数
+    pub fn twap(&self, key: &str, window_secs: u64, now: u64) -> Option<(f64, usize)> {
+        let pairs = self.pairs.read().unwrap();
+        let snapshots = pairs.get(key)?;
+        let start = now.saturating_sub(window_secs);
+
+        let mut weighted = 0.0;
+        let mut duration = 0u64;
+        let mut samples = 0;
+        for (i, snapshot) in snapshots.iter().enumerate() {
+            let end = snapshots.get(i + 1).map_or(now, |x| x.timestamp).min(now);
+            if end <= start {
+                continue;
+            }
+            let begin = snapshot.timestamp.max(start);
+            if end > begin {
+                weighted += snapshot.price * (end - begin) as f64;
+                duration += end - begin;
+            }
+            samples += 1;
+        }
+
+        if samples == 0 {
+            return None;
+        }
+        //快照刚记录时持续时间为0，直接用最新价格
+        if duration == 0 {
+            return snapshots.back().map(|x| (x.price, samples));
+        }
+        Some((weighted / duration as f64, samples))
+    }
+
+    fn tracked_pairs(&self) -> Vec<String> {
+        self.pairs.read().unwrap().keys().cloned().collect()
+    }
+
+    //先写临时文件再改名，进程中途退出不会留下不完整的文件。只保存已经有快照的交易对
+    fn save(&self) -> anyhow::Result<()> {
+        let path = match &self.config.history_path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let pairs = self.pairs.read().unwrap();
+        let recorded: HashMap<&String, &VecDeque<PriceSnapshot>> = pairs.iter().filter(|(_, x)| !x.is_empty()).collect();
+        let raw = serde_json::to_string(&recorded)?;
+        let tmp_path = format!("{}.tmp", path);
+        fs::write(&tmp_path, raw)?;
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    //后台任务：按间隔记录每个交易对的中间价。需要在tokio运行时里调用
+    pub fn start(history: Arc<PriceHistory>, registry: Arc<Registry>, cache: Arc<AccountCache>) {
+        rocket::tokio::spawn(async move {
+            let mut interval = rocket::tokio::time::interval(Duration::from_secs(history.config.snapshot_secs));
+            loop {
+                interval.tick().await;
+                history.evict(now_secs());
+                let data = registry.data();
+                for key in history.tracked_pairs() {
+                    let (base, quote) = match parse_pair_key(&key) {
+                        Some(pair) => pair,
+                        None => continue,
+                    };
+                    match mid_price(&data, &cache, &base, &quote).await {
+                        Ok(mid) => history.record(&key, PriceSnapshot {
+                            timestamp: now_secs(),
+                            price: mid.price,
+                            slot: mid.slot,
+                        }),
+                        Err(e) => println!("price snapshot {} fail: {}", key, e),
+                    }
+                }
+                if let Err(e) = history.save() {
+                    println!("save price history fail: {}", e);
+                }
+            }
+        });
+    }
+}
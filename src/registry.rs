@@ -0,0 +1,231 @@
+use crate::api;
+use crate::pool::pool;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Outcome, Request};
+use market::graph::PoolGraph;
+use market::pool::{PoolInfo, RawPool};
+use market::dex::DEXES;
+use api::{TokenAddr, RawTokenAddr, load_token_data_from_file};
+use pool::{load_farm_data_from_file, load_pool_farm_data_from_file};
+
+const TOKEN_MINT_PATH: &str = "./token_mint.json";
+const FARM_PATH: &str = "./resource/farm/orca.json";
+//token_list按链区分的token列表
+const TOKEN_LIST_CHAINS: [&str; 2] = ["solana", "ethereum"];
+
+//检查文件修改时间的间隔
+const WATCH_INTERVAL_SECS: u64 = 5;
+
+//调用/admin/reload时需要在X-Admin-Token里带上这个环境变量的值，没有配置时不开放
+const ADMIN_TOKEN_ENV: &str = "ADMIN_TOKEN";
+const ADMIN_TOKEN_HEADER: &str = "X-Admin-Token";
+
+//启动时从json文件解析一次的池子和token数据，各接口直接按索引查询
+#[derive(Debug, Default)]
+pub struct RegistryData {
+    //token_mint.json，按mint索引
+    pub tokens: HashMap<String, TokenAddr>,
+    //resource/token下各条链的token列表
+    pub token_lists: HashMap<String, Vec<RawTokenAddr>>,
+    //market::dex里注册的全部市场路由池子的图，每个池子两个方向各一条边，查询时按市场过滤
+    pub graph: PoolGraph,
+    //pool_info用的池子，顺序和文件一致
+    pub pools: Vec<PoolInfo>,
+    //pool_list用的池子
+    pub raw_pools: Vec<RawPool>,
+    //farm mint -> lp mint
+    pub farm_to_lp: HashMap<String, String>,
+    //lp mint -> farm mint
+    pub lp_to_farm: HashMap<String, String>,
+    //lp mint -> pools下标
+    pools_by_lp: HashMap<String, Vec<usize>>,
+    //交易对(两个mint按字符串排序) -> pools下标
+    pools_by_pair: HashMap<(String, String), Vec<usize>>,
+}
+
+impl RegistryData {
+    pub fn load() -> Result<Self> {
+        let tokens = load_token_data_from_file(&TOKEN_MINT_PATH.to_string())?;
+
+        let mut token_lists = HashMap::new();
+        for chain in TOKEN_LIST_CHAINS {
+            let raw_info = fs::read_to_string(token_list_path(chain))?;
+            let vec: Vec<RawTokenAddr> = serde_json::from_str(&raw_info)?;
+            token_lists.insert(chain.to_string(), vec);
+        }
+
+        let mut graph = PoolGraph::new();
+        let mut pools = vec![];
+        for dex in DEXES {
+            graph.add_pools(dex.id, (dex.load_market_pools)()?);
+            if let Some(load_pools) = dex.load_pools {
+                pools.extend(load_pools()?);
+            }
+        }
+
+        let mut pools_by_lp: HashMap<String, Vec<usize>> = HashMap::new();
+        let mut pools_by_pair: HashMap<(String, String), Vec<usize>> = HashMap::new();
+        for (index, pool) in pools.iter().enumerate() {
+            pools_by_lp.entry(pool.lp_mint_key.to_string()).or_default().push(index);
+            let pair = pair_key(&pool.quote_mint_key.to_string(), &pool.base_mint_key.to_string());
+            pools_by_pair.entry(pair).or_default().push(index);
+        }
+
+        Ok(RegistryData {
+            tokens,
+            token_lists,
+            graph,
+            pools,
+            raw_pools: RawPool::load_all_pool_data(None)?,
+            farm_to_lp: load_farm_data_from_file(&FARM_PATH.to_string())?,
+            lp_to_farm: load_pool_farm_data_from_file(&FARM_PATH.to_string())?,
+            pools_by_lp,
+            pools_by_pair,
+        })
+    }
+
+    //market为None时不限市场，按文件顺序取第一个
+    pub fn pool_by_lp(&self, lp_mint: &str, market: Option<&str>) -> Option<&PoolInfo> {
+        self.pools_by_lp.get(lp_mint)?
+            .iter()
+            .map(|x| &self.pools[*x])
+            .find(|x| is_market(x, market))
+    }
+
+    //交易对不区分quote/base方向
+    pub fn pool_by_pair(&self, mint_a: &str, mint_b: &str, market: Option<&str>) -> Option<&PoolInfo> {
+        self.pools_by_pair.get(&pair_key(mint_a, mint_b))?
+            .iter()
+            .map(|x| &self.pools[*x])
+            .find(|x| is_market(x, market))
+    }
+
+    //pool_list的数据，market为None时返回全部市场
+    pub fn raw_pools(&self, market: Option<&str>) -> Vec<RawPool> {
+        self.raw_pools.iter()
+            .filter(|x| market.map_or(true, |m| x.market.to_lowercase().eq(m)))
+            .cloned()
+            .collect()
+    }
+
+    pub fn token_list(&self, chain: &str) -> Vec<RawTokenAddr> {
+        self.token_lists.get(chain).cloned().unwrap_or_default()
+    }
+}
+
+fn pair_key(mint_a: &str, mint_b: &str) -> (String, String) {
+    if mint_a <= mint_b {
+        (mint_a.to_string(), mint_b.to_string())
+    } else {
+        (mint_b.to_string(), mint_a.to_string())
+    }
+}
+
+fn is_market(pool: &PoolInfo, market: Option<&str>) -> bool {
+    market.map_or(true, |m| pool.market_type.get_name().0.to_lowercase().eq(m))
+}
+
+//放在rocket managed state里共享，重新加载时整体替换，正在处理的请求继续使用旧数据
+pub struct Registry {
+    data: RwLock<Arc<RegistryData>>,
+    modified: Mutex<Vec<Option<SystemTime>>>,
+}
+
+impl Registry {
+    pub fn load() -> Result<Self> {
+        let modified = files_modified();
+        let data = RegistryData::load()?;
+        Ok(Registry {
+            data: RwLock::new(Arc::new(data)),
+            modified: Mutex::new(modified),
+        })
+    }
+
+    pub fn data(&self) -> Arc<RegistryData> {
+        self.data.read().unwrap().clone()
+    }
+
+    //重新解析全部文件，失败时保留原来的数据
+    pub fn reload(&self) -> Result<Arc<RegistryData>> {
+        let modified = files_modified();
+        let data = Arc::new(RegistryData::load()?);
+        *self.data.write().unwrap() = data.clone();
+        *self.modified.lock().unwrap() = modified;
+        Ok(data)
+    }
+
+    //文件修改时间有变化才重新加载
+    pub fn reload_if_changed(&self) -> Result<bool> {
+        if files_modified() == *self.modified.lock().unwrap() {
+            return Ok(false);
+        }
+        self.reload()?;
+        Ok(true)
+    }
+
+    //后台任务定期检查文件变化，解析文件在阻塞线程池里执行，不占用异步工作线程。需要在tokio运行时里调用
+    pub fn watch(registry: Arc<Registry>) {
+        rocket::tokio::spawn(async move {
+            let mut interval = rocket::tokio::time::interval(Duration::from_secs(WATCH_INTERVAL_SECS));
+            //第一次tick立即返回，启动时刚加载过
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let registry = registry.clone();
+                match rocket::tokio::task::spawn_blocking(move || registry.reload_if_changed()).await {
+                    Ok(Ok(true)) => info!("registry reloaded"),
+                    Ok(Ok(false)) => {}
+                    Ok(Err(e)) => error!("registry reload fail: {}", e),
+                    Err(e) => error!("registry reload task fail: {}", e),
+                }
+            }
+        });
+    }
+}
+
+//token和farm文件加上market::dex里各市场的池子文件，任意一个文件的修改时间变化都会整体重新加载
+fn watched_files() -> Vec<String> {
+    let mut files = vec![TOKEN_MINT_PATH.to_string(), FARM_PATH.to_string()];
+    files.extend(TOKEN_LIST_CHAINS.iter().map(|x| token_list_path(x)));
+    for path in DEXES.iter().flat_map(|x| x.pool_files.iter()) {
+        if !files.iter().any(|x| x == path) {
+            files.push(path.to_string());
+        }
+    }
+    files
+}
+
+fn token_list_path(chain: &str) -> String {
+    format!("./resource/token/{}.json", chain)
+}
+
+fn files_modified() -> Vec<Option<SystemTime>> {
+    watched_files().iter()
+        .map(|x| fs::metadata(x).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+//管理接口的请求头校验
+pub struct AdminToken;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminToken {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let expected = match env::var(ADMIN_TOKEN_ENV) {
+            Ok(token) if !token.is_empty() => token,
+            _ => return Outcome::Error((Status::Forbidden, ())),
+        };
+        match request.headers().get_one(ADMIN_TOKEN_HEADER) {
+            Some(token) if token == expected => Outcome::Success(AdminToken),
+            _ => Outcome::Error((Status::Unauthorized, ())),
+        }
+    }
+}
@@ -5,12 +5,13 @@ use std::collections::HashMap;
 use api::RawTokenAddr;
 use bytemuck::__core::cmp::Ordering;
 use rust_decimal::Decimal;
-use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
-use bytemuck::__core::ops::Div;
+use rust_decimal::prelude::{FromStr, ToPrimitive};
 use market::pool::RawPool;
-use spl_token_swap::curve::base::SwapCurve;
-use spl_token_swap::curve::stable::StableCurve;
-use spl_token_swap::curve::calculator::{TradeDirection, CurveCalculator};
+
+//滑点按百万分之一精度换算成整数
+const SLIPPAGE_SCALE: u128 = 1_000_000;
+//Decimal最多支持28位小数
+const MAX_DECIMAL_SCALE: u32 = 28;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct OptResponse {
@@ -19,6 +20,23 @@ pub struct OptResponse {
     pub data: Vec<OptRank>,
 }
 
+//base64编码的未签名交易
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BuildSwapResponse {
+    pub code: u32,
+    pub msg: String,
+    pub transaction: Option<String>,
+}
+
+//重新加载后的token和池子数量
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReloadResponse {
+    pub code: u32,
+    pub msg: String,
+    pub tokens: usize,
+    pub pools: usize,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct TokenListResponse {
     pub total: u32,
@@ -35,32 +53,91 @@ pub struct PoolListResponse {
     pub data: Vec<RawPool>,
 }
 
+//ExactIn指定输入数量，ExactOut指定输出数量
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+pub enum SwapMode {
+    ExactIn,
+    ExactOut,
+}
+
 #[derive(Debug, Serialize, PartialEq, Deserialize)]
 pub struct OptRank {
+    pub swap_mode: SwapMode,
+    pub amount_in: f64,
+    pub amount_in_raw: u64,
     pub amount_out: f64,
+    pub amount_out_raw: u64,
+    //ExactOut时考虑滑点后最多付出的数量
+    pub max_amount_in: Option<f64>,
+    pub max_amount_in_raw: Option<u64>,
     pub quote_mint: String,
     pub base_mint: String,
     pub slippage: f32,
     pub opt: Vec<OptMarket>,
+    //候选路径里因链上状态无法swap而被剔除的池子
+    #[serde(default)]
+    pub excluded: Vec<ExcludedPool>,
+    //报价使用的账户数据中最早的slot
+    #[serde(default)]
+    pub slot: u64,
+    //最优拆单结果，只在排序前使用
+    #[serde(skip)]
+    pub split: Vec<OptMarket>,
+    //拆单失败的原因，这时只返回单条路径的方案
+    #[serde(default)]
+    pub split_error: Option<String>,
+    #[serde(flatten)]
+    pub summary: QuoteSummary,
+}
+
+//确认页展示的成交明细，在opt_best里按最终方案计算
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Default)]
+#[serde(default)]
+pub struct QuoteSummary {
+    //不含滑点的预期产出
+    pub expected_out: f64,
+    pub expected_out_raw: u64,
+    //扣除滑点后的最少产出，也是交易里最后一跳的minimum_amount_out。ExactOut时就是指定的输出
+    pub min_out: f64,
+    pub min_out_raw: u64,
+    //1个输出token花费多少输入token，包含手续费
+    pub effective_price: f64,
+    //相对中间价少得到的百分比，包含手续费。有路径拿不到中间价时为None
+    pub price_impact_pct: Option<f64>,
+    //各跳手续费按成交比例折算成输入token后的总和
+    pub total_fee: f64,
+    pub total_fee_raw: u64,
+    //方案里所有路径都能组装成交易，serum订单簿路径目前不能
+    pub buildable: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 pub struct OptMarket {
     pub market: String,
     pub program_id: String,
+    //*_raw是链上最小单位，不带_raw的是按精度换算后的数量
+    pub amount_in: f64,
+    pub amount_in_raw: u64,
     pub amount_out: f64,
+    pub amount_out_raw: u64,
     pub percentage: f32,
+    //不计手续费和价格冲击时1个输入能换多少输出，用来计算价格冲击
+    #[serde(default)]
+    pub mid_rate: Option<f64>,
+    //每一跳都有对应的swap指令时才能用build_swap组装成交易
+    #[serde(default)]
+    pub buildable: bool,
     pub routes: Vec<OptRoute>,
 }
 
 impl OptMarket {
-    pub fn set_info(&mut self, market: String, program_id: String) {
-        self.market = market;
-        self.program_id = program_id;
-    }
-
-    pub fn get_amount(&self) -> f64 {
-        self.amount_out
+    //每一跳的手续费按路径输入和这一跳输入的比例折算成输入token
+    pub fn fee_in_source(&self) -> u64 {
+        self.routes.iter()
+            .filter(|x| x.source_amount_raw > 0)
+            .map(|x| x.fee.source_fee_raw as u128 * self.amount_in_raw as u128 / x.source_amount_raw as u128)
+            .sum::<u128>()
+            .min(u64::MAX as u128) as u64
     }
 }
 
@@ -68,192 +145,181 @@ impl OptMarket {
 pub struct OptRoute {
     //pool_key或market_key
     pub route_key: String,
+    //这一跳所在的市场
+    pub market: String,
+    pub program_id: String,
 
     pub source_amount: f64,
+    pub source_amount_raw: u64,
     pub source_name: String,
     pub source_mint: String,
     pub source_decimals: u8,
 
     pub destination_amount: f64,
+    pub destination_amount_raw: u64,
     pub destination_name: String,
     pub destination_mint: String,
     pub destination_decimals: u8,
 
     pub source_value: u64,
     pub destination_value: u64,
+    //1减去名义费率，各市场含义一致
     pub fee_factor: f64,
+    #[serde(default)]
+    pub fee: RouteFee,
     pub amp: Option<u64>,
     pub data: HashMap<String, String>,
 
 }
 
+//一跳的手续费，同时按源token和目标token给出。实际收取的一边是链上的精确值，另一边按这一跳的成交价折算
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Default)]
+pub struct RouteFee {
+    //名义费率，orca包含owner费率
+    pub fee_rate: f64,
+    //实际收取手续费的token
+    pub fee_mint: String,
+    pub source_fee: f64,
+    pub source_fee_raw: u64,
+    pub destination_fee: f64,
+    pub destination_fee_raw: u64,
+    //其中归协议的部分(orca owner fee、saber admin fee、raydium pnl)，单位是fee_mint
+    pub protocol_fee_raw: u64,
+}
+
+//被剔除的池子和原因，例如raydium池子不是Initialized状态、saber池子被暂停
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct ExcludedPool {
+    pub pool_key: String,
+    pub market: String,
+    pub reason: String,
+}
+
 impl PartialOrd for OptMarket {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Option::from(self.amount_out.total_cmp(&other.amount_out))
+        Option::from(self.amount_out_raw.cmp(&other.amount_out_raw))
     }
 }
 
 impl OptRank {
     pub fn opt_best(&mut self) -> Result<Vec<Self>> {
+        if self.swap_mode == SwapMode::ExactOut {
+            return Ok(self.opt_best_exact_out());
+        }
+
         //按amount排序
         self.opt.sort_by(|a, b| b.partial_cmp(&a).unwrap());
 
-        let mut opts = vec![];
-        let mut manage_opt = HashMap::new();
+        if self.opt.is_empty() {
+            return Ok(vec![]);
+        }
 
-        for opt in self.opt.iter() {
-            let opt_clone = opt.clone();
+        //opt里是OptInitData::quote按各跳所在市场的Amm adapter对全额输入的精确报价，排第一的直接作为100%走单条路径的方案
+        let mut opt_res = vec![self.exact_in_rank(vec![self.opt[0].clone()], self.split_error.clone())];
 
-            if !manage_opt.contains_key(&opt.market) {
-                manage_opt.insert(&opt.market, 0);
-                opts.push(opt_clone);
-            }
+        //拆到两条及以上路径才作为单独的方案
+        if self.split.len() > 1 {
+            opt_res.push(self.exact_in_rank(self.split.clone(), None));
         }
 
-        //计算一个单独的
-        let mut opt_res = vec![];
-        if !opts.is_empty() {
-            let one_step_best = self.cal_one_best_market_amount_out(opts[0].clone());
-            opt_res.push(one_step_best);
+        for rank in opt_res.iter_mut() {
+            rank.summarize();
         }
+        opt_res.sort_by(|a, b| b.partial_cmp(&a).unwrap());
+        Ok(opt_res)
+    }
 
+    //指定输出时按所需输入从少到多排序，取最省的一条路径
+    fn opt_best_exact_out(&mut self) -> Vec<Self> {
+        self.opt.sort_by(|a, b| a.amount_in_raw.cmp(&b.amount_in_raw));
 
-        if opts.is_empty() {
-            Ok(vec![])
-        } else if opts.len() == 1 {
-            Ok(opt_res)
-        } else {
-            // if opts.len() <= 1 {
-            //     opt_res.push(OptRank {
-            //         amount_out: opts[0].amount_out + opts[1].amount_out,
-            //         quote_mint: self.quote_mint.to_string(),
-            //         base_mint: self.base_mint.to_string(),
-            //         slippage: self.slippage,
-            //         opt: opts,
-            //     });
-            // }
-
-            opt_res.push(OptRank {
-                amount_out: opts[0].amount_out + opts[1].amount_out,
-                quote_mint: self.quote_mint.to_string(),
-                base_mint: self.base_mint.to_string(),
-                slippage: self.slippage,
-                opt: opts,
-            });
-
-            opt_res.sort_by(|a, b| b.partial_cmp(&a).unwrap());
-            Ok(opt_res)
+        if self.opt.is_empty() {
+            return vec![];
         }
-    }
 
-    fn cal_one_best_market_amount_out(&self, mut opt: OptMarket) -> OptRank {
-        let mut amount_in = opt.routes[0].source_amount * 2.0;
-
-        let mut to_amount: f64 = 0.0;
-
-        for mut step in &mut opt.routes {
-            let basic: i128 = 10;
-            let quote_pow = basic.pow(step.source_decimals as u32);
-            let base_pow = basic.pow(step.destination_decimals as u32);
-            let quote_amount = step.source_value;
-            let base_amount = step.destination_value;
-            if self.quote_mint.eq(&step.source_mint) || self.base_mint.eq(&step.destination_mint) {
-                //pc to coin
-                let from_amount = amount_in * (quote_pow as f64);
-                let mut amount_out_format;
-
-                if opt.market.eq("Raydium") {
-                    let from_amount_with_fee = from_amount * step.fee_factor;
-                    let denominator = quote_amount as f64 + from_amount_with_fee;
-                    let amount_out = base_amount as f64 * from_amount_with_fee / denominator;
-                    amount_out_format = Decimal::from_f64(amount_out / (base_pow as f64)).unwrap().div(Decimal::from_f32(1 as f32 + self.slippage as f32 / 100 as f32).unwrap());
-                } else {
-                    let from_amount_with_fee = from_amount - from_amount * step.fee_factor;
-                    match step.amp {
-                        Some(amp) => {
-                            let sc = StableCurve {
-                                amp
-                            };
-                            let sc_result = sc.swap_without_fees(from_amount_with_fee.to_u128().unwrap(),
-                                                                 quote_amount as u128,
-                                                                 base_amount as u128,
-                                                                 TradeDirection::AtoB).unwrap();
-                            let amount_out = Decimal::from_u128(sc_result.destination_amount_swapped).unwrap();
-                            amount_out_format = amount_out.div(Decimal::from(base_pow)).div(Decimal::from_f32(1 as f32 + self.slippage as f32 / 100 as f32).unwrap());
-                        }
-                        None => {
-                            let sc = SwapCurve::default();
-                            let sc_result = sc.calculator.swap_without_fees(from_amount_with_fee.to_u128().unwrap(),
-                                                                            quote_amount as u128,
-                                                                            base_amount as u128,
-                                                                            TradeDirection::AtoB).unwrap();
-                            let amount_out = Decimal::from_u128(sc_result.destination_amount_swapped).unwrap();
-                            amount_out_format = amount_out.div(Decimal::from(base_pow)).div(Decimal::from_f32(1 as f32 + self.slippage as f32 / 100 as f32).unwrap());
-                        }
-                    };
-                }
-
-                amount_out_format.rescale(step.destination_decimals as u32);
-                step.source_amount = amount_in.clone();
-                step.destination_amount = amount_out_format.to_f64().unwrap();
-                amount_in = amount_out_format.to_f64().unwrap();
-                to_amount = amount_in.clone();
-            } else {
-                //coin to pc
-                let from_amount = amount_in * (base_pow as f64);
-                let mut amount_out_format;
-
-                if opt.market.eq("Raydium") {
-                    let from_amount_with_fee = from_amount * step.fee_factor;
-                    let denominator = quote_amount as f64 + from_amount_with_fee;
-                    let amount_out = base_amount as f64 * from_amount_with_fee / denominator;
-                    amount_out_format = Decimal::from_f64(amount_out / (quote_pow as f64)).unwrap().div(Decimal::from_f32(1 as f32 + self.slippage as f32 / 100 as f32).unwrap());
-                } else {
-                    let from_amount_with_fee = from_amount - from_amount * step.fee_factor;
-                    match step.amp {
-                        Some(amp) => {
-                            let sc = StableCurve {
-                                amp
-                            };
-                            let sc_result = sc.swap_without_fees(from_amount_with_fee.to_u128().unwrap(),
-                                                                 base_amount as u128,
-                                                                 quote_amount as u128,
-                                                                 TradeDirection::BtoA).unwrap();
-                            let amount_out = Decimal::from_u128(sc_result.destination_amount_swapped).unwrap();
-                            amount_out_format = amount_out.div(Decimal::from(quote_pow)).div(Decimal::from_f32(1 as f32 + self.slippage as f32 / 100 as f32).unwrap());
-                        }
-                        None => {
-                            let sc = SwapCurve::default();
-                            let sc_result = sc.calculator.swap_without_fees(from_amount_with_fee.to_u128().unwrap(),
-                                                                            base_amount as u128,
-                                                                            quote_amount as u128,
-                                                                            TradeDirection::BtoA).unwrap();
-                            let amount_out = Decimal::from_u128(sc_result.destination_amount_swapped).unwrap();
-                            amount_out_format = amount_out.div(Decimal::from(quote_pow)).div(Decimal::from_f32(1 as f32 + self.slippage as f32 / 100 as f32).unwrap());
-                        }
-                    };
-                }
-
-                amount_out_format.rescale(step.destination_decimals as u32);
-                step.source_amount = amount_in.clone();
-                step.destination_amount = amount_out_format.to_f64().unwrap();
-                amount_in = amount_out_format.to_f64().unwrap();
-                to_amount = amount_in.clone();
-            }
+        let best = self.opt[0].clone();
+        let max_amount_in_raw = add_slippage(best.amount_in_raw, self.slippage);
+
+        let mut rank = OptRank {
+            swap_mode: SwapMode::ExactOut,
+            amount_in: best.amount_in,
+            amount_in_raw: best.amount_in_raw,
+            amount_out: self.amount_out,
+            amount_out_raw: self.amount_out_raw,
+            max_amount_in: Some(ui_amount(max_amount_in_raw, best.routes[0].source_decimals)),
+            max_amount_in_raw: Some(max_amount_in_raw),
+            quote_mint: self.quote_mint.to_string(),
+            base_mint: self.base_mint.to_string(),
+            slippage: self.slippage,
+            opt: vec![best],
+            excluded: self.excluded.clone(),
+            slot: self.slot,
+            split: vec![],
+            split_error: None,
+            summary: QuoteSummary::default(),
         };
+        rank.summarize();
+        vec![rank]
+    }
 
-        //手动100%
-        opt.percentage = 1.0;
-        opt.amount_out = to_amount;
-
+    //按指定输入走opt里各路径的方案，产出是各路径之和
+    fn exact_in_rank(&self, opt: Vec<OptMarket>, split_error: Option<String>) -> OptRank {
+        let amount_out_raw = opt.iter().map(|x| x.amount_out_raw).sum();
+        let decimals = opt[0].routes[opt[0].routes.len() - 1].destination_decimals;
         OptRank {
-            amount_out: to_amount,
+            swap_mode: SwapMode::ExactIn,
+            amount_in: self.amount_in,
+            amount_in_raw: self.amount_in_raw,
+            amount_out: ui_amount(amount_out_raw, decimals),
+            amount_out_raw,
+            max_amount_in: None,
+            max_amount_in_raw: None,
             quote_mint: self.quote_mint.to_string(),
             base_mint: self.base_mint.to_string(),
             slippage: self.slippage,
-            opt: vec![opt],
+            opt,
+            excluded: self.excluded.clone(),
+            slot: self.slot,
+            split: vec![],
+            split_error,
+            summary: QuoteSummary::default(),
         }
     }
+
+    //按最终方案汇总预期产出、最少产出、成交价、价格冲击和手续费
+    fn summarize(&mut self) {
+        if self.opt.is_empty() {
+            return;
+        }
+        let source_decimals = self.opt[0].routes[0].source_decimals;
+        let destination_decimals = {
+            let routes = &self.opt[0].routes;
+            routes[routes.len() - 1].destination_decimals
+        };
+
+        let expected_out_raw: u64 = self.opt.iter().map(|x| x.amount_out_raw).sum();
+        let min_out_raw = match self.swap_mode {
+            SwapMode::ExactIn => self.opt.iter().map(|x| apply_slippage(x.amount_out_raw, self.slippage)).sum(),
+            SwapMode::ExactOut => self.amount_out_raw,
+        };
+        let amount_in: f64 = self.opt.iter().map(|x| x.amount_in).sum();
+        let expected_out = ui_amount(expected_out_raw, destination_decimals);
+        //中间价下应得的产出，各路径按自己的输入计算
+        let mid_out: Option<f64> = self.opt.iter().map(|x| x.mid_rate.map(|rate| x.amount_in * rate)).sum();
+        let total_fee_raw = self.opt.iter().map(|x| x.fee_in_source()).sum();
+
+        self.summary = QuoteSummary {
+            expected_out,
+            expected_out_raw,
+            min_out: ui_amount(min_out_raw, destination_decimals),
+            min_out_raw,
+            effective_price: if expected_out > 0.0 { amount_in / expected_out } else { 0.0 },
+            price_impact_pct: mid_out.filter(|x| *x > 0.0).map(|x| (1.0 - expected_out / x) * 100.0),
+            total_fee: ui_amount(total_fee_raw, source_decimals),
+            total_fee_raw,
+            buildable: self.opt.iter().all(|x| x.buildable),
+        };
+    }
 }
 
 impl PartialOrd for OptRank {
@@ -262,8 +328,52 @@ impl PartialOrd for OptRank {
     }
 }
 
+//最小单位换算成带精度的数量。精度超过Decimal支持的28位时，多出的部分用f64再除
+pub fn ui_amount(raw: u64, decimals: u8) -> f64 {
+    let scale = (decimals as u32).min(MAX_DECIMAL_SCALE);
+    let amount = Decimal::from_i128_with_scale(raw as i128, scale).to_f64().unwrap_or_default();
+    amount / 10f64.powi((decimals as u32 - scale) as i32)
+}
 
+//带精度的数量换算成最小单位，多出的精度向下取整。
+//先转成最短的十进制字符串，避免0.1这类f64的二进制误差。超出u64范围时返回0
+pub fn raw_amount(ui: f64, decimals: u8) -> u64 {
+    let amount = Decimal::from_str(&ui.to_string()).unwrap_or_default();
+    (0..decimals).try_fold(amount, |x, _| x.checked_mul(Decimal::from(10u8)))
+        .and_then(|x| x.floor().to_u64())
+        .unwrap_or(0)
+}
 
+//扣除滑点后的最少产出，即乘以(1 - slippage / 100)，向下取整
+pub fn apply_slippage(raw: u64, slippage: f32) -> u64 {
+    let slippage = (slippage as f64 * SLIPPAGE_SCALE as f64 / 100.0).round() as u128;
+    (raw as u128 * SLIPPAGE_SCALE.saturating_sub(slippage) / SLIPPAGE_SCALE) as u64
+}
 
+//加上滑点后的最多输入，向上取整
+pub fn add_slippage(raw: u64, slippage: f32) -> u64 {
+    let slippage = (slippage as f64 * SLIPPAGE_SCALE as f64 / 100.0).round() as u128;
+    ((raw as u128 * (SLIPPAGE_SCALE + slippage) + SLIPPAGE_SCALE - 1) / SLIPPAGE_SCALE) as u64
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_slippage() {
+        assert_eq!(apply_slippage(1_000_000, 1.0), 990_000);
+        assert_eq!(apply_slippage(1_000_000, 0.5), 995_000);
+        assert_eq!(apply_slippage(1_000_000, 0.0), 1_000_000);
+        //向下取整
+        assert_eq!(apply_slippage(999, 1.0), 989);
+        assert_eq!(apply_slippage(u64::MAX, 0.0), u64::MAX);
+    }
 
+    #[test]
+    fn test_add_slippage() {
+        assert_eq!(add_slippage(1_000_000, 1.0), 1_010_000);
+        //向上取整
+        assert_eq!(add_slippage(999, 1.0), 1_009);
+    }
+}
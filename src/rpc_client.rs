@@ -1,21 +1,67 @@
 use std::collections::HashMap;
+use std::future::Future;
+use std::time::Duration;
 use serde::{Serialize, Deserialize};
-use std::thread;
-use std::sync::mpsc;
-use safe_transmute::alloc::sync::Arc;
-use crate::pool::pool;
-use crate::api;
-use pool::load_pool_farm_data_from_file;
-use api::load_token_data_from_file;
+use futures::stream::{self, StreamExt};
+use rocket::tokio::time::{sleep, timeout};
+use rust_decimal::prelude::FromStr;
+use serde_json::json;
+use solana_program::{program_pack::Pack, pubkey::Pubkey, system_program};
+use solana_sdk::{account::Account, commitment_config::CommitmentConfig, signature::Signature};
+use solana_client::rpc_client::GetConfirmedSignaturesForAddress2Config;
+use solana_client::rpc_config::RpcTransactionConfig;
+use solana_client::rpc_request::RpcRequest;
+use solana_client::rpc_response::{Response as RpcResponse, RpcConfirmedTransactionStatusWithSignature, RpcKeyedAccount};
+use spl_token::state::{Account as TokenAccount, Mint};
+use market::pool::PoolInfo;
+use solana_transaction_status::{EncodedConfirmedTransactionWithStatusMeta, EncodedTransaction, UiInstruction,
+                                UiMessage, UiParsedInstruction, UiTransactionEncoding};
+use crate::error::{ApiError, ApiResult};
+use crate::node_client::RpcPool;
+use crate::registry::RegistryData;
+use crate::account_cache::{AccountCache, fetch_accounts};
+use crate::pool::pool::{pool_account_keys, pool_reserves, pool_lp_supply};
+use crate::response::ui_amount;
+use crate::price::{TokenPrice, price_tokens};
 
 const SOLSCAN_TRANSACTION_URL: &str = "https://public-api.solscan.io/account/transactions?account=";
 const SOLSCAN_DETAIL_URL: &str = "https://public-api.solscan.io/transaction/";
-const SOLSCAN_ASSET_URL: &str = "https://api.solscan.io/account/tokens?price=1&address=";
+const TOKEN_2022_PROGRAM_ID: &str = "TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb";
+//同时请求交易详情的数量
+const MAX_DETAIL_REQUESTS: usize = 8;
+//交易列表和单笔详情请求的超时和失败后的重试次数，重试间隔按次数递增
+const REQUEST_TIMEOUT_SECS: u64 = 10;
+const REQUEST_RETRIES: u32 = 2;
+const RETRY_DELAY_MILLIS: u64 = 300;
+//每页交易数，solscan最多50
+const DEFAULT_HISTORY_LIMIT: u32 = 10;
+const MAX_HISTORY_LIMIT: u32 = 50;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AccountRequest {
     pub address: String,
+    //上一页最后一笔交易的签名
     pub before: Option<String>,
+    pub limit: Option<u32>,
+}
+
+//交易记录的来源，rpc时直接从节点的getSignaturesForAddress/getTransaction解析
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum HistorySource {
+    Solscan,
+    Rpc,
+}
+
+impl FromStr for HistorySource {
+    type Err = ApiError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "solscan" => Ok(HistorySource::Solscan),
+            "rpc" => Ok(HistorySource::Rpc),
+            _ => Err(ApiError::InvalidRequest(format!("unknown history source {}", s))),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -32,12 +78,140 @@ pub struct TxResponse {
     #[serde(rename = "parsedInstruction")]
     parsed_instruction: Vec<HashMap<String, String>>,
     detail: Option<TxDetail>,
+    //详情重试后仍然失败的原因，只影响这一笔
+    #[serde(rename = "detailError", default, skip_serializing_if = "Option::is_none")]
+    detail_error: Option<String>,
 }
 
 impl TxResponse {
-    pub fn set_detail(&mut self, detail: Option<TxDetail>) {
-        self.detail = detail;
+    pub fn set_detail(&mut self, detail: Result<TxDetail, String>) {
+        match detail {
+            Ok(detail) => {
+                self.detail = Some(detail);
+                self.detail_error = None;
+            }
+            Err(e) => {
+                self.detail = None;
+                self.detail_error = Some(e);
+            }
+        }
     }
+
+    //只有签名列表里的信息，详情获取失败时使用
+    fn from_signature(status: &RpcConfirmedTransactionStatusWithSignature) -> Self {
+        TxResponse {
+            block_time: status.block_time.unwrap_or_default().max(0) as u64,
+            slot: status.slot,
+            tx_hash: status.signature.clone(),
+            fee: 0,
+            status: tx_status(status.err.is_none()),
+            lamport: 0,
+            signer: vec![],
+            parsed_instruction: vec![],
+            detail: None,
+            detail_error: None,
+        }
+    }
+
+    //按solscan的字段从jsonParsed格式的交易里取值
+    fn from_transaction(tx_hash: &str, tx: EncodedConfirmedTransactionWithStatusMeta) -> Result<Self, String> {
+        let message = match tx.transaction.transaction {
+            EncodedTransaction::Json(ui_transaction) => match ui_transaction.message {
+                UiMessage::Parsed(message) => message,
+                UiMessage::Raw(_) => return Err("transaction not json parsed".to_string()),
+            },
+            _ => return Err("transaction not json encoded".to_string()),
+        };
+        let meta = tx.transaction.meta.ok_or_else(|| "transaction meta missing".to_string())?;
+
+        let block_time = tx.block_time.unwrap_or_default().max(0) as u64;
+        let status = tx_status(meta.err.is_none());
+        let signer: Vec<String> = message.account_keys.iter().filter(|x| x.signer).map(|x| x.pubkey.clone()).collect();
+        //fee payer的余额变化
+        let lamport = match (meta.pre_balances.first(), meta.post_balances.first()) {
+            (Some(pre), Some(post)) => pre.abs_diff(*post),
+            _ => 0,
+        };
+
+        let parsed_instruction = message.instructions.iter()
+            .map(|instruction| {
+                let mut map = HashMap::new();
+                match instruction {
+                    UiInstruction::Parsed(UiParsedInstruction::Parsed(parsed)) => {
+                        let instruction_type = parsed.parsed.get("type").and_then(|x| x.as_str()).unwrap_or(&parsed.program);
+                        map.insert("programId".to_string(), parsed.program_id.clone());
+                        map.insert("type".to_string(), instruction_type.to_string());
+                    }
+                    UiInstruction::Parsed(UiParsedInstruction::PartiallyDecoded(decoded)) => {
+                        map.insert("programId".to_string(), decoded.program_id.clone());
+                        map.insert("type".to_string(), "unknown".to_string());
+                    }
+                    UiInstruction::Compiled(_) => {}
+                }
+                map
+            })
+            .filter(|x| !x.is_empty())
+            .collect();
+
+        let input_account = message.account_keys.iter().enumerate()
+            .map(|(index, x)| TxInputAccount {
+                account: x.pubkey.clone(),
+                signer: x.signer,
+                writable: x.writable,
+                pre_balance: meta.pre_balances.get(index).cloned().unwrap_or_default(),
+                post_balance: meta.post_balances.get(index).cloned().unwrap_or_default(),
+            })
+            .collect();
+
+        let detail = TxDetail {
+            block_time,
+            slot: tx.slot,
+            tx_hash: tx_hash.to_string(),
+            fee: meta.fee as u32,
+            status: status.clone(),
+            lamport,
+            signer: signer.clone(),
+            log_message: meta.log_messages.unwrap_or_default(),
+            input_account,
+            recent_blockhash: message.recent_blockhash,
+        };
+
+        Ok(TxResponse {
+            block_time,
+            slot: tx.slot,
+            tx_hash: tx_hash.to_string(),
+            fee: meta.fee as u32,
+            status,
+            lamport,
+            signer,
+            parsed_instruction,
+            detail: Some(detail),
+            detail_error: None,
+        })
+    }
+}
+
+//和solscan返回的状态一致
+fn tx_status(success: bool) -> String {
+    if success { "Success".to_string() } else { "Fail".to_string() }
+}
+
+//单个请求超时或失败时重试，全部失败返回最后一次的原因
+async fn with_retry<T, F, Fut>(f: F) -> Result<T, String>
+    where F: Fn() -> Fut,
+          Fut: Future<Output = Result<T, String>> {
+    let mut last_error = String::new();
+    for attempt in 0..=REQUEST_RETRIES {
+        if attempt > 0 {
+            sleep(Duration::from_millis(RETRY_DELAY_MILLIS * attempt as u64)).await;
+        }
+        match timeout(Duration::from_secs(REQUEST_TIMEOUT_SECS), f()).await {
+            Ok(Ok(value)) => return Ok(value),
+            Ok(Err(e)) => last_error = e,
+            Err(_) => last_error = format!("timeout after {}s", REQUEST_TIMEOUT_SECS),
+        }
+    }
+    Err(last_error)
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -96,7 +270,49 @@ pub struct AssetResponse {
     token_symbol: Option<String>,
     #[serde(rename = "priceUsdt")]
     price_usdt: Option<f64>,
+    #[serde(rename = "tokenProgram", default)]
+    token_program: String,
+    //lp token按池子储备和lp总量折算出的底层资产
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    underlying: Option<Vec<UnderlyingAsset>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PortfolioResponse {
+    pub address: String,
+    //所有能定价的资产价值之和，单位USDC
+    #[serde(rename = "totalValue")]
+    pub total_value: f64,
+    //有余额但找不到满足深度要求的定价路径的资产数，不计入总价值
+    pub unpriced: usize,
+    pub assets: Vec<AssetValue>,
+}
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AssetValue {
+    #[serde(rename = "tokenAddress")]
+    token_address: String,
+    #[serde(rename = "tokenAccount")]
+    token_account: String,
+    #[serde(rename = "tokenSymbol")]
+    token_symbol: Option<String>,
+    amount: f64,
+    price: Option<f64>,
+    value: Option<f64>,
+    //定价路径经过的市场，lp token为lp:市场名
+    source: Option<String>,
+    confidence: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UnderlyingAsset {
+    mint: String,
+    symbol: Option<String>,
+    market: String,
+    #[serde(rename = "poolAccount")]
+    pool_account: String,
+    #[serde(rename = "tokenAmount")]
+    token_amount: TokenAmount,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -110,8 +326,27 @@ pub struct TokenAmount {
 }
 
 impl AccountRequest {
-    pub fn get_history(&self) -> Vec<TxResponse> {
+    fn history_limit(&self) -> ApiResult<u32> {
+        match self.limit {
+            Some(limit) if limit == 0 || limit > MAX_HISTORY_LIMIT => {
+                Err(ApiError::BadPagination(format!("limit must be between 1 and {}", MAX_HISTORY_LIMIT)))
+            }
+            Some(limit) => Ok(limit),
+            None => Ok(DEFAULT_HISTORY_LIMIT),
+        }
+    }
+
+    pub async fn get_history(&self, source: HistorySource, rpc: &RpcPool) -> ApiResult<Vec<TxResponse>> {
+        match source {
+            HistorySource::Solscan => self.get_history_from_solscan().await,
+            HistorySource::Rpc => self.get_history_from_rpc(rpc).await,
+        }
+    }
+
+    async fn get_history_from_solscan(&self) -> ApiResult<Vec<TxResponse>> {
+        let limit = self.history_limit()?;
         let mut transaction_url: String = SOLSCAN_TRANSACTION_URL.to_owned() + &self.address.clone();
+        transaction_url.push_str(&format!("&limit={}", limit));
 
         match &self.before {
             Some(s) => {
@@ -120,80 +355,371 @@ impl AccountRequest {
             }
             None => {}
         }
-        let res = reqwest::blocking::get(transaction_url).unwrap();
-        let mut tx_res = res.json::<Vec<TxResponse>>().unwrap();
+        let transaction_url = &transaction_url;
+        let mut tx_res = with_retry(|| async move {
+            let res = reqwest::get(transaction_url.as_str()).await.map_err(|e| e.to_string())?;
+            res.json::<Vec<TxResponse>>().await.map_err(|e| e.to_string())
+        }).await.map_err(ApiError::Upstream)?;
+
+        //详情按交易顺序返回，同时最多MAX_DETAIL_REQUESTS个请求
+        let tx_hashes: Vec<String> = tx_res.iter().map(|x| x.tx_hash.clone()).collect();
+        let details: Vec<Result<TxDetail, String>> = stream::iter(tx_hashes)
+            .map(|tx_hash| async move {
+                let detail_url: String = SOLSCAN_DETAIL_URL.to_owned() + &*tx_hash;
+                let detail_url = &detail_url;
+                //单笔详情失败时不带detail返回，在detailError里说明原因
+                with_retry(|| async move {
+                    let res = reqwest::get(detail_url.as_str()).await.map_err(|e| e.to_string())?;
+                    res.json::<TxDetail>().await.map_err(|e| e.to_string())
+                }).await
+            })
+            .buffered(MAX_DETAIL_REQUESTS)
+            .collect()
+            .await;
+
+        for (tx, detail) in tx_res.iter_mut().zip(details) {
+            tx.set_detail(detail);
+        }
 
-        let (tx, rx) = mpsc::channel();
+        Ok(tx_res)
+    }
 
-        let arc_tx_res = Arc::new(tx_res.clone());
+    async fn get_history_from_rpc(&self, rpc: &RpcPool) -> ApiResult<Vec<TxResponse>> {
+        let limit = self.history_limit()?;
+        let address = Pubkey::from_str(&self.address).map_err(|_| ApiError::InvalidRequest(format!("invalid address {}", self.address)))?;
+        let before = match &self.before {
+            Some(before) => Some(Signature::from_str(before).map_err(|_| ApiError::InvalidRequest(format!("invalid signature {}", before)))?),
+            None => None,
+        };
+
+        //processed的交易查不到签名列表，至少要confirmed
+        let commitment = match rpc.commitment() {
+            x if x.is_finalized() => x,
+            _ => CommitmentConfig::confirmed(),
+        };
+        let signatures = rpc.call(|client| client.get_signatures_for_address_with_config(&address, GetConfirmedSignaturesForAddress2Config {
+            before,
+            until: None,
+            limit: Some(limit as usize),
+            commitment: Some(commitment),
+        })).await?;
+
+        let config = RpcTransactionConfig {
+            encoding: Some(UiTransactionEncoding::JsonParsed),
+            commitment: Some(commitment),
+            ..RpcTransactionConfig::default()
+        };
+        let tx_res: Vec<TxResponse> = stream::iter(signatures.iter())
+            .map(|status| async move {
+                let detail = with_retry(|| async move {
+                    let signature = Signature::from_str(&status.signature).map_err(|e| e.to_string())?;
+                    let tx = rpc.call(|client| client.get_transaction_with_config(&signature, config)).await.map_err(|e| e.to_string())?;
+                    TxResponse::from_transaction(&status.signature, tx)
+                }).await;
+                match detail {
+                    Ok(tx) => tx,
+                    Err(e) => {
+                        let mut tx = TxResponse::from_signature(status);
+                        tx.set_detail(Err(e));
+                        tx
+                    }
+                }
+            })
+            .buffered(MAX_DETAIL_REQUESTS)
+            .collect()
+            .await;
 
-        for i in 0..arc_tx_res.len() {
-            let tx_hash = arc_tx_res[i].tx_hash.clone();
-            let tx = tx.clone();
-            thread::spawn(move || {
-                let detail_url: String = SOLSCAN_DETAIL_URL.to_owned() + &*tx_hash;
-                let detail = reqwest::blocking::get(detail_url).unwrap();
-                let tx_detail = detail.json::<TxDetail>().unwrap();
-                let _send_result = tx.send((i, tx_detail));
-            });
-        }
+        Ok(tx_res)
+    }
 
-        for i in rx.recv() {
-            tx_res[i.0].set_detail(Option::from(i.1));
+    //资产列表，price_usdt由路由图按链上储备计算
+    pub async fn get_assets(&self, registry: &RegistryData, rpc: &RpcPool, cache: &AccountCache) -> ApiResult<Vec<AssetResponse>> {
+        let mut assets = self.load_assets(registry, rpc, cache).await?;
+        let prices = price_tokens(registry, cache, &asset_mints(&assets)).await?;
+        for asset in assets.iter_mut() {
+            asset.price_usdt = asset_value(asset, &prices).map(|x| x.price);
         }
+        Ok(assets)
+    }
 
-        tx_res
+    //每个资产的USDC价格和价值，以及总价值。lp token按底层资产计价
+    pub async fn get_portfolio(&self, registry: &RegistryData, rpc: &RpcPool, cache: &AccountCache) -> ApiResult<PortfolioResponse> {
+        let assets = self.load_assets(registry, rpc, cache).await?;
+        let prices = price_tokens(registry, cache, &asset_mints(&assets)).await?;
+
+        let mut values: Vec<AssetValue> = assets.iter()
+            .map(|asset| {
+                let valued = asset_value(asset, &prices);
+                AssetValue {
+                    token_address: asset.token_address.clone(),
+                    token_account: asset.token_account.clone(),
+                    token_symbol: asset.token_symbol.clone(),
+                    amount: asset.token_amount.ui_amount,
+                    price: valued.as_ref().map(|x| x.price),
+                    value: valued.as_ref().map(|x| x.value),
+                    source: valued.as_ref().map(|x| x.source.clone()),
+                    confidence: valued.as_ref().map(|x| x.confidence),
+                }
+            })
+            .collect();
+        values.sort_by(|a, b| b.value.unwrap_or_default().total_cmp(&a.value.unwrap_or_default()));
+
+        Ok(PortfolioResponse {
+            address: self.address.clone(),
+            total_value: values.iter().filter_map(|x| x.value).sum(),
+            unpriced: values.iter().filter(|x| x.value.is_none() && x.amount > 0.0).count(),
+            assets: values,
+        })
     }
 
-    pub fn get_assets(&self) -> Vec<AssetResponse> {
-        let asset_url: String = SOLSCAN_ASSET_URL.to_owned() + &self.address.clone();
+    //SOL余额加上spl-token和Token-2022的全部token账户，名称图标取自token列表，lp token折算成底层资产
+    async fn load_assets(&self, registry: &RegistryData, rpc: &RpcPool, cache: &AccountCache) -> ApiResult<Vec<AssetResponse>> {
+        let owner = Pubkey::from_str(&self.address).map_err(|_| ApiError::InvalidRequest(format!("invalid address {}", self.address)))?;
+
+        let balance = rpc.call(|client| client.get_balance_with_commitment(&owner, rpc.commitment())).await?.value;
+        let mut holdings = vec![TokenHolding {
+            account: owner,
+            mint: spl_token::native_mint::id(),
+            amount: balance,
+            program_id: system_program::id(),
+            lamports: balance,
+            rent_epoch: 0,
+        }];
+        holdings.extend(load_token_accounts(rpc, &owner, &spl_token::id()).await?);
+        //还没有部署Token-2022的集群上查询会报错，只跳过这部分
+        let token_2022 = Pubkey::from_str(TOKEN_2022_PROGRAM_ID).map_err(|e| ApiError::Internal(e.to_string()))?;
+        match load_token_accounts(rpc, &owner, &token_2022).await {
+            Ok(token_accounts) => holdings.extend(token_accounts),
+            Err(e) => println!("load token-2022 accounts fail: {}", e),
+        }
+
+        let decimals = load_decimals(registry, rpc, &holdings).await?;
+        let underlying = load_underlying(registry, cache, &holdings, &decimals).await?;
 
         //遇到此资产滤掉
-        let orca_farm_key = "Aquafarm".trim();
+        let orca_farm_key = "Aquafarm";
 
-        let client =reqwest::blocking::Client::new();
-        let res = client.get(&asset_url)
-            .header("User-Agent","PostmanRuntime/7.29.0")
-            .header("Host","api.solscan.io").send().unwrap();
-        let asset_res = res.json::<OutApiResponse<AssetResponse>>().unwrap();
+        let mut res = vec![];
+        for holding in holdings {
+            let mint = holding.mint.to_string();
+            //lp token在列表里没有时用对应farm token的信息
+            let token_info = registry.tokens.get(&mint)
+                .or_else(|| registry.lp_to_farm.get(&mint).and_then(|x| registry.tokens.get(x)));
+            if token_info.map_or(false, |x| x.description.contains(orca_farm_key)) {
+                //滤掉orca farm资产
+                continue;
+            }
 
-        //加载farm-pool对应关系
-        let farm_main_path = "./resource/farm/orca.json".to_string();
-        let pool_farm_map = load_pool_farm_data_from_file(&farm_main_path).unwrap();
+            res.push(AssetResponse {
+                token_address: mint.clone(),
+                token_amount: token_amount(holding.amount, decimals.get(&holding.mint).cloned().unwrap_or_default()),
+                token_account: holding.account.to_string(),
+                token_name: token_info.map(|x| x.description.clone()).unwrap_or_default(),
+                token_icon: token_info.map(|x| x.icon_uri.clone()).unwrap_or_default(),
+                rent_epoch: holding.rent_epoch,
+                lamports: holding.lamports,
+                token_symbol: token_info.map(|x| x.name.clone()),
+                price_usdt: None,
+                token_program: holding.program_id.to_string(),
+                underlying: underlying.get(&holding.account).cloned(),
+            });
+        }
 
-        //查询
-        let token_main_path = "./token_mint.json".to_string();
-        let tokens_adr = load_token_data_from_file(&token_main_path).expect("load token data fail");
+        Ok(res)
+    }
+}
 
-        let mut res = vec![];
+//资产和lp底层资产涉及的全部mint
+fn asset_mints(assets: &[AssetResponse]) -> Vec<Pubkey> {
+    let mut mints = vec![];
+    for asset in assets {
+        mints.push(asset.token_address.as_str());
+        for underlying in asset.underlying.iter().flatten() {
+            mints.push(underlying.mint.as_str());
+        }
+    }
+    mints.iter().filter_map(|x| Pubkey::from_str(x).ok()).collect()
+}
 
-        for asset in &mut asset_res.data.iter() {
-            if asset.token_name.contains(orca_farm_key) {
-                //滤掉orca farm资产
-                continue;
+struct ValuedAsset {
+    price: f64,
+    value: f64,
+    source: String,
+    confidence: f64,
+}
+
+//lp token按底层资产的价值之和计价，置信度取底层资产中最低的；任何一边没有价格时不计价
+fn asset_value(asset: &AssetResponse, prices: &HashMap<Pubkey, TokenPrice>) -> Option<ValuedAsset> {
+    let token_price = |mint: &str| Pubkey::from_str(mint).ok().and_then(|x| prices.get(&x));
+    match &asset.underlying {
+        Some(underlying) => {
+            let mut value = 0.0;
+            let mut confidence: f64 = 1.0;
+            for x in underlying {
+                let price = token_price(&x.mint)?;
+                value += x.token_amount.ui_amount * price.price;
+                confidence = confidence.min(price.confidence);
             }
-            if asset.token_name.eq("") {
-                let farm_address = pool_farm_map.get(&asset.token_address);
-
-                //处理 orca pool 回填信息
-                if farm_address.is_some() {
-                    let farm_option = tokens_adr.get(farm_address.unwrap());
-
-                    if farm_option.is_some() {
-                        let token_info = farm_option.unwrap();
-                        let mut pool_asset = asset.clone();
-                        pool_asset.token_name = token_info.description.clone();
-                        pool_asset.token_icon = token_info.icon_uri.clone();
-                        pool_asset.token_symbol = Some(token_info.name.clone());
-                        res.push(pool_asset);
-                    }
-                }
-            } else {
-                res.push(asset.clone());
+            let amount = asset.token_amount.ui_amount;
+            Some(ValuedAsset {
+                price: if amount > 0.0 { value / amount } else { 0.0 },
+                value,
+                source: format!("lp:{}", underlying.first().map(|x| x.market.as_str()).unwrap_or_default()),
+                confidence,
+            })
+        }
+        None => {
+            let price = token_price(&asset.token_address)?;
+            Some(ValuedAsset {
+                price: price.price,
+                value: asset.token_amount.ui_amount * price.price,
+                source: price.source.clone(),
+                confidence: price.confidence,
+            })
+        }
+    }
+}
+
+struct TokenHolding {
+    //SOL余额时是钱包地址
+    account: Pubkey,
+    mint: Pubkey,
+    amount: u64,
+    program_id: Pubkey,
+    lamports: u64,
+    rent_epoch: u64,
+}
+
+//base64返回账户数据，spl-token和Token-2022按同样的基础结构解析
+async fn load_token_accounts(rpc: &RpcPool, owner: &Pubkey, program_id: &Pubkey) -> ApiResult<Vec<TokenHolding>> {
+    let params = json!([
+        owner.to_string(),
+        {"programId": program_id.to_string()},
+        {"encoding": "base64", "commitment": rpc.commitment().commitment},
+    ]);
+    let response: RpcResponse<Vec<RpcKeyedAccount>> = rpc.call(|client| {
+        client.send(RpcRequest::GetTokenAccountsByOwner, params.clone())
+    }).await?;
+
+    let mut holdings = vec![];
+    for keyed in response.value {
+        let account = match keyed.account.decode::<Account>() {
+            Some(account) => account,
+            None => continue,
+        };
+        //Token-2022账户的扩展数据在基础结构之后
+        if account.data.len() < TokenAccount::LEN {
+            continue;
+        }
+        let token_account = match TokenAccount::unpack_from_slice(&account.data[..TokenAccount::LEN]) {
+            Ok(token_account) => token_account,
+            Err(_) => continue,
+        };
+        holdings.push(TokenHolding {
+            account: Pubkey::from_str(&keyed.pubkey).map_err(|_| ApiError::AccountDecode(keyed.pubkey.clone()))?,
+            mint: token_account.mint,
+            amount: token_account.amount,
+            program_id: *program_id,
+            lamports: account.lamports,
+            rent_epoch: account.rent_epoch,
+        });
+    }
+    Ok(holdings)
+}
+
+//token列表里没有的mint从链上读取精度
+async fn load_decimals(registry: &RegistryData, rpc: &RpcPool, holdings: &[TokenHolding]) -> ApiResult<HashMap<Pubkey, u8>> {
+    let mut decimals = HashMap::new();
+    let mut unknown = vec![];
+    for holding in holdings {
+        match registry.tokens.get(&holding.mint.to_string()) {
+            Some(token) => {
+                decimals.insert(holding.mint, token.decimal);
+            }
+            None if holding.mint == spl_token::native_mint::id() => {
+                decimals.insert(holding.mint, spl_token::native_mint::DECIMALS);
             }
+            None => unknown.push(holding.mint),
         }
+    }
+
+    let (_slot, accounts) = fetch_accounts(rpc, &unknown).await?;
+    for (mint, account) in unknown.iter().zip(accounts) {
+        let mint_info = account.filter(|x| x.data.len() >= Mint::LEN)
+            .and_then(|x| Mint::unpack_from_slice(&x.data[..Mint::LEN]).ok());
+        if let Some(mint_info) = mint_info {
+            decimals.insert(*mint, mint_info.decimals);
+        }
+    }
+    Ok(decimals)
+}
+
+//持有的lp token按份额折算成池子两边的token，key是token账户
+async fn load_underlying(registry: &RegistryData,
+                         cache: &AccountCache,
+                         holdings: &[TokenHolding],
+                         decimals: &HashMap<Pubkey, u8>) -> ApiResult<HashMap<Pubkey, Vec<UnderlyingAsset>>> {
+    let lp_holdings: Vec<(&TokenHolding, &PoolInfo)> = holdings.iter()
+        .filter(|x| x.amount > 0)
+        .filter_map(|x| registry.pool_by_lp(&x.mint.to_string(), None).map(|pool| (x, pool)))
+        .collect();
+    let keys: Vec<Pubkey> = lp_holdings.iter().flat_map(|(_, pool)| pool_account_keys(pool)).collect();
+    let (account_map, _slot) = cache.get_accounts(&keys).await?;
+
+    let mut res = HashMap::new();
+    for (holding, pool) in lp_holdings {
+        let reserves = pool_reserves(&account_map, pool).and_then(|x| Ok((x, pool_lp_supply(&account_map, pool)?)));
+        let ((quote_reserve, base_reserve), supply) = match reserves {
+            Ok(reserves) if reserves.1 > 0 => reserves,
+            Ok(_) => continue,
+            Err(e) => {
+                println!("load lp {} reserves fail: {}", holding.mint, e);
+                continue;
+            }
+        };
+
+        let (market, _program_id) = pool.market_type.get_name();
+        let underlying = [(pool.quote_mint_key, quote_reserve), (pool.base_mint_key, base_reserve)].iter()
+            .map(|(mint, reserve)| {
+                let amount = (*reserve as u128 * holding.amount as u128 / supply as u128) as u64;
+                let token = registry.tokens.get(&mint.to_string());
+                let decimal = token.map(|x| x.decimal).or_else(|| decimals.get(mint).cloned()).unwrap_or_default();
+                UnderlyingAsset {
+                    mint: mint.to_string(),
+                    symbol: token.map(|x| x.name.clone()),
+                    market: market.clone(),
+                    pool_account: pool.pool_key.to_string(),
+                    token_amount: token_amount(amount, decimal),
+                }
+            })
+            .collect();
+        res.insert(holding.account, underlying);
+    }
+    Ok(res)
+}
+
+fn token_amount(raw: u64, decimals: u8) -> TokenAmount {
+    TokenAmount {
+        amount: raw.to_string(),
+        decimals: decimals as u64,
+        ui_amount: ui_amount(raw, decimals),
+        ui_amount_string: ui_amount_string(raw, decimals),
+    }
+}
 
-        res
+//按精度在整数字符串里插入小数点并去掉末尾的0，精度超过Decimal支持的28位时也不会丢失
+fn ui_amount_string(raw: u64, decimals: u8) -> String {
+    let decimals = decimals as usize;
+    if decimals == 0 {
+        return raw.to_string();
+    }
+    let digits = format!("{:0>width$}", raw, width = decimals + 1);
+    let (integer, fraction) = digits.split_at(digits.len() - decimals);
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.is_empty() {
+        integer.to_string()
+    } else {
+        format!("{}.{}", integer, fraction)
     }
 }
 
@@ -222,10 +748,31 @@ pub struct EthFee {
 }
 
 impl EthFee {
-    pub fn gastracker() -> EtherscanResp<EthFee> {
+    pub async fn gastracker() -> ApiResult<EtherscanResp<EthFee>> {
         let eth_fee_url: String = String::from("https://api.etherscan.io/api?module=gastracker&action=gasoracle&apikey=8AY47QX8ZP86AI5868EUS4MUW628ZJI6W9");
-        let res = reqwest::blocking::get(eth_fee_url).unwrap();
-        res.json::<EtherscanResp<EthFee>>().unwrap()
+        let res = reqwest::get(eth_fee_url).await?;
+        Ok(res.json::<EtherscanResp<EthFee>>().await?)
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_amount_string() {
+        assert_eq!(token_amount(1_500_000, 6).ui_amount_string, "1.5");
+        assert_eq!(token_amount(42, 0).ui_amount_string, "42");
+        assert_eq!(token_amount(0, 9).ui_amount_string, "0");
+        assert_eq!(token_amount(1, 9).ui_amount_string, "0.000000001");
+    }
+
+    #[test]
+    fn token_amount_above_decimal_scale() {
+        let amount = token_amount(12_345, 30);
+        assert_eq!(amount.ui_amount_string, "0.000000000000000000000000012345");
+        assert_eq!(amount.decimals, 30);
+        assert!(amount.ui_amount > 0.0);
+    }
+}
@@ -1,36 +1,36 @@
 use std::collections::HashMap;
 use std::fs;
-use rocket_contrib::json::Json;
+use rocket::serde::json::Json;
 use crate::api;
 use crate::response;
 use api::RawTokenAddr;
 use response::TokenListResponse;
 use serde::{Serialize, Deserialize};
+use crate::error::{ApiError, ApiResult};
+use crate::registry::RegistryData;
 
-pub fn token_list(page: Option<u32>, pagesize: Option<u32>, search: Option<String>, address: Option<String>, symbol: Option<String>) -> Json<TokenListResponse> {
-    let token_main_path = "./resource/token/solana.json".to_string();
-    let raw_info = fs::read_to_string(token_main_path).expect("Error read file");
-    let mut vec: Vec<RawTokenAddr> = serde_json::from_str(&raw_info).unwrap();
+pub fn token_list(registry: &RegistryData, page: Option<u32>, pagesize: Option<u32>, search: Option<String>, address: Option<String>, symbol: Option<String>) -> ApiResult<Json<TokenListResponse>> {
+    let mut vec: Vec<RawTokenAddr> = registry.token_list("solana");
 
     //查询固定某一个address
     match address {
         Some(a) => {
             for token in vec.iter() {
                 if token.address.eq(&a) {
-                    return Json(TokenListResponse {
+                    return Ok(Json(TokenListResponse {
                         total: 1,
                         pagesize: 1,
                         page: 1,
                         data: vec![token.clone()],
-                    });
+                    }));
                 }
             }
-            return Json(TokenListResponse {
+            return Ok(Json(TokenListResponse {
                 total: 0,
                 pagesize: 1,
                 page: 1,
                 data: vec![],
-            });
+            }));
         }
         None => {}
     }
@@ -53,20 +53,20 @@ pub fn token_list(page: Option<u32>, pagesize: Option<u32>, search: Option<Strin
         Some(symbol) => {
             for token in vec.iter() {
                 if token.symbol.eq(&symbol) {
-                    return Json(TokenListResponse {
+                    return Ok(Json(TokenListResponse {
                         total: 1,
                         pagesize: 1,
                         page: 1,
                         data: vec![token.clone()],
-                    });
+                    }));
                 }
             }
-            return Json(TokenListResponse {
+            return Ok(Json(TokenListResponse {
                 total: 0,
                 pagesize: 1,
                 page: 1,
                 data: vec![],
-            });
+            }));
         }
         None => {}
     }
@@ -76,75 +76,81 @@ pub fn token_list(page: Option<u32>, pagesize: Option<u32>, search: Option<Strin
     let total = vec.len() as u32;
 
     if total == 0 {
-        return Json(TokenListResponse {
+        return Ok(Json(TokenListResponse {
             total,
             pagesize: total,
             page: 1,
             data: vec![],
-        });
+        }));
     }
 
     match page {
         Some(p) => {
+            if p == 0 {
+                return Err(ApiError::BadPagination("page starts from 1".to_string()));
+            }
             start_page = p - 1;
         }
         None => {
-            return Json(TokenListResponse {
+            return Ok(Json(TokenListResponse {
                 total,
                 pagesize: total,
                 page: 1,
                 data: vec,
-            });
+            }));
         }
     }
 
     match pagesize {
         Some(s) => {
+            if s == 0 {
+                return Err(ApiError::BadPagination("pagesize must be greater than 0".to_string()));
+            }
             size = s;
         }
         None => {}
     }
 
-    start_index = start_page * size;
-    let mut end_index = start_index + size;
+    start_index = start_page.checked_mul(size)
+        .filter(|x| *x < total)
+        .ok_or_else(|| ApiError::BadPagination(format!("page {} out of range", start_page + 1)))?;
+    let mut end_index = start_index.saturating_add(size);
 
     if end_index >= total {
         end_index = total;
     }
 
     let res = vec[start_index as usize..end_index as usize].to_vec();
-    Json(TokenListResponse {
+    Ok(Json(TokenListResponse {
         total,
         pagesize: size,
         page: start_page + 1,
         data: res,
-    })
+    }))
 }
 
-pub fn eth_tokens(page: Option<u32>, pagesize: Option<u32>, search: Option<String>, address: Option<String>, symbol: Option<String>) -> Json<TokenListResponse> {
-    let token_main_path = "./resource/token/ethereum.json".to_string();
-    let raw_info = fs::read_to_string(token_main_path).expect("Error read file");
-    let mut vec: Vec<RawTokenAddr> = serde_json::from_str(&raw_info).unwrap();
+pub fn eth_tokens(registry: &RegistryData, page: Option<u32>, pagesize: Option<u32>, search: Option<String>, address: Option<String>, symbol: Option<String>) -> ApiResult<Json<TokenListResponse>> {
+    let mut vec: Vec<RawTokenAddr> = registry.token_list("ethereum");
 
     //查询固定某一个address
     match address {
         Some(a) => {
             for token in vec.iter() {
                 if token.address.eq(&a) {
-                    return Json(TokenListResponse {
+                    return Ok(Json(TokenListResponse {
                         total: 1,
                         pagesize: 1,
                         page: 1,
                         data: vec![token.clone()],
-                    });
+                    }));
                 }
             }
-            return Json(TokenListResponse {
+            return Ok(Json(TokenListResponse {
                 total: 0,
                 pagesize: 1,
                 page: 1,
                 data: vec![],
-            });
+            }));
         }
         None => {}
     }
@@ -167,20 +173,20 @@ pub fn eth_tokens(page: Option<u32>, pagesize: Option<u32>, search: Option<Strin
         Some(symbol) => {
             for token in vec.iter() {
                 if token.symbol.eq(&symbol) {
-                    return Json(TokenListResponse {
+                    return Ok(Json(TokenListResponse {
                         total: 1,
                         pagesize: 1,
                         page: 1,
                         data: vec![token.clone()],
-                    });
+                    }));
                 }
             }
-            return Json(TokenListResponse {
+            return Ok(Json(TokenListResponse {
                 total: 0,
                 pagesize: 1,
                 page: 1,
                 data: vec![],
-            });
+            }));
         }
         None => {}
     }
@@ -191,49 +197,57 @@ pub fn eth_tokens(page: Option<u32>, pagesize: Option<u32>, search: Option<Strin
     let total = vec.len() as u32;
 
     if total == 0 {
-        return Json(TokenListResponse {
+        return Ok(Json(TokenListResponse {
             total,
             pagesize: total,
             page: 1,
             data: vec![],
-        });
+        }));
     }
 
     match page {
         Some(p) => {
+            if p == 0 {
+                return Err(ApiError::BadPagination("page starts from 1".to_string()));
+            }
             start_page = p - 1;
         }
         None => {
-            return Json(TokenListResponse {
+            return Ok(Json(TokenListResponse {
                 total,
                 pagesize: total,
                 page: 1,
                 data: vec,
-            });
+            }));
         }
     }
 
     match pagesize {
         Some(s) => {
+            if s == 0 {
+                return Err(ApiError::BadPagination("pagesize must be greater than 0".to_string()));
+            }
             size = s;
         }
         None => {}
     }
 
-    start_index = start_page * size;
-    let mut end_index = start_index + size;
+    start_index = start_page.checked_mul(size)
+        .filter(|x| *x < total)
+        .ok_or_else(|| ApiError::BadPagination(format!("page {} out of range", start_page + 1)))?;
+    let mut end_index = start_index.saturating_add(size);
 
     if end_index >= total {
         end_index = total;
     }
 
     let res = vec[start_index as usize..end_index as usize].to_vec();
-    Json(TokenListResponse {
+    Ok(Json(TokenListResponse {
         total,
         pagesize: size,
         page: start_page + 1,
         data: res,
-    })
+    }))
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -256,11 +270,19 @@ pub struct WoreholeAddress {
     pub target_token: Option<RawTokenAddr>,
 }
 
-pub fn bridge_token_by_origin(source_chain: String, to_chain: String, origin_address: String) -> WoreholeAddress {
+//链名来自请求参数，只允许字母数字，对应文件不存在说明不支持这条链
+fn read_chain_file(path: String, chain: &str) -> ApiResult<String> {
+    if chain.is_empty() || !chain.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(ApiError::InvalidRequest(format!("unsupported chain {}", chain)));
+    }
+    fs::read_to_string(path).map_err(|_| ApiError::InvalidRequest(format!("unsupported chain {}", chain)))
+}
+
+pub fn bridge_token_by_origin(source_chain: String, to_chain: String, origin_address: String) -> ApiResult<WoreholeAddress> {
     //获取目标
     let wrap_token_path = format!("./resource/token/worehole_{}.json", source_chain);
-    let raw_info = fs::read_to_string(wrap_token_path).expect("Error read file");
-    let vec: Vec<RawWorehole> = serde_json::from_str(&raw_info).unwrap();
+    let raw_info = read_chain_file(wrap_token_path, &source_chain)?;
+    let vec: Vec<RawWorehole> = serde_json::from_str(&raw_info)?;
 
     let mut origin = origin_address.clone();
     if origin_address.eq("") && source_chain.eq("ethereum") {
@@ -281,7 +303,7 @@ pub fn bridge_token_by_origin(source_chain: String, to_chain: String, origin_add
     };
 
     if wrap_address.is_none() {
-        return worehole_address;
+        return Ok(worehole_address);
     }
 
     let token_path;
@@ -290,8 +312,8 @@ pub fn bridge_token_by_origin(source_chain: String, to_chain: String, origin_add
     } else {
         token_path = format!("./resource/token/{}.json", to_chain);
     }
-    let raw_info = fs::read_to_string(token_path).expect("Error read file");
-    let vec: Vec<RawTokenAddr> = serde_json::from_str(&raw_info).unwrap();
+    let raw_info = read_chain_file(token_path, &to_chain)?;
+    let vec: Vec<RawTokenAddr> = serde_json::from_str(&raw_info)?;
     let tokens_adr: HashMap<String, RawTokenAddr> = vec
         .iter()
         .map(|x| {
@@ -304,14 +326,14 @@ pub fn bridge_token_by_origin(source_chain: String, to_chain: String, origin_add
     if wrap_address.is_some() {
         worehole_address.target_token = Some(wrap_address.unwrap().clone());
     }
-    worehole_address
+    Ok(worehole_address)
 }
 
-pub fn bridge_token_by_wrap(source_chain: String, to_chain: String, wrap_address: String) -> WoreholeAddress {
+pub fn bridge_token_by_wrap(source_chain: String, to_chain: String, wrap_address: String) -> ApiResult<WoreholeAddress> {
     //获取to_chain target token
     let wrap_token_path = format!("./resource/token/worehole_{}.json", to_chain);
-    let raw_info = fs::read_to_string(wrap_token_path).expect("Error read file");
-    let vec: Vec<RawWorehole> = serde_json::from_str(&raw_info).unwrap();
+    let raw_info = read_chain_file(wrap_token_path, &to_chain)?;
+    let vec: Vec<RawWorehole> = serde_json::from_str(&raw_info)?;
 
     let mut target_address = None;
     for worehole in vec.iter() {
@@ -330,7 +352,7 @@ pub fn bridge_token_by_wrap(source_chain: String, to_chain: String, wrap_address
     };
 
     if target_address.is_none() {
-        return worehole_address;
+        return Ok(worehole_address);
     }
 
     let token_path;
@@ -339,8 +361,8 @@ pub fn bridge_token_by_wrap(source_chain: String, to_chain: String, wrap_address
     } else {
         token_path = format!("./resource/token/{}.json", to_chain);
     }
-    let raw_info = fs::read_to_string(token_path).expect("Error read file");
-    let vec: Vec<RawTokenAddr> = serde_json::from_str(&raw_info).unwrap();
+    let raw_info = read_chain_file(token_path, &to_chain)?;
+    let vec: Vec<RawTokenAddr> = serde_json::from_str(&raw_info)?;
     let tokens_adr: HashMap<String, RawTokenAddr> = vec
         .iter()
         .map(|x| {
@@ -353,5 +375,5 @@ pub fn bridge_token_by_wrap(source_chain: String, to_chain: String, wrap_address
     if target_address.is_some() {
         worehole_address.target_token = Some(target_address.unwrap().clone());
     }
-    worehole_address
+    Ok(worehole_address)
 }
\ No newline at end of file
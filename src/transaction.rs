@@ -0,0 +1,304 @@
+use crate::node_client::RpcPool;
+use crate::response;
+use crate::error::{ApiError, ApiResult};
+use serde::{Serialize, Deserialize};
+use anyhow::{Result, anyhow};
+use rust_decimal::prelude::FromStr;
+use solana_program::pubkey::Pubkey;
+use solana_program::instruction::Instruction;
+use solana_program::system_instruction;
+use solana_sdk::transaction::Transaction;
+use solana_sdk::packet::PACKET_DATA_SIZE;
+use spl_associated_token_account::{get_associated_token_address, create_associated_token_account};
+use market::dex::{self, RouteAmount, SwapAccounts};
+use response::{OptRank, OptMarket, OptRoute, SwapMode, apply_slippage};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BuildSwapRequest {
+    pub wallet: String,
+    //opt_swap返回的其中一个方案
+    pub rank: OptRank,
+}
+
+impl BuildSwapRequest {
+    //serum订单簿路径没有swap指令，这类方案不能组装成交易
+    pub fn validate(&self) -> ApiResult<()> {
+        let routes = self.rank.opt.iter().flat_map(|x| x.routes.iter());
+        for route in routes {
+            if !is_buildable(&route.market) {
+                return Err(ApiError::InvalidRequest(format!("market {} can not be built into a transaction", route.market)));
+            }
+        }
+        Ok(())
+    }
+
+    //把报价方案组装成未签名交易，base64编码，由钱包签名后发送
+    pub async fn build(&self, rpc: &RpcPool) -> Result<String> {
+        let wallet = Pubkey::from_str(&self.wallet)?;
+        if self.rank.opt.is_empty() {
+            return Err(anyhow!("empty route"));
+        }
+
+        //路径上所有token的ATA，不存在的需要先创建
+        let mut mints = vec![];
+        for market in &self.rank.opt {
+            for route in &market.routes {
+                for mint in [&route.source_mint, &route.destination_mint] {
+                    let mint = Pubkey::from_str(mint)?;
+                    if !mints.contains(&mint) {
+                        mints.push(mint);
+                    }
+                }
+            }
+        }
+        let token_accounts: Vec<Pubkey> = mints.iter().map(|x| get_associated_token_address(&wallet, x)).collect();
+        let exists = rpc.call(|client| client.get_multiple_accounts_with_commitment(&token_accounts, rpc.commitment())).await?.value;
+
+        let native_mint = spl_token::native_mint::id();
+        let source_mint = Pubkey::from_str(&self.rank.opt[0].routes[0].source_mint)?;
+        let destination_mint = {
+            let routes = &self.rank.opt[0].routes;
+            Pubkey::from_str(&routes[routes.len() - 1].destination_mint)?
+        };
+
+        //先组装各路径的swap指令，同时得到需要转入wSOL账户的输入总量
+        let mut swap_instructions = vec![];
+        let mut total_amount_in = 0;
+        for market in &self.rank.opt {
+            let (market_instructions, amount_in) = self.market_instructions(&wallet, market)?;
+            swap_instructions.extend(market_instructions);
+            total_amount_in += amount_in;
+        }
+
+        let mut instructions = vec![];
+        for (index, mint) in mints.iter().enumerate() {
+            if exists[index].is_none() {
+                instructions.push(create_associated_token_account(&wallet, &wallet, mint));
+            }
+        }
+
+        //卖出SOL时先把SOL转进wSOL账户
+        let wsol_account = get_associated_token_address(&wallet, &native_mint);
+        if source_mint.eq(&native_mint) {
+            instructions.push(system_instruction::transfer(&wallet, &wsol_account, total_amount_in));
+            instructions.push(spl_token::instruction::sync_native(&spl_token::id(), &wsol_account)?);
+        }
+        instructions.extend(swap_instructions);
+
+        //涉及SOL时最后关闭wSOL账户，剩余的都换回SOL
+        if source_mint.eq(&native_mint) || destination_mint.eq(&native_mint) {
+            instructions.push(spl_token::instruction::close_account(&spl_token::id(), &wsol_account, &wallet, &wallet, &[])?);
+        }
+
+        let blockhash = rpc.call(|client| client.get_latest_blockhash()).await?;
+        let mut transaction = Transaction::new_with_payer(&instructions, Some(&wallet));
+        transaction.message.recent_blockhash = blockhash;
+
+        let data = bincode::serialize(&transaction)?;
+        if data.len() > PACKET_DATA_SIZE {
+            return Err(anyhow!("transaction too large: {} bytes", data.len()));
+        }
+        Ok(base64::encode(data))
+    }
+
+    //一条路径的swap指令和最多付出的输入。
+    //ExactOut的单跳路径在支持指定输出的市场上直接按指定输出swap，最多付出max_amount_in；
+    //ExactOut的其它路径第一跳卖出max_amount_in，中间跳至少得到报价的产出，最后一跳至少得到指定的输出；
+    //ExactIn按报价的输入卖出，滑点放在每一跳的产出上
+    fn market_instructions(&self, wallet: &Pubkey, market: &OptMarket) -> Result<(Vec<Instruction>, u64)> {
+        let routes = &market.routes;
+        let max_amount_in = self.rank.max_amount_in_raw.unwrap_or(market.amount_in_raw);
+        if self.rank.swap_mode == SwapMode::ExactOut && routes.len() == 1 && supports_exact_out(&routes[0].market) {
+            let amount = RouteAmount::ExactOut { max_amount_in, amount_out: self.rank.amount_out_raw };
+            return Ok((vec![build_route_instruction(wallet, &routes[0], amount)?], max_amount_in));
+        }
+
+        let (total_amount_in, minimum_amount_out) = match self.rank.swap_mode {
+            SwapMode::ExactIn => (market.amount_in_raw, apply_slippage(market.amount_out_raw, self.rank.slippage)),
+            SwapMode::ExactOut => (max_amount_in, self.rank.amount_out_raw),
+        };
+        let mut instructions = vec![];
+        let mut amount_in = total_amount_in;
+        for (index, route) in routes.iter().enumerate() {
+            //完整的滑点检查放在最后一跳。ExactIn的中间跳按滑点保护这一跳的产出，
+            //ExactOut的滑点余量已经放在第一跳多付的输入上，中间跳至少得到报价的产出。
+            //下一跳只卖出这一跳保证能得到的数量，实际多出的部分留在中间token账户
+            let minimum_amount_out = if index == routes.len() - 1 {
+                minimum_amount_out
+            } else {
+                match self.rank.swap_mode {
+                    SwapMode::ExactIn => apply_slippage(route.destination_amount_raw, self.rank.slippage),
+                    SwapMode::ExactOut => route.destination_amount_raw,
+                }
+            };
+            instructions.push(build_route_instruction(wallet, route, RouteAmount::ExactIn { amount_in, minimum_amount_out })?);
+            amount_in = minimum_amount_out;
+        }
+        Ok((instructions, total_amount_in))
+    }
+}
+
+//能组装成交易的市场，raydium池子关联的serum订单簿路径("Serum")没有对应的swap指令
+pub fn is_buildable(market: &str) -> bool {
+    dex::find(market).map_or(false, |x| x.swap_instruction.is_some())
+}
+
+//链上支持按指定输出swap的市场
+fn supports_exact_out(market: &str) -> bool {
+    dex::find(market).map_or(false, |x| x.exact_out)
+}
+
+//指令的账户和参数由各DEX模块组装
+fn build_route_instruction(wallet: &Pubkey, route: &OptRoute, amount: RouteAmount) -> Result<Instruction> {
+    let swap_instruction = dex::find(&route.market)
+        .and_then(|x| x.swap_instruction)
+        .ok_or_else(|| anyhow!("market {} not supported", route.market))?;
+    let accounts = SwapAccounts {
+        program_id: Pubkey::from_str(&route.program_id)?,
+        pool_key: Pubkey::from_str(&route.route_key)?,
+        wallet: *wallet,
+        source: get_associated_token_address(wallet, &Pubkey::from_str(&route.source_mint)?),
+        destination: get_associated_token_address(wallet, &Pubkey::from_str(&route.destination_mint)?),
+        data: &route.data,
+    };
+    swap_instruction(&accounts, amount)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use market::raydium::data::RAYDIUM_MARKET;
+    use market::raydium::instruction::{AmmInstruction, SwapInstructionBaseIn, SwapInstructionBaseOut};
+    use response::{QuoteSummary, RouteFee};
+
+    const RAYDIUM_PROGRAM_ID: &str = "675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8";
+
+    //raydium一跳，swap指令需要的账户都用随机地址
+    fn raydium_route(source_mint: &Pubkey, destination_mint: &Pubkey, source_amount_raw: u64, destination_amount_raw: u64) -> OptRoute {
+        let keys = ["poolMint", "openOrders", "targetOrders", "baseVault", "quoteVault", "marketProgramId", "marketId",
+            "marketBids", "marketAsks", "marketEventQueue", "marketBaseVault", "marketQuoteVault", "marketVaultSigner"];
+        let data: HashMap<String, String> = keys.iter().map(|x| (x.to_string(), Pubkey::new_unique().to_string())).collect();
+        OptRoute {
+            route_key: Pubkey::new_unique().to_string(),
+            market: RAYDIUM_MARKET.to_string(),
+            program_id: RAYDIUM_PROGRAM_ID.to_string(),
+            source_amount: 0.0,
+            source_amount_raw,
+            source_name: String::new(),
+            source_mint: source_mint.to_string(),
+            source_decimals: 6,
+            destination_amount: 0.0,
+            destination_amount_raw,
+            destination_name: String::new(),
+            destination_mint: destination_mint.to_string(),
+            destination_decimals: 6,
+            source_value: 0,
+            destination_value: 0,
+            fee_factor: 1.0,
+            fee: RouteFee::default(),
+            amp: None,
+            data,
+        }
+    }
+
+    fn request(swap_mode: SwapMode, market: OptMarket) -> BuildSwapRequest {
+        BuildSwapRequest {
+            wallet: Pubkey::new_unique().to_string(),
+            rank: OptRank {
+                swap_mode,
+                amount_in: 0.0,
+                amount_in_raw: market.amount_in_raw,
+                amount_out: 0.0,
+                amount_out_raw: market.amount_out_raw,
+                max_amount_in: None,
+                max_amount_in_raw: None,
+                quote_mint: market.routes[0].source_mint.clone(),
+                base_mint: market.routes[market.routes.len() - 1].destination_mint.clone(),
+                slippage: 1.0,
+                opt: vec![market],
+                excluded: vec![],
+                slot: 0,
+                split: vec![],
+                split_error: None,
+                summary: QuoteSummary::default(),
+            },
+        }
+    }
+
+    //USDC -> SOL -> RAY两跳
+    fn two_hops() -> OptMarket {
+        let mints = [Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique()];
+        OptMarket {
+            market: RAYDIUM_MARKET.to_string(),
+            program_id: RAYDIUM_PROGRAM_ID.to_string(),
+            amount_in: 0.0,
+            amount_in_raw: 1_000_000,
+            amount_out: 0.0,
+            amount_out_raw: 3_000_000,
+            percentage: 1.0,
+            mid_rate: None,
+            buildable: true,
+            routes: vec![
+                raydium_route(&mints[0], &mints[1], 1_000_000, 2_000_000),
+                raydium_route(&mints[1], &mints[2], 2_000_000, 3_000_000),
+            ],
+        }
+    }
+
+    fn swap_base_in(instruction: &Instruction) -> SwapInstructionBaseIn {
+        match AmmInstruction::unpack(&instruction.data).unwrap() {
+            AmmInstruction::SwapBaseIn(x) => x,
+            x => panic!("unexpected instruction {:?}", x),
+        }
+    }
+
+    #[test]
+    fn multi_hop_sells_intermediate_minimum() {
+        let req = request(SwapMode::ExactIn, two_hops());
+        let wallet = Pubkey::from_str(&req.wallet).unwrap();
+        let (instructions, amount_in) = req.market_instructions(&wallet, &req.rank.opt[0]).unwrap();
+        assert_eq!(amount_in, 1_000_000);
+        assert_eq!(instructions.len(), 2);
+
+        let first = swap_base_in(&instructions[0]);
+        let second = swap_base_in(&instructions[1]);
+        assert_eq!(first.amount_in, 1_000_000);
+        assert_eq!(first.minimum_amount_out, apply_slippage(2_000_000, 1.0));
+        //第二跳卖出的是第一跳保证能得到的数量，不是报价的产出
+        assert_eq!(second.amount_in, first.minimum_amount_out);
+        assert_eq!(second.minimum_amount_out, apply_slippage(3_000_000, 1.0));
+    }
+
+    #[test]
+    fn multi_hop_exact_out_sells_max_amount_in() {
+        let mut req = request(SwapMode::ExactOut, two_hops());
+        req.rank.max_amount_in_raw = Some(1_010_000);
+        let wallet = Pubkey::from_str(&req.wallet).unwrap();
+        let (instructions, amount_in) = req.market_instructions(&wallet, &req.rank.opt[0]).unwrap();
+        assert_eq!(amount_in, 1_010_000);
+
+        //raydium支持指定输出，但两跳时仍按指定输入组装
+        let first = swap_base_in(&instructions[0]);
+        let second = swap_base_in(&instructions[1]);
+        assert_eq!(first.amount_in, 1_010_000);
+        assert_eq!(first.minimum_amount_out, 2_000_000);
+        assert_eq!(second.amount_in, 2_000_000);
+        //最后一跳至少得到指定的输出，不再扣滑点
+        assert_eq!(second.minimum_amount_out, 3_000_000);
+    }
+
+    #[test]
+    fn single_hop_exact_out_uses_swap_base_out() {
+        let mut market = two_hops();
+        market.routes.truncate(1);
+        market.amount_out_raw = 2_000_000;
+        let mut req = request(SwapMode::ExactOut, market);
+        req.rank.max_amount_in_raw = Some(1_010_000);
+        let wallet = Pubkey::from_str(&req.wallet).unwrap();
+        let (instructions, amount_in) = req.market_instructions(&wallet, &req.rank.opt[0]).unwrap();
+        assert_eq!(amount_in, 1_010_000);
+        assert_eq!(AmmInstruction::unpack(&instructions[0].data).unwrap(),
+                   AmmInstruction::SwapBaseOut(SwapInstructionBaseOut { max_amount_in: 1_010_000, amount_out: 2_000_000 }));
+    }
+}